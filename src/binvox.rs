@@ -0,0 +1,152 @@
+use std::io::Read;
+
+use parry3d_f64::math::{Point, Vector};
+
+use crate::import::{JSONImporter, DEFAULT_LEAF_SIZE, DEFAULT_VERTEX_OFFSET};
+use crate::squarion::*;
+use crate::svo::*;
+
+/// A .binvox file, as written by binvox, cuda_voxelizer and others.
+pub struct BinvoxFile {
+    pub dims: Vector<i32>,
+    /// Where the model was before voxelizing, and how large it was. Only kept for reference,
+    /// the voxels are placed from the core's minimum corner.
+    pub translate: Vector<f64>,
+    pub scale: f64,
+    pub voxels: Vec<Point<i32>>,
+}
+
+#[derive(Debug)]
+pub struct ModelTooLarge {
+    pub dims: Vector<i32>,
+    pub core_size: i32,
+}
+
+impl std::fmt::Display for ModelTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "model is {}x{}x{} voxels, but the core only fits {} on each axis",
+            self.dims.x, self.dims.y, self.dims.z, self.core_size
+        )
+    }
+}
+
+impl std::error::Error for ModelTooLarge {}
+
+impl BinvoxFile {
+    /// Builds the SVO with every voxel set to `material`. The model doesn't need power of two
+    /// dimensions, the rest of the core is left empty.
+    pub fn create_svo(
+        &self,
+        material: u64,
+        height: usize,
+    ) -> Result<Svo<Option<VoxelCellData>>, ModelTooLarge> {
+        let core_size = 128 << (height - 5);
+        if self.dims.max() > core_size {
+            return Err(ModelTooLarge {
+                dims: self.dims,
+                core_size,
+            });
+        }
+
+        let mut importer = JSONImporter;
+        let (material_mapper, material_id_to_index) = importer
+            .create_material_mapper(&[material], true)
+            .expect("a single material always fits");
+        let material_index = material_id_to_index[&material];
+        let mut svo = importer.create_empty_lods(
+            Point::origin(),
+            height,
+            DEFAULT_LEAF_SIZE,
+            &material_mapper,
+        );
+        for pos in &self.voxels {
+            // Materials live on the +[1, 1, 1] vertex, the same place the JSON importer puts
+            // them.
+            importer.set_material_at_all_lods(
+                &mut svo,
+                pos + Vector::repeat(1),
+                material_index,
+                DEFAULT_VERTEX_OFFSET,
+                height,
+            );
+        }
+        Ok(importer.finish_svo(svo))
+    }
+}
+
+fn read_line(reader: &mut impl Read) -> Result<String, DeserializeError> {
+    let mut line = Vec::new();
+    loop {
+        let byte = u8::deserialize(reader)?;
+        if byte == b'\n' {
+            break;
+        }
+        line.push(byte);
+    }
+    Ok(std::str::from_utf8(&line)?.trim().to_string())
+}
+
+fn parse_values<T: std::str::FromStr, const N: usize>(
+    tokens: std::str::SplitWhitespace,
+) -> Result<[T; N], DeserializeError> {
+    let values = tokens
+        .map(|t| t.parse::<T>().map_err(|_| DeserializeError::BadData))
+        .collect::<Result<Vec<_>, _>>()?;
+    values.try_into().map_err(|_| DeserializeError::BadData)
+}
+
+impl Deserialize for BinvoxFile {
+    fn deserialize(reader: &mut impl Read) -> Result<Self, DeserializeError> {
+        if !read_line(reader)?.starts_with("#binvox") {
+            return Err(DeserializeError::BadData);
+        }
+
+        let mut dims = None;
+        let mut translate = Vector::zeros();
+        let mut scale = 1.0;
+        loop {
+            let line = read_line(reader)?;
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("dim") => dims = Some(parse_values::<i32, 3>(tokens)?),
+                Some("translate") => translate = Vector::from(parse_values::<f64, 3>(tokens)?),
+                Some("scale") => scale = parse_values::<f64, 1>(tokens)?[0],
+                Some("data") => break,
+                _ => (),
+            }
+        }
+        let [depth, height, width] = dims.ok_or(DeserializeError::BadData)?;
+        if depth <= 0 || height <= 0 || width <= 0 {
+            return Err(DeserializeError::BadData);
+        }
+
+        // The data is run length encoded as (value, count) byte pairs. x runs slowest, then z,
+        // then y, so the header's dimensions are in x, z, y order.
+        let total = depth as usize * height as usize * width as usize;
+        let mut voxels = Vec::new();
+        let mut index = 0;
+        while index < total {
+            let value = u8::deserialize(reader)?;
+            let count = u8::deserialize(reader)? as usize;
+            if value != 0 {
+                for i in index..(index + count).min(total) {
+                    let i = i as i32;
+                    let x = i / (height * width);
+                    let z = i / width % height;
+                    let y = i % width;
+                    voxels.push(Point::new(x, y, z));
+                }
+            }
+            index += count;
+        }
+
+        Ok(BinvoxFile {
+            dims: Vector::new(depth, width, height),
+            translate,
+            scale,
+            voxels,
+        })
+    }
+}
@@ -1,12 +1,47 @@
+use std::collections::{HashMap, HashSet};
+
 use base64::prelude::*;
 use chrono::prelude::*;
 use clap::ValueEnum;
 use parry3d_f64::math::{Point, Vector};
-use serde_json::json;
+use rayon::prelude::*;
+use serde_json::{json, Value};
 
 use crate::squarion::*;
 use crate::svo::*;
 
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum BlueprintError {
+    BadJson(String),
+    BadBase64(base64::DecodeError),
+    BadVoxelData(DeserializeError),
+}
+
+impl std::fmt::Display for BlueprintError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlueprintError::BadJson(message) => write!(f, "{}", message),
+            BlueprintError::BadBase64(e) => write!(f, "{}", e),
+            BlueprintError::BadVoxelData(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BlueprintError {}
+
+impl From<base64::DecodeError> for BlueprintError {
+    fn from(value: base64::DecodeError) -> Self {
+        BlueprintError::BadBase64(value)
+    }
+}
+
+impl From<DeserializeError> for BlueprintError {
+    fn from(value: DeserializeError) -> Self {
+        BlueprintError::BadVoxelData(value)
+    }
+}
+
 struct VoxelData {
     pub height: usize,
     pub coords: Point<i32>,
@@ -45,6 +80,10 @@ impl VoxelData {
     }
 }
 
+// Encodes every cell along with its metadata. The tree doesn't store any metadata, it's rebuilt
+// bottom-up here each time a blueprint is written: leaves from their own voxels, and every other
+// cell by combining its eight children. So trees from any importer, pruned or not, always get
+// metadata that matches their voxels.
 fn make_voxel_data(
     data: &Svo<Option<VoxelCellData>>,
 ) -> (Vec<VoxelData>, Option<RangeZYX>) {
@@ -81,7 +120,199 @@ fn make_voxel_data(
     (result, meta.heavy_current.bounding_box)
 }
 
-#[derive(Clone, Copy, PartialEq, ValueEnum)]
+// The game writes numbers as `{ "$numberLong": "1" }`, while we write them as plain numbers.
+fn parse_number(value: &Value) -> Option<i64> {
+    match value {
+        Value::Object(o) => o.get("$numberLong").and_then(parse_number),
+        Value::String(s) => s.parse().ok(),
+        v => v.as_i64(),
+    }
+}
+
+type CellKey = (usize, Point<i32>);
+
+fn cell_key(range: &RangeZYX) -> CellKey {
+    let extent = range.size.x;
+    (
+        (extent as u32).trailing_zeros() as usize,
+        range.origin / extent,
+    )
+}
+
+fn build_node(
+    range: &RangeZYX,
+    cells: &mut HashMap<CellKey, VoxelCellData>,
+    present: &HashSet<CellKey>,
+) -> SvoNode<Option<VoxelCellData>> {
+    let key = cell_key(range);
+    if !present.contains(&key) {
+        return SvoNode::Leaf(None);
+    }
+    let cell = cells.remove(&key);
+    let octants = range.split_at_center();
+    if range.size.x == 1 || !octants.iter().any(|o| present.contains(&cell_key(o))) {
+        SvoNode::Leaf(cell)
+    } else {
+        SvoNode::Internal(
+            cell,
+            Box::new(octants.map(|o| build_node(&o, cells, present))),
+        )
+    }
+}
+
+/// Rebuilds the voxel SVO from the `VoxelData` of a construct json. This is the inverse of
+/// what `Blueprint::to_construct_json` writes.
+pub fn decode_voxel_data(json: &Value) -> Result<Svo<Option<VoxelCellData>>, BlueprintError> {
+    let bad_json = |what: &str| BlueprintError::BadJson(what.to_string());
+    let entries = json["VoxelData"]
+        .as_array()
+        .ok_or_else(|| bad_json("missing VoxelData"))?;
+
+    // Every chunk decodes on its own, so they're decoded in parallel and then put back in order.
+    let decoded = entries
+        .par_iter()
+        .map(|entry| -> Result<_, BlueprintError> {
+            let height = parse_number(&entry["h"]).ok_or_else(|| bad_json("bad cell height"))? - 3;
+            let mut coords = Point::origin();
+            for (i, axis) in ["x", "y", "z"].iter().enumerate() {
+                coords[i] =
+                    parse_number(&entry[*axis]).ok_or_else(|| bad_json("bad cell coords"))? as i32;
+            }
+            let data = entry["records"]["voxel"]["data"]["$binary"]
+                .as_str()
+                .ok_or_else(|| bad_json("missing voxel data"))?;
+            let voxels = VoxelCellData::decompress(&BASE64_STANDARD.decode(data)?)?;
+            // The metadata is recalculated when writing, but make sure it isn't corrupt.
+            if let Some(meta) = entry["records"]["meta"]["data"]["$binary"].as_str() {
+                AggregateMetadata::decompress(&BASE64_STANDARD.decode(meta)?)?;
+            }
+            Ok((height, coords, voxels))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut cells = HashMap::new();
+    for (height, coords, voxels) in decoded {
+        if height < 0 || cells.insert((height as usize, coords), voxels).is_some() {
+            return Err(bad_json("bad or duplicate cell"));
+        }
+    }
+
+    let &(height, coords) = cells
+        .keys()
+        .max_by_key(|(h, _)| *h)
+        .ok_or_else(|| bad_json("no voxel cells"))?;
+
+    // Mark every cell and its ancestors, so we only descend into the occupied parts of the tree.
+    let mut present = HashSet::new();
+    for &(mut h, mut c) in cells.keys() {
+        while h <= height && present.insert((h, c)) {
+            h += 1;
+            c = c.map(|v| v.div_euclid(2));
+        }
+    }
+    if present.iter().filter(|(h, _)| *h == height).count() != 1 {
+        return Err(bad_json("voxel cells do not share a single root"));
+    }
+
+    let extent = 1 << height;
+    let range = RangeZYX::with_extent(coords * extent, extent);
+    let root = build_node(&range, &mut cells, &present);
+    Ok(Svo { root, range })
+}
+
+/// Something wrong with one cell of a construct json, found by `verify_voxel_data`.
+pub struct CellProblem {
+    pub height: i64,
+    pub coords: Point<i32>,
+    pub problem: String,
+}
+
+// Checks that a chunk record decodes, matches its stored hash, and comes out the same after being
+// re-encoded. Returns the chunk and the hash of its bytes.
+fn verify_chunk<T>(record: &Value) -> Result<(T, i64), String>
+where
+    T: Serialize + Deserialize,
+{
+    let data = record["data"]["$binary"].as_str().ok_or("missing data")?;
+    let bytes = BASE64_STANDARD.decode(data).map_err(|e| e.to_string())?;
+    // `decompress` expects at least the magic and size.
+    if bytes.len() < 12 {
+        return Err("data is truncated".to_string());
+    }
+    let chunk = T::decompress(&bytes).map_err(|e| format!("failed to decode: {}", e))?;
+    let bytes_hash = hash(&bytes);
+    if let Some(stored_hash) = parse_number(&record["hash"]) {
+        if stored_hash != bytes_hash {
+            return Err(format!(
+                "stored hash {} doesn't match the data's {}",
+                stored_hash, bytes_hash
+            ));
+        }
+    }
+
+    let serialized = |chunk: &T| {
+        let mut bytes = Vec::new();
+        chunk.serialize(&mut bytes).map(|_| bytes)
+    };
+    let reencoded = chunk
+        .compress()
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| T::decompress(&bytes).map_err(|e| e.to_string()))
+        .map_err(|e| format!("failed to re-encode: {}", e))?;
+    if serialized(&chunk).ok() != serialized(&reencoded).ok() {
+        return Err("changes when re-encoded".to_string());
+    }
+    Ok((chunk, bytes_hash))
+}
+
+/// Checks every cell of a construct json on its own: both chunks have to decode, match their
+/// stored hashes and survive re-encoding, and the metadata has to name the voxel chunk's hash.
+/// Returns how many cells were checked, and the problems found.
+pub fn verify_voxel_data(json: &Value) -> Result<(usize, Vec<CellProblem>), BlueprintError> {
+    let entries = json["VoxelData"]
+        .as_array()
+        .ok_or_else(|| BlueprintError::BadJson("missing VoxelData".to_string()))?;
+
+    let mut problems = Vec::new();
+    for entry in entries {
+        let height = parse_number(&entry["h"]).map(|h| h - 3);
+        let coords = ["x", "y", "z"].map(|axis| parse_number(&entry[axis]));
+        let mut report = |problem: String| {
+            problems.push(CellProblem {
+                height: height.unwrap_or_default(),
+                coords: Point::from(coords.map(|c| c.unwrap_or_default() as i32)),
+                problem,
+            })
+        };
+        if height.is_none() || coords.contains(&None) {
+            report("bad cell height or coords".to_string());
+        }
+
+        let records = &entry["records"];
+        let voxel_hash = match verify_chunk::<VoxelCellData>(&records["voxel"]) {
+            Ok((_, voxel_hash)) => Some(voxel_hash),
+            Err(problem) => {
+                report(format!("voxel chunk: {}", problem));
+                None
+            }
+        };
+        match verify_chunk::<AggregateMetadata>(&records["meta"]) {
+            Ok((meta, _)) => {
+                let meta_voxel_hash = meta.light_current.hash_voxel();
+                if voxel_hash.is_some() && meta_voxel_hash != voxel_hash {
+                    report(format!(
+                        "meta chunk names voxel hash {:?}, but the voxel chunk's is {:?}",
+                        meta_voxel_hash, voxel_hash
+                    ));
+                }
+            }
+            Err(problem) => report(format!("meta chunk: {}", problem)),
+        }
+    }
+    Ok((entries.len(), problems))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
 pub enum CoreType {
     Dynamic,
     Static,
@@ -89,7 +320,8 @@ pub enum CoreType {
 }
 
 impl CoreType {
-    fn kind(&self) -> u32 {
+    /// The number the game uses for this type of core.
+    pub fn kind(&self) -> u32 {
         match self {
             CoreType::Dynamic => 4,
             CoreType::Static => 3,
@@ -97,8 +329,21 @@ impl CoreType {
         }
     }
 
-    fn element_id(&self, size: CoreSize) -> u64 {
-        match self {
+    /// Finds the type of core the game numbers `kind`.
+    pub fn from_kind(kind: u32) -> Option<CoreType> {
+        CoreType::value_variants()
+            .iter()
+            .find(|core_type| core_type.kind() == kind)
+            .copied()
+    }
+
+    /// Whether this type of core comes in the given size.
+    pub fn has_size(&self, size: CoreSize) -> bool {
+        self.element_id(size).is_some()
+    }
+
+    fn element_id(&self, size: CoreSize) -> Option<u64> {
+        let id = match self {
             CoreType::Dynamic => match size {
                 CoreSize::XS => 183890713,
                 CoreSize::S => 183890525,
@@ -126,9 +371,10 @@ impl CoreType {
                 CoreSize::S => 3624940909,
                 CoreSize::M => 5904195,
                 CoreSize::L => 5904544,
-                _ => panic!("Space cores do not come in {:?}.", size),
+                _ => return None,
             },
-        }
+        };
+        Some(id)
     }
 }
 
@@ -164,6 +410,19 @@ impl CoreSize {
         // 32, 64, 128, etc
         (1 << (self.height() - 5)) * 32
     }
+
+    /// Finds the core size the game numbers `size`, see `CoreSize::size`.
+    pub fn from_size(size: usize) -> Option<CoreSize> {
+        CoreSize::value_variants()
+            .iter()
+            .find(|core_size| core_size.size() == size)
+            .copied()
+    }
+
+    /// The width of the core in voxels, on every axis.
+    pub fn voxels(&self) -> usize {
+        128 * (1 << (self.height() - 5))
+    }
 }
 
 pub struct CoreInfo {
@@ -175,7 +434,9 @@ pub struct CoreInfo {
 
 impl CoreInfo {
     pub fn from(core_size: CoreSize, core_type: CoreType) -> CoreInfo {
-        let element_id = core_type.element_id(core_size);
+        let element_id = core_type
+            .element_id(core_size)
+            .unwrap_or_else(|| panic!("{:?} cores do not come in {:?}.", core_type, core_size));
         let size = core_size.size();
         let kind = core_type.kind();
         let is_static = core_type != CoreType::Dynamic;
@@ -186,6 +447,18 @@ impl CoreInfo {
             is_static,
         }
     }
+
+    /// Finds the core with the given element type.
+    pub fn from_element_type(element_type: u64) -> Option<CoreInfo> {
+        for core_type in CoreType::value_variants() {
+            for core_size in CoreSize::value_variants() {
+                if core_type.element_id(*core_size) == Some(element_type) {
+                    return Some(CoreInfo::from(*core_size, *core_type));
+                }
+            }
+        }
+        None
+    }
 }
 
 pub struct Blueprint {
@@ -210,6 +483,51 @@ impl Blueprint {
         }
     }
 
+    /// Reads a construct json, such as one written by `to_construct_json` or downloaded from
+    /// the game.
+    pub fn from_construct_json(json: &Value) -> Result<Blueprint, BlueprintError> {
+        let name = json["Model"]["Name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let info = json["Elements"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|e| parse_number(&e["elementType"]))
+            .find_map(|element_type| CoreInfo::from_element_type(element_type as u64))
+            .ok_or_else(|| BlueprintError::BadJson("no core element".into()))?;
+        let voxel_data = decode_voxel_data(json)?;
+        // The fill material isn't stored anywhere, so use the most common one.
+        let fill_material = voxel_data
+            .voxel_counts()
+            .materials
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(material, _)| material.id)
+            .unwrap_or_default();
+        Ok(Blueprint::new(name, info, fill_material, voxel_data))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn voxel_data(&self) -> &Svo<Option<VoxelCellData>> {
+        &self.voxel_data
+    }
+
+    /// Replaces the voxel data with the result of `func`, keeping everything else.
+    pub fn map_voxel_data<F>(self, func: F) -> Blueprint
+    where
+        F: FnOnce(Svo<Option<VoxelCellData>>) -> Svo<Option<VoxelCellData>>,
+    {
+        Blueprint {
+            voxel_data: func(self.voxel_data),
+            ..self
+        }
+    }
+
     pub fn to_construct_json(&self) -> serde_json::Value {
         let (voxel_data, bb) = make_voxel_data(&self.voxel_data);
         if bb.is_none() {
@@ -279,3 +597,56 @@ impl Blueprint {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{JSONImporter, DEFAULT_LEAF_SIZE};
+
+    #[test]
+    fn parent_metadata_combines_its_children() {
+        // Three voxels in different cells of the first octant of an XS core.
+        let svo = JSONImporter
+            .process_json_and_create_svo(
+                r#"{"materials": {"1971262921": [[4, 4, 4], [40, 4, 4], [4, 40, 40]]}}"#.as_bytes(),
+                CoreSize::XS.height(),
+                DEFAULT_LEAF_SIZE,
+                true,
+                &HashMap::new(),
+                false,
+            )
+            .unwrap();
+        let SvoNode::Internal(_, octants) = &svo.root else {
+            panic!("the root should be split");
+        };
+        let SvoNode::Internal(Some(parent), leaves) = &octants[0] else {
+            panic!("the first octant should be split into cells");
+        };
+
+        let leaf_metadata = Vec::from_iter(leaves.iter().map(|leaf| match leaf {
+            SvoNode::Leaf(Some(cell_data)) => {
+                cell_data.calculate_metadata(hash(&cell_data.compress().unwrap()))
+            }
+            SvoNode::Leaf(None) => AggregateMetadata::default(),
+            SvoNode::Internal(..) => panic!("cells should be leaves"),
+        }));
+        let occupied = leaf_metadata
+            .iter()
+            .filter(|m| m.heavy_current.bounding_box.is_some())
+            .count();
+        assert_eq!(occupied, 3);
+        let expected =
+            AggregateMetadata::combine(hash(&parent.compress().unwrap()), &leaf_metadata);
+
+        // The first octant is two cells across at the origin.
+        let (cells, _) = make_voxel_data(&svo);
+        let written = cells
+            .iter()
+            .find(|cell| cell.height == 1 && cell.coords == Point::origin())
+            .expect("the first octant should be written");
+        assert_eq!(
+            written.meta_data,
+            BASE64_STANDARD.encode(expected.compress().unwrap())
+        );
+    }
+}
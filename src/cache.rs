@@ -0,0 +1,142 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use parry3d_f64::bounding_volume::Aabb;
+use parry3d_f64::math::Isometry;
+use parry3d_f64::shape::TriMesh;
+
+use crate::squarion::*;
+use crate::svo::Svo;
+use crate::voxelization::MeshPart;
+use crate::GenerateOptions;
+
+const MAGIC: u32 = 0x5d0c4c3e;
+const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum CacheError {
+    Io(std::io::Error),
+    Serialize(SerializeError),
+    Deserialize(DeserializeError),
+}
+
+impl std::fmt::Display for CacheError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheError::Io(e) => write!(f, "{}", e),
+            CacheError::Serialize(e) => write!(f, "failed to write cache: {}", e),
+            CacheError::Deserialize(e) => write!(f, "failed to read cache: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<std::io::Error> for CacheError {
+    fn from(value: std::io::Error) -> Self {
+        CacheError::Io(value)
+    }
+}
+
+impl From<SerializeError> for CacheError {
+    fn from(value: SerializeError) -> Self {
+        CacheError::Serialize(value)
+    }
+}
+
+impl From<DeserializeError> for CacheError {
+    fn from(value: DeserializeError) -> Self {
+        CacheError::Deserialize(value)
+    }
+}
+
+fn hash_mesh(mesh: &TriMesh, hasher: &mut impl Hasher) {
+    for vertex in mesh.vertices() {
+        for c in vertex.iter() {
+            c.to_bits().hash(hasher);
+        }
+    }
+    mesh.indices().hash(hasher);
+}
+
+/// Identifies everything that decides which voxels are filled: the mesh, the region it is
+/// voxelized in, and the voxelizer settings. Without parts the material isn't included, since it
+/// can be swapped on a cached tree. `DefaultHasher` isn't stable across Rust releases, so a
+/// rebuilt tool may not reuse older caches.
+pub fn fingerprint(
+    mesh: &TriMesh,
+    parts: &[MeshPart],
+    isometry: &Isometry<f64>,
+    aabb: &Aabb,
+    height: usize,
+    options: &GenerateOptions,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_mesh(mesh, &mut hasher);
+    for part in parts {
+        hash_mesh(&part.mesh, &mut hasher);
+        part.material.hash(&mut hasher);
+    }
+    for c in isometry.to_homogeneous().iter() {
+        c.to_bits().hash(&mut hasher);
+    }
+    for c in aabb.mins.iter().chain(aabb.maxs.iter()) {
+        c.to_bits().hash(&mut hasher);
+    }
+    height.hash(&mut hasher);
+    if parts.is_empty() {
+        options.interior_material.is_some().hash(&mut hasher);
+    } else {
+        options.interior_material.hash(&mut hasher);
+    }
+    options.hollow.hash(&mut hasher);
+    options.lattice.hash(&mut hasher);
+    options.collapse_solid.hash(&mut hasher);
+    options.smooth.hash(&mut hasher);
+    options.fill_mode.hash(&mut hasher);
+    options.samples.hash(&mut hasher);
+    options.sample_rule.hash(&mut hasher);
+    options.symmetry.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Loads the tree cached at `path`, or `None` if there is no cache there yet or it was made from
+/// a different fingerprint or by another version.
+pub fn read_cache(
+    path: &Path,
+    fingerprint: u64,
+) -> Result<Option<Svo<Option<VoxelCellData>>>, CacheError> {
+    let mut bytes = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => file.read_to_end(&mut bytes)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut reader = bytes.as_slice();
+    let magic = u32::deserialize(&mut reader)?;
+    if magic != MAGIC {
+        return Err(DeserializeError::BadMagic(magic, MAGIC).into());
+    }
+    if u32::deserialize(&mut reader)? != VERSION || u64::deserialize(&mut reader)? != fingerprint {
+        return Ok(None);
+    }
+    Ok(Some(Svo::decompress(reader)?))
+}
+
+/// Writes the tree to `path` with a header holding the fingerprint, then the LZ4 compressed tree.
+pub fn write_cache(
+    path: &Path,
+    fingerprint: u64,
+    svo: &Svo<Option<VoxelCellData>>,
+) -> Result<(), CacheError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    MAGIC.serialize(&mut writer)?;
+    VERSION.serialize(&mut writer)?;
+    fingerprint.serialize(&mut writer)?;
+    writer.write_all(&svo.compress()?)?;
+    writer.flush()?;
+    Ok(())
+}
@@ -1,12 +1,94 @@
 use crate::squarion::*;
 use crate::svo::*;
 use parry3d_f64::math::{Point, Vector};
-use serde_json::{Value};
-use indicatif::{ProgressBar, ProgressStyle};
-use std::collections::HashMap;
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::{json, Map, Value};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{debug, info, log_enabled, warn, Level};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+
+/// The most materials a single import can use. Index 0 means empty and index 1 is reserved
+/// for the debug material.
+pub const MAX_MATERIALS: usize = 253;
+
+/// Places a vertex in the middle of its voxel. Offsets are in 1/84ths of a voxel.
+pub const DEFAULT_VERTEX_OFFSET: [u8; 3] = [126, 126, 126];
+
+/// The width of a cell in voxels. This is what the game uses, so other leaf sizes won't load in
+/// game.
+pub const DEFAULT_LEAF_SIZE: usize = 32;
+
+// How many positions are collected before they're placed in parallel. Large enough to keep every
+// thread busy, small enough that the batch itself doesn't use much memory.
+pub(crate) const POSITION_BATCH_SIZE: usize = 1 << 16;
+
+// How many vertices are read between progress bar updates. Every update takes the bar's lock, which
+// costs more than handling a vertex.
+const PROGRESS_INTERVAL: u64 = 10_000;
+
+/// The LOD heights of the smallest and largest cores, see `CoreSize::height`.
+pub const MIN_HEIGHT: usize = 5;
+pub const MAX_HEIGHT: usize = 13;
+
+/// The width in voxels of the largest core, at `MAX_HEIGHT`. The game has no larger cores.
+pub const MAX_CORE_VOXELS: usize = 128 << (MAX_HEIGHT - 5);
+
+#[derive(Debug)]
+pub struct TooManyMaterials(pub usize);
+
+impl fmt::Display for TooManyMaterials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "too many materials ({}), max {}", self.0, MAX_MATERIALS)
+    }
+}
+
+impl std::error::Error for TooManyMaterials {}
 
 pub struct JSONImporter;
 
+// Every grid created by `JSONImporter::create_empty_lods` has the leaf size as its inner extent.
+fn leaf_size(svo: &Svo<Option<VoxelCellData>>) -> i32 {
+    match &svo.root {
+        SvoNode::Leaf(Some(cell_data)) | SvoNode::Internal(Some(cell_data), _) => {
+            cell_data.grid.inner_range().size.x
+        }
+        _ => DEFAULT_LEAF_SIZE as i32,
+    }
+}
+
+// How many finest LOD voxels a voxel of the root cell covers.
+fn initial_scale_factor(svo: &Svo<Option<VoxelCellData>>, height: usize) -> i32 {
+    (128 << (height - 5)) / leaf_size(svo)
+}
+
+// Sets a material in a cell, and resets the vertices of its voxel to `offset`. Without
+// `overwrite`, positions that already have a material are left alone.
+fn material_setter(
+    material: u8,
+    offset: [u8; 3],
+    overwrite: bool,
+) -> impl Fn(&mut VoxelCellData, Point<i32>, i32) + Sync {
+    move |cell_data, pos, _| {
+        if !overwrite && cell_data.grid.material_at(&pos).is_some() {
+            return;
+        }
+        cell_data.set_material_at_position(pos, material);
+
+        for dx in 0..=1 {
+            for dy in 0..=1 {
+                for dz in 0..=1 {
+                    let corner_position = Point::new(pos.x - dx, pos.y - dy, pos.z - dz);
+                    cell_data.set_vertex_offset_at_position(corner_position, offset);
+                }
+            }
+        }
+    }
+}
+
 impl JSONImporter {
     fn set_at_all_lods<F>(
         &mut self,
@@ -41,7 +123,7 @@ impl JSONImporter {
             let within_lod = global_position
                 .coords
                 .iter()
-                .all(|&coord| coord % scale_factor == 0);
+                .all(|&coord| coord.rem_euclid(scale_factor) == 0);
 
             if within_lod {
                 match node {
@@ -81,31 +163,143 @@ impl JSONImporter {
         );
     }
 
+    // Same as `set_at_all_lods` for many positions at once. Each node only depends on the
+    // positions within its own range, so the octants are visited in parallel. Positions are
+    // applied to each node in order, so the result is the same as setting them one at a time.
+    fn par_set_at_all_lods<F>(
+        &mut self,
+        svo: &mut Svo<Option<VoxelCellData>>,
+        global_positions: &[Point<i32>],
+        scale_factor: i32,
+        set_fn: F,
+    ) where
+        F: Fn(&mut VoxelCellData, Point<i32>, i32) + Sync,
+    {
+        fn traverse_svo<F>(
+            node: &mut SvoNode<Option<VoxelCellData>>,
+            range: &RangeZYX,
+            global_positions: &[Point<i32>],
+            scale_factor: i32,
+            set_fn: &F,
+        ) where
+            F: Fn(&mut VoxelCellData, Point<i32>, i32) + Sync,
+        {
+            let padding = scale_factor;
+            let padded_range = RangeZYX {
+                origin: range.origin - Vector::repeat(padding),
+                size: range.size + Vector::repeat(2 * padding),
+            };
+            let global_positions = Vec::from_iter(
+                global_positions
+                    .iter()
+                    .copied()
+                    .filter(|p| padded_range.contains_point(*p)),
+            );
+            if global_positions.is_empty() {
+                return;
+            }
+
+            if let SvoNode::Leaf(Some(cell_data)) | SvoNode::Internal(Some(cell_data), _) = node {
+                for global_position in &global_positions {
+                    let within_lod = global_position
+                        .coords
+                        .iter()
+                        .all(|&coord| coord.rem_euclid(scale_factor) == 0);
+                    if within_lod {
+                        set_fn(cell_data, *global_position, scale_factor);
+                    }
+                }
+            }
+
+            if let SvoNode::Internal(_, children) = node {
+                let octants = range.split_at_center();
+                children
+                    .par_iter_mut()
+                    .zip(octants.par_iter())
+                    .for_each(|(child, child_range)| {
+                        traverse_svo(
+                            child,
+                            child_range,
+                            &global_positions,
+                            scale_factor / 2,
+                            set_fn,
+                        )
+                    });
+            }
+        }
+
+        traverse_svo(
+            &mut svo.root,
+            &svo.range,
+            global_positions,
+            scale_factor,
+            &set_fn,
+        );
+    }
+
+    /// Sets the material at `global_position`, and resets the vertices of its voxel to
+    /// `offset`. Use `DEFAULT_VERTEX_OFFSET` for a plain cube.
     pub fn set_material_at_all_lods(
         &mut self,
         svo: &mut Svo<Option<VoxelCellData>>,
         global_position: Point<i32>,
         material: u8,
+        offset: [u8; 3],
         height: usize,
     ) {
-        let initial_scale_factor = 1 << (height - 3);
-
-        self.set_at_all_lods(svo, global_position, 0, initial_scale_factor, |cell_data, pos, scale| {
-            cell_data.set_material_at_position(pos, material);
-
-            for dx in 0..=1 {
-                for dy in 0..=1 {
-                    for dz in 0..=1 {
-                        let corner_position = Point::new(
-                            pos.x - dx,
-                            pos.y - dy,
-                            pos.z - dz,
-                        );
-                        cell_data.set_vertex_offset_at_position(corner_position, [126, 126, 126]);
-                    }
-                }
-            }
-        });
+        self.place_material_at_all_lods(svo, global_position, material, offset, height, true);
+    }
+
+    /// Like `set_material_at_all_lods`, but positions that already have a material are left
+    /// alone, along with their vertices.
+    pub fn union_material_at_all_lods(
+        &mut self,
+        svo: &mut Svo<Option<VoxelCellData>>,
+        global_position: Point<i32>,
+        material: u8,
+        offset: [u8; 3],
+        height: usize,
+    ) {
+        self.place_material_at_all_lods(svo, global_position, material, offset, height, false);
+    }
+
+    /// Same as calling `set_material_at_all_lods` for each position in order, but the
+    /// positions are placed in parallel.
+    pub fn set_materials_at_all_lods(
+        &mut self,
+        svo: &mut Svo<Option<VoxelCellData>>,
+        global_positions: &[Point<i32>],
+        material: u8,
+        offset: [u8; 3],
+        height: usize,
+    ) {
+        let initial_scale_factor = initial_scale_factor(svo, height);
+        self.par_set_at_all_lods(
+            svo,
+            global_positions,
+            initial_scale_factor,
+            material_setter(material, offset, true),
+        );
+    }
+
+    fn place_material_at_all_lods(
+        &mut self,
+        svo: &mut Svo<Option<VoxelCellData>>,
+        global_position: Point<i32>,
+        material: u8,
+        offset: [u8; 3],
+        height: usize,
+        overwrite: bool,
+    ) {
+        let initial_scale_factor = initial_scale_factor(svo, height);
+
+        self.set_at_all_lods(
+            svo,
+            global_position,
+            0,
+            initial_scale_factor,
+            material_setter(material, offset, overwrite),
+        );
     }
 
     pub fn set_vertex_offset_at_all_lods(
@@ -115,28 +309,66 @@ impl JSONImporter {
         offset: Point<u8>,
         height: usize,
     ) {
-        let initial_scale_factor = 1 << (height - 3);
+        let initial_scale_factor = initial_scale_factor(svo, height);
 
-        self.set_at_all_lods(svo, global_position, 0, initial_scale_factor, |cell_data, pos, scale| {
-            cell_data.set_vertex_offset_at_position(pos, offset.into());
-        });
+        self.set_at_all_lods(
+            svo,
+            global_position,
+            0,
+            initial_scale_factor,
+            |cell_data, pos, _scale| {
+                cell_data.set_vertex_offset_at_position(pos, offset.into());
+            },
+        );
     }
 
-    pub fn process_json_and_create_svo(
+    /// Like `set_vertex_offset_at_all_lods`, but vertices that were already moved away from
+    /// `DEFAULT_VERTEX_OFFSET` are left alone.
+    pub fn union_vertex_offset_at_all_lods(
         &mut self,
-        json_data: &Value,
+        svo: &mut Svo<Option<VoxelCellData>>,
+        global_position: Point<i32>,
+        offset: Point<u8>,
         height: usize,
-    ) -> Svo<Option<VoxelCellData>> {
-        let origin = Point::new(0, 0, 0);
+    ) {
+        let initial_scale_factor = initial_scale_factor(svo, height);
+
+        self.set_at_all_lods(
+            svo,
+            global_position,
+            0,
+            initial_scale_factor,
+            |cell_data, pos, _| {
+                let current = cell_data.grid.voxel_at(&pos);
+                if current.is_none() || current == Some(DEFAULT_VERTEX_OFFSET) {
+                    cell_data.set_vertex_offset_at_position(pos, offset.into());
+                }
+            },
+        );
+    }
 
-        // Extract materials mapping
-        let materials_json = json_data["materials"].as_object().expect("Invalid 'materials' mapping");
+    /// Builds the material mapper shared by every cell, along with the index of each material id.
+    /// With `debug_material` the debug material takes index 1 and the others start at 2,
+    /// otherwise they start at 1.
+    pub fn create_material_mapper(
+        &self,
+        material_ids: &[u64],
+        debug_material: bool,
+    ) -> Result<(MaterialMapper, HashMap<u64, u8>), TooManyMaterials> {
+        self.create_named_material_mapper(material_ids, debug_material, &HashMap::new())
+    }
 
-        // Collect material IDs
-        let material_ids: Vec<u64> = materials_json
-            .keys()
-            .map(|k| k.parse::<u64>().expect("Invalid material ID"))
-            .collect();
+    /// Like `create_material_mapper`, but materials in `names` get that short name instead of
+    /// `MatNNNNN`. Names longer than `SHORT_NAME_LENGTH` bytes are truncated, with a warning.
+    pub fn create_named_material_mapper(
+        &self,
+        material_ids: &[u64],
+        debug_material: bool,
+        names: &HashMap<u64, String>,
+    ) -> Result<(MaterialMapper, HashMap<u64, u8>), TooManyMaterials> {
+        if material_ids.len() > MAX_MATERIALS {
+            return Err(TooManyMaterials(material_ids.len()));
+        }
 
         // Build mapping from material IDs to indices
         let mut material_id_to_index: HashMap<u64, u8> = HashMap::new();
@@ -145,19 +377,24 @@ impl JSONImporter {
         let mut material_mapper = MaterialMapper::default();
 
         // Insert the debug material with index 1
-        material_mapper.insert(
-            1,
-            MaterialId {
-                id: 157903047,
-                short_name: "Debug1\0\0".into(),
-            },
-        );
+        if debug_material {
+            material_mapper.insert(
+                1,
+                MaterialId {
+                    id: 157903047,
+                    short_name: "Debug1\0\0".into(),
+                },
+            );
+        }
 
-        // Start material indices from 2 to avoid conflict with debug material
-        let mut material_index = 2;
+        // Start material indices after the debug material, if there is one
+        let mut material_index = if debug_material { 2 } else { 1 };
 
-        for material_id in &material_ids {
-            let short_name = format!("Mat{:05}", material_index); 
+        for material_id in material_ids {
+            let short_name = match names.get(material_id) {
+                Some(name) => fit_short_name(*material_id, name),
+                None => format!("Mat{:05}", material_index),
+            };
             material_mapper.insert(
                 material_index,
                 MaterialId {
@@ -169,135 +406,905 @@ impl JSONImporter {
             material_index += 1;
         }
 
-        // Create empty SVO with the material mapper
-        let mut svo = self.create_empty_lods(origin, height, &material_mapper);
-
-        // Process positions for each material
-        for (material_id_str, positions_json) in materials_json.iter() {
-            let material_id = material_id_str.parse::<u64>().expect("Invalid material ID");
-            let positions = positions_json.as_array().expect("Invalid positions array");
-            let material_index = *material_id_to_index.get(&material_id).expect("Material ID not found in mapping");
-
-            // Create a progress bar for positions
-            let position_bar = ProgressBar::new(positions.len() as u64);
-            position_bar.set_style(
-                ProgressStyle::default_bar()
-                    .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} ({eta})")
-                    .expect("Failed to set progress bar template")
-                    .progress_chars("#>-"),
-            );
+        Ok((material_mapper, material_id_to_index))
+    }
 
-            // Iterate over positions with progress bar
-            for pos in positions {
-                let global_position = Point::new(
-                    (pos[0].as_f64().unwrap() + 0.5).round() as i32,
-                    (pos[1].as_f64().unwrap() + 0.5).round() as i32,
-                    (pos[2].as_f64().unwrap() + 0.5).round() as i32,
-                );
-                self.set_material_at_all_lods(&mut svo, global_position, material_index, height);
-                position_bar.inc(1);
+    /// Converts the root range from voxels to cells and prunes the empty grids.
+    pub fn finish_svo(&self, mut svo: Svo<Option<VoxelCellData>>) -> Svo<Option<VoxelCellData>> {
+        // Adjust the root range, from voxels to leaves. Anything left over would silently shift
+        // the whole tree.
+        let leaf_size = leaf_size(&svo);
+        assert!(
+            svo.range.origin.iter().all(|v| v % leaf_size == 0)
+                && svo.range.size.iter().all(|v| v % leaf_size == 0),
+            "root range {:?} isn't a whole number of {} voxel leaves",
+            svo.range,
+            leaf_size
+        );
+        svo.range = RangeZYX {
+            origin: svo.range.origin / leaf_size,
+            size: svo.range.size / leaf_size,
+        };
+
+        svo.prune_empty_grids()
+    }
+
+    /// Reads the `{"materials": {...}, "vertices": [...]}` format and builds the SVO. The
+    /// input is streamed, so the document never has to fit in memory. `vertices` can be left
+    /// out, in which case every vertex keeps its default centred offset. A flat array of
+    /// `{"x": .., "y": .., "z": .., "material": ..}` voxels is also accepted, which places the
+    /// same positions as the map form but can't set vertices. Materials in `names` get that short
+    /// name, see `create_named_material_mapper`. Positions outside the core are dropped with a
+    /// warning, or are an error if `strict` is set.
+    pub fn process_json_and_create_svo(
+        &mut self,
+        reader: impl Read,
+        height: usize,
+        leaf_size: usize,
+        debug_material: bool,
+        names: &HashMap<u64, String>,
+        strict: bool,
+    ) -> Result<Svo<Option<VoxelCellData>>, serde_json::Error> {
+        let origin = Point::new(0, 0, 0);
+
+        // Material indices are handed out as the ids are read, and fixed up at the end once we
+        // know every id.
+        let (empty_mapper, _) = self.create_material_mapper(&[], debug_material).unwrap();
+        let svo = self
+            .try_create_empty_lods(origin, height, leaf_size, &empty_mapper)
+            .map_err(de::Error::custom)?;
+
+        let mut import = JsonImport {
+            importer: self,
+            svo,
+            height,
+            names,
+            material_ids: Vec::new(),
+            material_keys: HashSet::new(),
+            unknown_inline_ids: BTreeSet::new(),
+            material_offsets: HashMap::new(),
+            materials_read: false,
+            pending_vertices: Vec::new(),
+            outside_positions: 0,
+        };
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        (&mut import).deserialize(&mut deserializer)?;
+        deserializer.end()?;
+        let JsonImport {
+            mut svo,
+            material_ids,
+            outside_positions,
+            ..
+        } = import;
+        if outside_positions > 0 {
+            let message = format!("{} positions outside core bounds", outside_positions);
+            if strict {
+                return Err(de::Error::custom(message));
             }
-            position_bar.finish_with_message(format!("Positions for material {} processed", material_id));
+            warn!("{} were dropped", message);
         }
 
-        // Process vertices
-        let vertices = json_data["vertices"].as_array().expect("Invalid 'vertices' array");
-
-        // Create a progress bar for vertices
-        let vertex_bar = ProgressBar::new(vertices.len() as u64);
-        vertex_bar.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.magenta/red}] {pos}/{len} ({eta})")
-                .expect("Failed to set progress bar template")
-                .progress_chars("#>-"),
+        // The ids are read in document order, so they're sorted before being indexed, otherwise
+        // reordering the keys of the same materials would change the output. They're sorted as
+        // strings, since that's the order serde_json used to give the keys in, which keeps the
+        // indices the same as older versions.
+        let mut sorted_ids = material_ids.clone();
+        sorted_ids.sort_by_key(|id| id.to_string());
+        let (material_mapper, material_id_to_index) = self
+            .create_named_material_mapper(&sorted_ids, debug_material, names)
+            .map_err(de::Error::custom)?;
+        let remap = HashMap::from_iter(
+            material_ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| ((i + 2) as u8, material_id_to_index[id])),
         );
+        let material_mapper = Arc::new(material_mapper);
+        svo.for_each_mut(|_, v| {
+            if let Some(cell_data) = v {
+                cell_data.remap_materials(material_mapper.clone(), &remap);
+            }
+        });
 
-        // Iterate over vertices with progress bar
-        for vert in vertices {
-            let x = vert[0].as_f64().unwrap_or_else(|| vert[0].as_i64().unwrap() as f64);
-            let y = vert[1].as_f64().unwrap_or_else(|| vert[1].as_i64().unwrap() as f64);
-            let z = vert[2].as_f64().unwrap_or_else(|| vert[2].as_i64().unwrap() as f64);
-            let global_position = Point::new(x as i32, y as i32, z as i32);
-
-            let offset_x = vert[3].as_f64().unwrap_or_else(|| vert[3].as_i64().unwrap() as f64) as u8;
-            let offset_y = vert[4].as_f64().unwrap_or_else(|| vert[4].as_i64().unwrap() as f64) as u8;
-            let offset_z = vert[5].as_f64().unwrap_or_else(|| vert[5].as_i64().unwrap() as f64) as u8;
-            let offset = Point::new(offset_x, offset_y, offset_z);
-
-            self.set_vertex_offset_at_all_lods(&mut svo, global_position, offset, height);
-            vertex_bar.inc(1);
+        Ok(self.finish_svo(svo))
+    }
+
+    /// Builds an SVO with an empty cell at every node of a core with the given LOD height, with
+    /// cells `leaf_size` voxels wide. Fails if the height doesn't match a core size, or if the
+    /// leaf size isn't a power of two that fits in the core.
+    pub fn try_create_empty_lods(
+        &self,
+        origin: Point<i32>,
+        height: usize,
+        leaf_size: usize,
+        material_mapper: &MaterialMapper,
+    ) -> Result<Svo<Option<VoxelCellData>>, SvoError> {
+        // Larger heights would run out of memory or overflow the core size below, so this is
+        // checked before anything is built.
+        if height > MAX_HEIGHT {
+            return Err(SvoError::HeightTooLarge(height, MAX_HEIGHT));
         }
-        vertex_bar.finish_with_message("Vertices processed");
+        if height < MIN_HEIGHT {
+            return Err(SvoError::InvalidHeight(height));
+        }
+        let core_size = 128 * (1 << (height - 5));
+        // The finest LOD has to land on whole cells, or the LOD scales stop halving evenly.
+        if !leaf_size.is_power_of_two() || leaf_size > core_size {
+            return Err(SvoError::InvalidLeafSize(leaf_size, core_size));
+        }
+        let leaf_size = leaf_size as i32;
+        info!(
+            "Creating empty LODs with core size: {} and leaf size: {}",
+            core_size, leaf_size
+        );
 
-        // Adjust the root range
-        let scale_factor = 32;
-        svo.range = RangeZYX {
-            origin: svo.range.origin / scale_factor,
-            size: Vector::new(
-                svo.range.size.x / scale_factor,
-                svo.range.size.y / scale_factor,
-                svo.range.size.z / scale_factor,
-            ),
-        };
+        // Every node shares the one mapper.
+        let material_mapper = Arc::new(material_mapper.clone());
+        Svo::try_from_fn(origin, core_size, &|range| {
+            let inner_range = RangeZYX::with_extent(range.origin, leaf_size);
+            let grid = VertexGrid::new(VertexGrid::padded_range(&inner_range), inner_range);
+            let voxel_cell_data = VoxelCellData::new(grid, material_mapper.clone());
 
-        let pruned_svo = svo.prune_empty_grids();
-        pruned_svo
+            if range.size.x <= leaf_size {
+                debug!(
+                    "Creating leaf node with range origin = {:?}, size = {:?}",
+                    range.origin, range.size
+                );
+                SvoReturn::Leaf(Some(voxel_cell_data))
+            } else {
+                debug!(
+                    "Creating internal node with range origin = {:?}, size = {:?}",
+                    range.origin, range.size
+                );
+                SvoReturn::Internal(Some(voxel_cell_data))
+            }
+        })
     }
 
+    /// Same as `try_create_empty_lods`, but panics if the height or leaf size is invalid.
     pub fn create_empty_lods(
         &self,
         origin: Point<i32>,
         height: usize,
+        leaf_size: usize,
         material_mapper: &MaterialMapper,
     ) -> Svo<Option<VoxelCellData>> {
-        let core_size = 128 * (1 << (height - 5));
-        let leaf_size = 32;
-        println!("Creating empty LODs with core size: {} and leaf size: {}", core_size, leaf_size);
+        self.try_create_empty_lods(origin, height, leaf_size, material_mapper)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+}
 
-        // Recursive function to build the SVO nodes
-        fn build_svo_node(
-            range: &RangeZYX,
-            leaf_size: i32,
-            depth: usize,
-            max_depth: usize,
-            material_mapper: &MaterialMapper,
-        ) -> SvoNode<Option<VoxelCellData>> {
-
-            if range.size.x <= leaf_size || depth >= max_depth {
-                let outer_range = RangeZYX::with_extent(range.origin - Vector::repeat(1), 35);
-                let inner_range = RangeZYX::with_extent(range.origin, leaf_size);
-                let grid = VertexGrid::new(outer_range.clone(), inner_range.clone());
-
-                println!(
-                    "Creating leaf node at depth {} with range origin = {:?}, size = {:?}",
-                    depth, range.origin, range.size
+// Streaming state for `JSONImporter::process_json_and_create_svo`.
+struct JsonImport<'a> {
+    importer: &'a mut JSONImporter,
+    svo: Svo<Option<VoxelCellData>>,
+    height: usize,
+    names: &'a HashMap<u64, String>,
+    // Material ids in the order they were read. Each id's provisional index is its position + 2.
+    material_ids: Vec<u64>,
+    // The ids of the keys under "materials", which are the ids a position can name inline.
+    material_keys: HashSet<u64>,
+    // Inline ids that weren't a key or a name when they were read. A key later on can still
+    // make them valid, so they're only checked once every key has been read.
+    unknown_inline_ids: BTreeSet<u64>,
+    // Vertex offsets used around each material's voxels instead of `DEFAULT_VERTEX_OFFSET`.
+    material_offsets: HashMap<u64, [u8; 3]>,
+    materials_read: bool,
+    // Vertices that came before the materials. They have to be applied last, since setting a
+    // material resets the surrounding vertex offsets.
+    pending_vertices: Vec<(Point<i32>, Point<u8>)>,
+    // Positions whose voxel isn't in the core. They are dropped before placing, so none of them
+    // end up in a cell's padding either.
+    outside_positions: u64,
+}
+
+impl JsonImport<'_> {
+    // Returns `None` once there are too many materials to index. The ids are still recorded so
+    // the error can say how many there are.
+    fn material_index(&mut self, material_id: u64) -> Option<u8> {
+        let position = match self.material_ids.iter().position(|id| *id == material_id) {
+            Some(position) => position,
+            None => {
+                self.material_ids.push(material_id);
+                self.material_ids.len() - 1
+            }
+        };
+        (position < MAX_MATERIALS).then_some((position + 2) as u8)
+    }
+
+    // The vertex offset used around the voxels of a material index from `material_index`.
+    fn material_offset(&self, material_index: u8) -> [u8; 3] {
+        let material_id = self.material_ids[material_index as usize - 2];
+        self.material_offsets
+            .get(&material_id)
+            .copied()
+            .unwrap_or(DEFAULT_VERTEX_OFFSET)
+    }
+
+    fn place_batch(&mut self, batch: &[Point<i32>], material_index: u8, offset: [u8; 3]) {
+        // The root range is still in voxels, and each material is on the +[1, 1, 1] vertex of
+        // its voxel. Positions just outside the core would still land in the padding of the
+        // edge cells, so they're removed here rather than left to the grids.
+        let core = &self.svo.range;
+        let inside: Vec<_> = batch
+            .iter()
+            .copied()
+            .filter(|p| core.contains_point(p - Vector::repeat(1)))
+            .collect();
+        self.outside_positions += (batch.len() - inside.len()) as u64;
+        self.importer.set_materials_at_all_lods(
+            &mut self.svo,
+            &inside,
+            material_index,
+            offset,
+            self.height,
+        );
+    }
+
+    fn set_vertex(&mut self, position: Point<i32>, offset: Point<u8>) {
+        self.importer
+            .set_vertex_offset_at_all_lods(&mut self.svo, position, offset, self.height);
+    }
+}
+
+// Pads a short name with `\0` to the fixed length, or truncates it at a character boundary if
+// it's too long.
+fn fit_short_name(material_id: u64, name: &str) -> String {
+    let mut short_name = name.to_string();
+    if short_name.len() > SHORT_NAME_LENGTH {
+        let mut end = SHORT_NAME_LENGTH;
+        while !short_name.is_char_boundary(end) {
+            end -= 1;
+        }
+        short_name.truncate(end);
+        warn!(
+            "Name {:?} for material {} is longer than {} bytes, truncated to {:?}",
+            name, material_id, SHORT_NAME_LENGTH, short_name
+        );
+    }
+    while short_name.len() < SHORT_NAME_LENGTH {
+        short_name.push('\0');
+    }
+    short_name
+}
+
+// Positions are voxel corners, rounded to the vertex that holds the voxel's material.
+fn material_position(x: f64, y: f64, z: f64) -> Point<i32> {
+    Point::new(
+        (x + 0.5).round() as i32,
+        (y + 0.5).round() as i32,
+        (z + 0.5).round() as i32,
+    )
+}
+
+// Vertex offsets only go up to 255, so anything outside of that is clamped rather than left to
+// the `as` cast. Also returns whether the offset had to be clamped.
+fn clamp_offset(offset: [f64; 3]) -> ([u8; 3], bool) {
+    let clamped = offset.map(|v| v.clamp(0.0, 255.0));
+    (clamped.map(|v| v as u8), clamped != offset)
+}
+
+pub(crate) fn progress_bar(template: &str) -> ProgressBar {
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(
+        ProgressStyle::default_spinner()
+            .template(template)
+            .expect("Failed to set progress bar template"),
+    );
+    // Progress is info level output, so hide it along with the logs.
+    if !log_enabled!(Level::Info) {
+        bar.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    bar
+}
+
+impl<'de> DeserializeSeed<'de> for &mut JsonImport<'_> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for &mut JsonImport<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(
+            "an object with 'materials' and optionally 'vertices', or an array of \
+             {x, y, z, material} voxels",
+        )
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<(), A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let voxel_bar =
+            progress_bar("{spinner:.green} [{elapsed_precise}] {pos} voxels ({per_sec})");
+
+        // The materials can come in any order, so each one is batched separately.
+        let mut batches: BTreeMap<u8, Vec<Point<i32>>> = BTreeMap::new();
+        while let Some(voxel) = seq.next_element::<Value>()? {
+            let coordinate = |name: &str| -> Result<f64, A::Error> {
+                voxel.get(name).and_then(Value::as_f64).ok_or_else(|| {
+                    de::Error::custom(format!("voxel {} has no numeric {:?}", voxel, name))
+                })
+            };
+            let position = material_position(coordinate("x")?, coordinate("y")?, coordinate("z")?);
+            let material_id = match voxel.get("material") {
+                Some(Value::Number(n)) => n.as_u64(),
+                Some(Value::String(s)) => s.trim().parse().ok(),
+                _ => None,
+            };
+            let Some(material_id) = material_id else {
+                return Err(de::Error::custom(format!(
+                    "voxel {} has no material ID",
+                    voxel
+                )));
+            };
+            // Keep going once there are too many materials, so we can report the total.
+            let Some(material_index) = self.material_index(material_id) else {
+                continue;
+            };
+            let batch = batches.entry(material_index).or_default();
+            batch.push(position);
+            if batch.len() == POSITION_BATCH_SIZE {
+                self.place_batch(batch, material_index, DEFAULT_VERTEX_OFFSET);
+                voxel_bar.inc(batch.len() as u64);
+                batch.clear();
+            }
+        }
+        if self.material_ids.len() > MAX_MATERIALS {
+            return Err(de::Error::custom(TooManyMaterials(self.material_ids.len())));
+        }
+        for (material_index, batch) in batches {
+            self.place_batch(&batch, material_index, DEFAULT_VERTEX_OFFSET);
+            voxel_bar.inc(batch.len() as u64);
+        }
+        voxel_bar.finish_with_message("Voxels processed");
+        self.materials_read = true;
+        Ok(())
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<(), A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "materials" => {
+                    map.next_value_seed(Materials(&mut *self))?;
+                    self.materials_read = true;
+                    for (position, offset) in std::mem::take(&mut self.pending_vertices) {
+                        self.set_vertex(position, offset);
+                    }
+                }
+                "vertices" => {
+                    map.next_value_seed(Vertices(&mut *self))?;
+                }
+                // Sorted objects put this before "materials", which is where it has to be to
+                // apply while streaming.
+                "material_offsets" => {
+                    if self.materials_read {
+                        return Err(de::Error::custom(
+                            "material_offsets must come before materials",
+                        ));
+                    }
+                    let offsets = map.next_value::<HashMap<String, [f64; 3]>>()?;
+                    for (key, offset) in offsets {
+                        let material_id = key.trim().parse::<u64>().map_err(|_| {
+                            de::Error::custom(format!("invalid material ID {:?}", key))
+                        })?;
+                        let (offset, clamped) = clamp_offset(offset);
+                        if clamped {
+                            warn!(
+                                "Offset for material {} is outside of 0-255, clamped to {:?}",
+                                material_id, offset
+                            );
+                        }
+                        self.material_offsets.insert(material_id, offset);
+                    }
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if !self.materials_read {
+            return Err(de::Error::missing_field("materials"));
+        }
+        Ok(())
+    }
+}
+
+struct Materials<'b, 'a>(&'b mut JsonImport<'a>);
+
+impl<'de> DeserializeSeed<'de> for Materials<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Materials<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of material IDs to positions")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<(), A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        while let Some(key) = map.next_key::<String>()? {
+            let material_id = key
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| de::Error::custom(format!("invalid material ID {:?}", key)))?;
+            // Keys like "1" and " 01" are the same id. They share an index, so their positions
+            // are merged, in the order they are read.
+            if !self.0.material_keys.insert(material_id) {
+                warn!(
+                    "Material ID {} appears more than once, merging its positions",
+                    material_id
                 );
+            }
+            match self.0.material_index(material_id) {
+                Some(material_index) => map.next_value_seed(Positions {
+                    import: &mut *self.0,
+                    material_id,
+                    material_index,
+                })?,
+                // Keep going so we can report the total.
+                None => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if self.0.material_ids.len() > MAX_MATERIALS {
+            return Err(de::Error::custom(TooManyMaterials(
+                self.0.material_ids.len(),
+            )));
+        }
+        let import = &*self.0;
+        if let Some(material_id) = import
+            .unknown_inline_ids
+            .iter()
+            .find(|id| !import.material_keys.contains(id))
+        {
+            return Err(de::Error::custom(format!(
+                "material ID {} in a position is neither a material key nor in the names",
+                material_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+struct Positions<'b, 'a> {
+    import: &'b mut JsonImport<'a>,
+    material_id: u64,
+    material_index: u8,
+}
+
+impl<'de> DeserializeSeed<'de> for Positions<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Positions<'_, '_> {
+    type Value = ();
 
-                let voxel_cell_data = VoxelCellData::new(grid, material_mapper.clone());
-                SvoNode::Leaf(Some(voxel_cell_data))
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of [x, y, z] or [x, y, z, material] positions")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<(), A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        // Create a progress bar for positions
+        let position_bar =
+            progress_bar("{spinner:.green} [{elapsed_precise}] {pos} positions ({per_sec})");
+
+        // Positions are collected into a batch for each material, which are placed in parallel.
+        let import = &mut *self.import;
+        let place_batch =
+            |import: &mut JsonImport<'_>, material_index: u8, batch: &mut Vec<Point<i32>>| {
+                let offset = import.material_offset(material_index);
+                import.place_batch(batch, material_index, offset);
+                position_bar.inc(batch.len() as u64);
+                batch.clear();
+            };
+        let mut batches = BTreeMap::<u8, Vec<Point<i32>>>::new();
+        while let Some(pos) = seq.next_element::<Vec<f64>>()? {
+            if pos.len() < 3 {
+                return Err(de::Error::invalid_length(
+                    pos.len(),
+                    &"[x, y, z] or [x, y, z, material]",
+                ));
+            }
+            // A fourth value overrides the material of the group for just this position.
+            let material_index = match pos.get(3) {
+                None => self.material_index,
+                Some(&material_id) => {
+                    if material_id < 0.0 || material_id.fract() != 0.0 {
+                        return Err(de::Error::custom(format!(
+                            "invalid material ID {} in position {:?}",
+                            material_id, pos
+                        )));
+                    }
+                    let material_id = material_id as u64;
+                    if !import.material_keys.contains(&material_id)
+                        && !import.names.contains_key(&material_id)
+                    {
+                        import.unknown_inline_ids.insert(material_id);
+                    }
+                    // Keep going once there are too many materials, so we can report the total.
+                    match import.material_index(material_id) {
+                        Some(material_index) => material_index,
+                        None => continue,
+                    }
+                }
+            };
+            let batch = batches.entry(material_index).or_default();
+            batch.push(material_position(pos[0], pos[1], pos[2]));
+            if batch.len() == POSITION_BATCH_SIZE {
+                place_batch(import, material_index, batch);
+            }
+        }
+        for (material_index, mut batch) in batches {
+            place_batch(import, material_index, &mut batch);
+        }
+        position_bar.finish_with_message(format!(
+            "Positions for material {} processed",
+            self.material_id
+        ));
+        Ok(())
+    }
+}
+
+struct Vertices<'b, 'a>(&'b mut JsonImport<'a>);
+
+impl<'de> DeserializeSeed<'de> for Vertices<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Vertices<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of [x, y, z, offset_x, offset_y, offset_z] vertices")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<(), A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        // Create a progress bar for vertices
+        let vertex_bar =
+            progress_bar("{spinner:.magenta} [{elapsed_precise}] {pos} vertices ({per_sec})");
+
+        // Iterate over vertices with progress bar
+        let mut clamped_count = 0;
+        let mut unreported = 0;
+        while let Some(vert) = seq.next_element::<Vec<f64>>()? {
+            if vert.len() < 6 {
+                return Err(de::Error::invalid_length(
+                    vert.len(),
+                    &"[x, y, z, offset_x, offset_y, offset_z]",
+                ));
+            }
+            let global_position = Point::new(vert[0] as i32, vert[1] as i32, vert[2] as i32);
+            let (offset, clamped) = clamp_offset([vert[3], vert[4], vert[5]]);
+            let offset = Point::from(offset);
+            clamped_count += clamped as usize;
+
+            if self.0.materials_read {
+                self.0.set_vertex(global_position, offset);
             } else {
-                println!(
-                    "Creating internal node at depth {} with range origin = {:?}, size = {:?}",
-                    depth, range.origin, range.size
-                );
+                self.0.pending_vertices.push((global_position, offset));
+            }
+            unreported += 1;
+            if unreported == PROGRESS_INTERVAL {
+                vertex_bar.inc(unreported);
+                unreported = 0;
+            }
+        }
+        vertex_bar.inc(unreported);
+        vertex_bar.finish_with_message("Vertices processed");
+        if clamped_count > 0 {
+            warn!(
+                "{} vertex offsets were outside of 0-255 and have been clamped",
+                clamped_count
+            );
+        }
+        Ok(())
+    }
+}
 
-                let outer_range = RangeZYX::with_extent(range.origin - Vector::repeat(1), 35);
-                let inner_range = RangeZYX::with_extent(range.origin, leaf_size);
-                let grid = VertexGrid::new(outer_range.clone(), inner_range.clone());
+impl Svo<Option<VoxelCellData>> {
+    /// Exports the finest LOD in the format that `JSONImporter::process_json_and_create_svo`
+    /// reads, so a blueprint can be edited and imported again.
+    pub fn to_import_json(&self) -> Value {
+        let mut materials = BTreeMap::<u64, Vec<Value>>::new();
+        let mut vertices = Vec::new();
+        self.cata(|range, v, cs| {
+            let Some(cell_data) = v else { return };
+            if cs.is_some() || range.size.x != 1 {
+                return;
+            }
+            cell_data.grid.for_each_material(|pos, material| {
+                if let Some(material_id) = cell_data.mapping().get(material) {
+                    // The importer places materials at `round(position + 0.5)`, which is the
+                    // +[1, 1, 1] vertex, so step back to get the original position.
+                    let pos = pos - Vector::repeat(1);
+                    materials
+                        .entry(material_id.id)
+                        .or_default()
+                        .push(json!([pos.x, pos.y, pos.z]));
+                }
+            });
+            cell_data.grid.for_each_voxel(|pos, offset| {
+                vertices.push(json!([pos.x, pos.y, pos.z, offset[0], offset[1], offset[2]]));
+            });
+        });
 
-                let voxel_cell_data = VoxelCellData::new(grid, material_mapper.clone());
+        let materials = Map::from_iter(
+            materials
+                .into_iter()
+                .map(|(id, positions)| (id.to_string(), Value::Array(positions))),
+        );
+        json!({
+            "materials": materials,
+            "vertices": vertices,
+        })
+    }
+}
 
-                let children = Box::new(range.split_at_center().map(|sub_range| {
-                    build_svo_node(&sub_range, leaf_size, depth + 1, max_depth, material_mapper)
-                }));
+impl SvoDiff {
+    /// A patch in the format that `JSONImporter::process_json_and_create_svo` reads, with an
+    /// extra `"removed"` list of the voxels to clear. The importer ignores the removals.
+    pub fn to_import_json(&self) -> Value {
+        let materials = Map::from_iter(self.materials.iter().map(|(id, positions)| {
+            let positions = positions.iter().map(|p| json!([p.x, p.y, p.z]));
+            (id.to_string(), Value::Array(positions.collect()))
+        }));
+        let vertices = Vec::from_iter(
+            self.vertices
+                .iter()
+                .map(|(p, o)| json!([p.x, p.y, p.z, o[0], o[1], o[2]])),
+        );
+        let removed = Vec::from_iter(self.removed.iter().map(|p| json!([p.x, p.y, p.z])));
+        json!({
+            "materials": materials,
+            "vertices": vertices,
+            "removed": removed,
+        })
+    }
+}
 
-                SvoNode::Internal(Some(voxel_cell_data), children)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blueprint::{Blueprint, CoreInfo, CoreSize, CoreType};
+    use std::collections::BTreeSet;
+    use std::sync::Mutex;
+
+    fn import(json: &str) -> Svo<Option<VoxelCellData>> {
+        JSONImporter
+            .process_json_and_create_svo(
+                json.as_bytes(),
+                CoreSize::XS.height(),
+                DEFAULT_LEAF_SIZE,
+                true,
+                &HashMap::new(),
+                false,
+            )
+            .unwrap()
+    }
+
+    // The construct json with the timestamps taken out, since those change on every call.
+    fn construct_json(svo: Svo<Option<VoxelCellData>>) -> Value {
+        fn remove_timestamps(value: &mut Value) {
+            match value {
+                Value::Object(object) => {
+                    for key in ["created_at", "updated_at", "CreatedAt"] {
+                        object.remove(key);
+                    }
+                    object.values_mut().for_each(remove_timestamps);
+                }
+                Value::Array(array) => array.iter_mut().for_each(remove_timestamps),
+                _ => (),
             }
         }
+        let info = CoreInfo::from(CoreSize::XS, CoreType::Dynamic);
+        let mut json = Blueprint::new("test".into(), info, 1, svo).to_construct_json();
+        remove_timestamps(&mut json);
+        json
+    }
+
+    #[test]
+    fn material_key_order_does_not_change_the_blueprint() {
+        let forward = import(
+            r#"{"materials": {
+                "1971262921": [[4, 4, 4], [5, 4, 4]],
+                "20": [[4, 5, 4]],
+                "1234": [[4, 4, 5], [40, 40, 40]]
+            }}"#,
+        );
+        let shuffled = import(
+            r#"{"materials": {
+                "1234": [[4, 4, 5], [40, 40, 40]],
+                "1971262921": [[4, 4, 4], [5, 4, 4]],
+                "20": [[4, 5, 4]]
+            }}"#,
+        );
+        assert_eq!(construct_json(forward), construct_json(shuffled));
+    }
+
+    #[test]
+    fn positions_outside_the_core_are_dropped() {
+        // The core holds voxels 0 to 127 on each axis, so [-1, 4, 4] and [128, 4, 4] are just
+        // outside either side of it.
+        let json = r#"{"materials": {"5": [[4, 4, 4], [-1, 4, 4], [128, 4, 4]]}}"#;
+        let dropped = import(json);
+        let inside = import(r#"{"materials": {"5": [[4, 4, 4]]}}"#);
+        assert_eq!(construct_json(dropped), construct_json(inside));
 
-        let root_range = RangeZYX::with_extent(origin, core_size as i32);
-        let root_node = build_svo_node(&root_range, leaf_size, 0, height - 3, material_mapper);
-        println!("Created root node at depth 0 with range origin = {:?}, size = {:?}", root_range.origin, root_range.size);
-        Svo { root: root_node, range: root_range }
+        let strict = JSONImporter.process_json_and_create_svo(
+            json.as_bytes(),
+            CoreSize::XS.height(),
+            DEFAULT_LEAF_SIZE,
+            true,
+            &HashMap::new(),
+            true,
+        );
+        let error = strict.err().unwrap().to_string();
+        assert!(error.starts_with("2 positions outside core bounds"));
+    }
+
+    #[test]
+    fn inline_material_ids_must_be_known() {
+        let import_with_names = |json: &str, names: &HashMap<u64, String>| {
+            JSONImporter.process_json_and_create_svo(
+                json.as_bytes(),
+                CoreSize::XS.height(),
+                DEFAULT_LEAF_SIZE,
+                true,
+                names,
+                false,
+            )
+        };
+        let names = HashMap::from([(7, "Named".to_string())]);
+
+        // 6 is a key, even though it comes after the position naming it, and 7 has a name.
+        let known = r#"{"materials": {"5": [[4, 4, 4, 6], [5, 4, 4, 7]], "6": [[6, 4, 4]]}}"#;
+        assert!(import_with_names(known, &names).is_ok());
+
+        let unknown = r#"{"materials": {"5": [[4, 4, 4], [5, 4, 4, 8]]}}"#;
+        let error = import_with_names(unknown, &names).err().unwrap();
+        assert!(error.to_string().starts_with("material ID 8 in a position"));
+    }
+
+    #[test]
+    fn repeated_material_ids_are_merged() {
+        let svo = import(
+            r#"{"materials": {
+                "5": [[4, 4, 4]],
+                " 5 ": [[8, 8, 8]],
+                "05": [[12, 12, 12]]
+            }}"#,
+        );
+        let mut indices = BTreeSet::new();
+        svo.cata(|range, v, cs| {
+            if let (Some(cell_data), None) = (v, cs) {
+                if range.size.x == 1 {
+                    cell_data.grid.for_each_material(|_, index| {
+                        indices.insert(index);
+                    });
+                }
+            }
+        });
+        assert_eq!(indices.len(), 1);
+
+        let counts = svo.voxel_counts();
+        assert_eq!(counts.materials.len(), 1);
+        let (material, count) = counts.materials.first_key_value().unwrap();
+        assert_eq!(material.id, 5);
+        assert_eq!(*count, 3);
+    }
+
+    #[test]
+    fn offsets_in_range_are_kept() {
+        assert_eq!(clamp_offset([0.0, 126.0, 255.0]), ([0, 126, 255], false));
+    }
+
+    #[test]
+    fn negative_offsets_are_clamped_to_zero() {
+        assert_eq!(clamp_offset([-1.0, 126.0, -300.0]), ([0, 126, 0], true));
+    }
+
+    #[test]
+    fn large_offsets_are_clamped_to_255() {
+        // `as u8` alone would wrap 300 around to 44.
+        assert_eq!(clamp_offset([300.0, 126.0, 255.5]), ([255, 126, 255], true));
+    }
+
+    #[test]
+    fn nan_offsets_become_zero() {
+        assert_eq!(clamp_offset([f64::NAN, 126.0, 1.0]), ([0, 126, 1], true));
+    }
+
+    // The scales of the LODs each position is set at, for a core centred on the origin.
+    fn lod_scales(positions: &[Point<i32>], parallel: bool) -> BTreeMap<[i32; 3], BTreeSet<i32>> {
+        let (mapper, _) = JSONImporter.create_material_mapper(&[], false).unwrap();
+        let mut svo = JSONImporter.create_empty_lods(
+            Point::new(-64, -64, -64),
+            CoreSize::XS.height(),
+            DEFAULT_LEAF_SIZE,
+            &mapper,
+        );
+        let scale_factor = initial_scale_factor(&svo, CoreSize::XS.height());
+        let calls = Mutex::new(BTreeMap::<[i32; 3], BTreeSet<i32>>::new());
+        let record = |_: &mut VoxelCellData, pos: Point<i32>, scale: i32| {
+            let mut calls = calls.lock().unwrap();
+            calls
+                .entry([pos.x, pos.y, pos.z])
+                .or_default()
+                .insert(scale);
+        };
+        if parallel {
+            JSONImporter.par_set_at_all_lods(&mut svo, positions, scale_factor, record);
+        } else {
+            for position in positions {
+                JSONImporter.set_at_all_lods(&mut svo, *position, 0, scale_factor, record);
+            }
+        }
+        calls.into_inner().unwrap()
+    }
+
+    #[test]
+    fn positions_on_both_sides_of_the_origin_reach_every_lod() {
+        // Aligned to the coarsest LOD, which is 4 voxels per cell in an XS core.
+        let half = [
+            Point::new(4, 4, 4),
+            Point::new(8, -12, 16),
+            Point::new(28, 4, 60),
+        ];
+        let positions = Vec::from_iter(half.iter().flat_map(|p| [*p, -*p]));
+        for parallel in [false, true] {
+            let scales = lod_scales(&positions, parallel);
+            for position in &positions {
+                assert_eq!(
+                    scales.get(&[position.x, position.y, position.z]),
+                    Some(&BTreeSet::from([1, 2, 4])),
+                    "{:?} should be set at every LOD",
+                    position
+                );
+            }
+        }
     }
 }
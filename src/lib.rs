@@ -0,0 +1,692 @@
+pub mod binvox;
+pub mod blueprint;
+pub mod cache;
+pub mod import;
+pub mod mesh_export;
+pub mod mesh_import;
+pub mod points;
+pub mod primitive;
+pub mod squarion;
+pub mod svo;
+pub mod vox;
+pub mod voxelization;
+
+use std::collections::HashMap;
+use std::f64::consts::FRAC_PI_2;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use log::info;
+use parry3d_f64::bounding_volume::Aabb;
+use parry3d_f64::math::{Isometry, Point, Vector};
+use parry3d_f64::na::UnitQuaternion;
+use parry3d_f64::shape::TriMesh;
+use serde_json::{json, Value};
+
+use crate::blueprint::*;
+use crate::cache::{fingerprint, read_cache, write_cache, CacheError};
+use crate::import::{JSONImporter, TooManyMaterials, DEFAULT_LEAF_SIZE, DEFAULT_VERTEX_OFFSET};
+use crate::squarion::{RangeZYX, VoxelCellData};
+use crate::svo::{Axis, CombineOp, Svo, SvoError};
+use crate::voxelization::*;
+
+/// How a model is scaled into the core.
+#[derive(Debug, Clone, Copy)]
+pub enum ScaleMode {
+    /// Scale the model to fill the core, leaving `pad` empty voxels on each side of the longest
+    /// axis. A `margin` instead leaves that fraction of the core empty, split between both
+    /// sides, and takes precedence over the pad. Without either the model fills half the core.
+    Auto {
+        pad: Option<u32>,
+        margin: Option<f64>,
+    },
+    Uniform(f64),
+    PerAxis(Vector<f64>),
+}
+
+/// DU's vertical axis. `UpAxis` stands models upright along it, and `Anchor` moves them along it.
+pub const VERTICAL_AXIS: Axis = Axis::Z;
+
+/// Where the model sits vertically in the core, along `VERTICAL_AXIS`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum Anchor {
+    /// On the floor of the core, e.g. for buildings.
+    Bottom,
+    #[default]
+    Center,
+    /// Against the ceiling of the core.
+    Top,
+}
+
+impl Anchor {
+    /// Moves the voxelized region along `VERTICAL_AXIS` so the mesh sits at this anchor.
+    pub fn apply(&self, aabb: Aabb, mesh_aabb: &Aabb) -> Aabb {
+        let axis = VERTICAL_AXIS as usize;
+        let shift = match self {
+            Anchor::Bottom => mesh_aabb.mins[axis] - aabb.mins[axis],
+            Anchor::Center => 0.0,
+            Anchor::Top => mesh_aabb.maxs[axis] - aabb.maxs[axis],
+        };
+        let shift = Vector::ith(axis, shift);
+        Aabb::new(aabb.mins + shift, aabb.maxs + shift)
+    }
+}
+
+/// The up axis of the model's coordinate system. DU is Z-up, see `VERTICAL_AXIS`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum UpAxis {
+    /// Most game engines and the glTF and OBJ exporters of many tools.
+    Y,
+    #[default]
+    Z,
+}
+
+impl UpAxis {
+    /// The rotation that turns this up axis into `VERTICAL_AXIS`.
+    pub fn rotation(&self) -> UnitQuaternion<f64> {
+        match self {
+            UpAxis::Y => UnitQuaternion::from_axis_angle(&Vector::x_axis(), FRAC_PI_2),
+            UpAxis::Z => UnitQuaternion::identity(),
+        }
+    }
+
+    /// The axis pointing forward, which is the one mirrored to change handedness.
+    pub fn forward_axis(&self) -> usize {
+        match self {
+            UpAxis::Y => 2,
+            UpAxis::Z => 1,
+        }
+    }
+}
+
+/// The handedness of the model's coordinate system. DU is right-handed, like Blender.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum Handedness {
+    /// Unity, Unreal and 3ds Max among others.
+    Left,
+    #[default]
+    Right,
+}
+
+/// Copies of the model laid out on a grid, centred on the original.
+#[derive(Debug, Clone, Copy)]
+pub struct Tiling {
+    pub counts: Vector<u32>,
+    /// Distance between neighbouring copies, in voxels.
+    pub spacing: i32,
+}
+
+impl Tiling {
+    /// The offset of every copy, in voxels.
+    pub fn offsets(&self) -> Vec<Vector<i32>> {
+        let axis = |count: u32| {
+            let start = (count.max(1) as i32 - 1) * self.spacing / 2;
+            Vec::from_iter((0..count as i32).map(move |i| i * self.spacing - start))
+        };
+        let mut offsets = Vec::new();
+        for x in axis(self.counts.x) {
+            for y in axis(self.counts.y) {
+                for z in axis(self.counts.z) {
+                    offsets.push(Vector::new(x, y, z));
+                }
+            }
+        }
+        offsets
+    }
+}
+
+pub struct GenerateOptions {
+    pub size: CoreSize,
+    pub core_type: CoreType,
+    pub material: u64,
+    /// Material for solid voxels enclosed on all 6 sides, instead of `material`.
+    pub interior_material: Option<u64>,
+    pub scale: ScaleMode,
+    pub anchor: Anchor,
+    /// Only keep a shell this many voxels thick.
+    pub hollow: Option<u32>,
+    /// Keep a lattice of inside voxels when hollowing, with planes this many voxels apart.
+    pub lattice: Option<u32>,
+    /// Remove everything outside of this range, in voxels from the core's minimum corner.
+    pub crop: Option<RangeZYX>,
+    /// Fill regions entirely inside the mesh with a single coarse cell.
+    pub collapse_solid: bool,
+    /// Move surface vertices onto the mesh surface for smoother curves.
+    pub smooth: bool,
+    /// How to decide which voxels are inside the mesh.
+    pub fill_mode: FillMode,
+    /// Points tested along each axis of a surface voxel, see `Voxelizer::with_samples`.
+    pub samples: u32,
+    pub sample_rule: SampleRule,
+    /// Mirror the model across the middle of the core, perpendicular to this axis. This happens
+    /// before `origin` moves the model. Along `VERTICAL_AXIS` it needs `Anchor::Center`, since
+    /// anchoring moves the model off the middle.
+    pub mirror: Option<Axis>,
+    /// The model is symmetric across the middle of the core, perpendicular to this axis. Only the
+    /// positive half is voxelized, then mirrored onto the other, which takes about half the time.
+    /// Like `mirror`, along `VERTICAL_AXIS` it needs `Anchor::Center`.
+    pub symmetry: Option<Axis>,
+    /// Move the model this many voxels within the core, so parts generated separately line up
+    /// when placed at the same position. Regions collapsed by `collapse_solid` have no finest LOD,
+    /// so they aren't moved.
+    pub origin: Option<Vector<i32>>,
+    /// Repeat the model on a grid. Regions collapsed by `collapse_solid` have no finest LOD, so
+    /// they aren't repeated.
+    pub tile: Option<Tiling>,
+    /// Stop early when this is set, see `Voxelizer::with_cancel`.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Reuse the voxels from this file if they were made from the same mesh and settings, and
+    /// write them there otherwise.
+    pub cache: Option<PathBuf>,
+}
+
+/// The name of a value on the command line, e.g. `xl` for `CoreSize::XL`.
+pub fn value_name(value: &impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map_or(String::new(), |v| v.get_name().to_string())
+}
+
+impl GenerateOptions {
+    /// The settings that decide what gets generated, for recording how a blueprint was made.
+    /// `cancel` and `cache` only change how long it takes, so they're left out.
+    pub fn to_json(&self) -> Value {
+        let scale = match self.scale {
+            ScaleMode::Auto { pad, margin } => json!({ "auto": { "pad": pad, "margin": margin } }),
+            ScaleMode::Uniform(factor) => json!(factor),
+            ScaleMode::PerAxis(factors) => json!(factors.as_slice()),
+        };
+        json!({
+            "size": value_name(&self.size),
+            "core_type": value_name(&self.core_type),
+            "material": self.material,
+            "interior_material": self.interior_material,
+            "scale": scale,
+            "anchor": value_name(&self.anchor),
+            "hollow": self.hollow,
+            "lattice": self.lattice,
+            "crop": self.crop.map(|crop| json!({
+                "origin": crop.origin.coords.as_slice(),
+                "size": crop.size.as_slice(),
+            })),
+            "collapse_solid": self.collapse_solid,
+            "smooth": self.smooth,
+            "fill_mode": value_name(&self.fill_mode),
+            "samples": self.samples,
+            "sample_rule": value_name(&self.sample_rule),
+            "mirror": self.mirror.as_ref().map(value_name),
+            "symmetry": self.symmetry.as_ref().map(value_name),
+            "origin": self.origin.map(|origin| json!(origin.as_slice())),
+            "tile": self.tile.as_ref().map(|tile| json!({
+                "counts": tile.counts.as_slice(),
+                "spacing": tile.spacing,
+            })),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum GenerateError {
+    /// The mesh has no extent on any axis, so there is nothing to scale.
+    DegenerateMesh,
+    TooManyMaterials(TooManyMaterials),
+    /// `GenerateOptions::cancel` was set before voxelizing finished.
+    Cancelled,
+    Cache(CacheError),
+    Svo(SvoError),
+    /// Moving the model by `origin` takes some of it outside a core this many voxels across.
+    OriginOutsideCore {
+        origin: Vector<i32>,
+        voxels: usize,
+    },
+    /// The pad leaves no room for the model in a core this many voxels across.
+    PadTooLarge {
+        pad: u32,
+        voxels: usize,
+    },
+    /// The auto scale margin isn't a fraction in [0, 1).
+    MarginOutOfRange(f64),
+    /// The model is anchored off the middle of the core, but mirrored across it along this axis.
+    AnchoredMirror(Axis),
+}
+
+impl std::fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenerateError::DegenerateMesh => write!(f, "mesh is degenerate on every axis"),
+            GenerateError::TooManyMaterials(e) => write!(f, "{}", e),
+            GenerateError::Cancelled => write!(f, "cancelled"),
+            GenerateError::Cache(e) => write!(f, "{}", e),
+            GenerateError::Svo(e) => write!(f, "{}", e),
+            GenerateError::OriginOutsideCore { origin, voxels } => write!(
+                f,
+                "moving the model by {}, {}, {} voxels takes it outside the {} voxel core",
+                origin.x, origin.y, origin.z, voxels
+            ),
+            GenerateError::PadTooLarge { pad, voxels } => write!(
+                f,
+                "a pad of {} voxels leaves no room in a core {} voxels across",
+                pad, voxels
+            ),
+            GenerateError::MarginOutOfRange(margin) => write!(
+                f,
+                "a margin of {} isn't a fraction of the core from 0 up to 1",
+                margin
+            ),
+            GenerateError::AnchoredMirror(axis) => write!(
+                f,
+                "an anchored model isn't in the middle of the core, so it can't be mirrored \
+                 across the middle along {}",
+                value_name(axis)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+impl From<TooManyMaterials> for GenerateError {
+    fn from(value: TooManyMaterials) -> Self {
+        GenerateError::TooManyMaterials(value)
+    }
+}
+
+impl From<VoxelizeError> for GenerateError {
+    fn from(value: VoxelizeError) -> Self {
+        match value {
+            VoxelizeError::TooManyMaterials(e) => GenerateError::TooManyMaterials(e),
+            VoxelizeError::Svo(e) => GenerateError::Svo(e),
+        }
+    }
+}
+
+impl From<CacheError> for GenerateError {
+    fn from(value: CacheError) -> Self {
+        GenerateError::Cache(value)
+    }
+}
+
+/// The region of model space that gets voxelized into a core of the given LOD height.
+pub fn voxelization_aabb(
+    mesh_aabb: &Aabb,
+    height: usize,
+    scale: ScaleMode,
+) -> Result<Aabb, GenerateError> {
+    let extents = Vector::repeat(4.0 * (1 << height) as f64);
+    let aabb = match scale {
+        ScaleMode::Auto { pad, margin } => {
+            // A cube around the longest axis, grown so the pad fits on both sides. This is built
+            // directly rather than by scaling the mesh bounds, since flat meshes have a zero
+            // extent on some axis.
+            let max_extent = mesh_aabb.extents().max();
+            if max_extent <= 0.0 {
+                return Err(GenerateError::DegenerateMesh);
+            }
+            let factor = match margin {
+                Some(margin) => {
+                    if !(0.0..1.0).contains(&margin) {
+                        return Err(GenerateError::MarginOutOfRange(margin));
+                    }
+                    1.0 / (1.0 - margin)
+                }
+                None => {
+                    let voxels = DEFAULT_LEAF_SIZE << height;
+                    let pad_voxels = pad.map_or(voxels / 4, |pad| pad as usize);
+                    if 2 * pad_voxels >= voxels {
+                        return Err(GenerateError::PadTooLarge {
+                            pad: pad_voxels as u32,
+                            voxels,
+                        });
+                    }
+                    voxels as f64 / (voxels - 2 * pad_voxels) as f64
+                }
+            };
+            Aabb::from_half_extents(
+                mesh_aabb.center(),
+                Vector::repeat(max_extent * factor / 2.0),
+            )
+        }
+        ScaleMode::Uniform(factor) => Aabb::from_half_extents(mesh_aabb.center(), extents / factor),
+        ScaleMode::PerAxis(factors) => {
+            Aabb::from_half_extents(mesh_aabb.center(), extents.component_div(&factors))
+        }
+    };
+    Ok(aabb)
+}
+
+/// The smallest core whose bounds hold the mesh at the given scale. This is only a bounds check,
+/// nothing is voxelized or counted. With `ScaleMode::Auto` the mesh is scaled to fit any core,
+/// so this is the smallest size with room for the pad.
+pub fn smallest_core_size(mesh_aabb: &Aabb, scale: ScaleMode) -> Option<CoreSize> {
+    CoreSize::value_variants().iter().copied().find(|size| {
+        voxelization_aabb(mesh_aabb, size.height() - 3, scale).is_ok_and(|aabb| {
+            let extents = aabb.extents() - mesh_aabb.extents();
+            extents.min() >= 0.0
+        })
+    })
+}
+
+// Copies the finest LOD to every offset, in voxels, and builds the coarser LODs from the copies.
+// Only one copy of the model is ever voxelized. Where copies overlap, the first one placed is
+// kept.
+fn copy_svo(
+    svo: Svo<Option<VoxelCellData>>,
+    offsets: &[Vector<i32>],
+    height: usize,
+) -> Svo<Option<VoxelCellData>> {
+    copy_voxels(&svo, offsets, height).unwrap_or(svo)
+}
+
+// Like `copy_svo`, into a new SVO of the given LOD height. Voxels that land outside of it only
+// go into the padding around its edge cells, if at all. Returns `None` if `svo` has no cells.
+fn copy_voxels(
+    svo: &Svo<Option<VoxelCellData>>,
+    offsets: &[Vector<i32>],
+    height: usize,
+) -> Option<Svo<Option<VoxelCellData>>> {
+    let mut mapping = None;
+    svo.cata(|_, v, _| {
+        if let Some(cell_data) = v {
+            mapping.get_or_insert_with(|| cell_data.mapping().clone());
+        }
+    });
+    let mapping = mapping?;
+
+    let mut importer = JSONImporter;
+    let mut tiled =
+        importer.create_empty_lods(Point::origin(), height, DEFAULT_LEAF_SIZE, &mapping);
+    // Setting a material resets the vertices around it, so every material goes in before any
+    // of the vertices.
+    svo.cata(|range, v, cs| {
+        let Some(cell_data) = v else { return };
+        if cs.is_some() || range.size.x != 1 {
+            return;
+        }
+        cell_data.grid.for_each_material(|pos, material| {
+            for offset in offsets {
+                importer.union_material_at_all_lods(
+                    &mut tiled,
+                    pos + offset,
+                    material,
+                    DEFAULT_VERTEX_OFFSET,
+                    height,
+                );
+            }
+        });
+    });
+    svo.cata(|range, v, cs| {
+        let Some(cell_data) = v else { return };
+        if cs.is_some() || range.size.x != 1 {
+            return;
+        }
+        cell_data.grid.for_each_voxel(|pos, voxel| {
+            for offset in offsets {
+                importer.union_vertex_offset_at_all_lods(
+                    &mut tiled,
+                    pos + offset,
+                    Point::from(voxel),
+                    height,
+                );
+            }
+        });
+    });
+    Some(importer.finish_svo(tiled))
+}
+
+// The smallest and largest material vertex of the finest LOD, if there are any voxels.
+fn material_bounds(svo: &Svo<Option<VoxelCellData>>) -> Option<(Point<i32>, Point<i32>)> {
+    let mut bounds: Option<(Point<i32>, Point<i32>)> = None;
+    svo.cata(|range, v, cs| {
+        let Some(cell_data) = v else { return };
+        if cs.is_some() || range.size.x != 1 {
+            return;
+        }
+        cell_data.grid.for_each_material(|pos, _| {
+            bounds = Some(match bounds {
+                Some((mins, maxs)) => (mins.inf(&pos), maxs.sup(&pos)),
+                None => (pos, pos),
+            });
+        });
+    });
+    bounds
+}
+
+/// Voxelizes a mesh as if its bounds were `mesh_aabb`. Meshes voxelized with the same bounds
+/// line up voxel for voxel, even if they have different extents. `options.core_type` isn't used.
+pub fn voxelize_mesh(
+    mesh: TriMesh,
+    parts: Vec<MeshPart>,
+    isometry: Isometry<f64>,
+    mesh_aabb: &Aabb,
+    options: &GenerateOptions,
+) -> Result<Svo<Option<VoxelCellData>>, GenerateError> {
+    let height = options.size.height() - 3;
+    // Mirroring is always across the middle of the core, which is only where the model is if it
+    // isn't anchored.
+    let mirrors_vertically = [options.mirror, options.symmetry].contains(&Some(VERTICAL_AXIS));
+    if options.anchor != Anchor::Center && mirrors_vertically {
+        return Err(GenerateError::AnchoredMirror(VERTICAL_AXIS));
+    }
+    let aabb = voxelization_aabb(mesh_aabb, height, options.scale)?;
+    let aabb = options.anchor.apply(aabb, mesh_aabb);
+    let cache = options.cache.as_ref().map(|path| {
+        let fingerprint = fingerprint(&mesh, &parts, &isometry, &aabb, height, options);
+        (path, fingerprint)
+    });
+    let has_parts = !parts.is_empty();
+
+    let voxelizer = Voxelizer::new(isometry, mesh)
+        .with_hollow(options.hollow)
+        .with_lattice(options.lattice)
+        .with_interior_material(options.interior_material)
+        .with_collapse_solid(options.collapse_solid)
+        .with_smooth(options.smooth)
+        .with_fill_mode(options.fill_mode)
+        .with_samples(options.samples, options.sample_rule)
+        .with_cancel(options.cancel.clone())
+        .with_parts(parts)
+        .with_half_space(options.symmetry);
+    let cached = match cache {
+        Some((path, fingerprint)) => {
+            let cached = read_cache(path, fingerprint)?;
+            if cached.is_some() {
+                info!("Reusing the voxels cached in {}", path.display());
+            }
+            cached
+        }
+        None => None,
+    };
+    let mut svo = match cached {
+        Some(mut svo) => {
+            // Without parts the fingerprint leaves out the materials, so apply the current ones.
+            if !has_parts {
+                let mapping = Arc::new(create_material_mapper(
+                    options.material,
+                    options.interior_material,
+                ));
+                svo.for_each_mut(|_, cell_data| {
+                    if let Some(cell_data) = cell_data {
+                        cell_data.remap_materials(mapping.clone(), &HashMap::new());
+                    }
+                });
+            }
+            svo
+        }
+        None => {
+            let svo = voxelizer.create_lods(&aabb, Point::origin(), height, options.material)?;
+            if voxelizer.is_cancelled() {
+                return Err(GenerateError::Cancelled);
+            }
+            if let Some((path, fingerprint)) = cache {
+                write_cache(path, fingerprint, &svo)?;
+            }
+            svo
+        }
+    };
+    if let Some(axis) = options.symmetry {
+        // The halves don't share any voxels, only the root LOD, which was built in full.
+        let mirrored = svo.mirrored(axis, DEFAULT_LEAF_SIZE);
+        svo = svo
+            .combine(mirrored, CombineOp::Union)
+            .expect("mirroring keeps the range and materials");
+    }
+    if let Some(axis) = options.mirror {
+        // The union keeps the original where both halves overlap, so voxels along the plane
+        // aren't doubled up.
+        let mirrored = svo.mirrored(axis, DEFAULT_LEAF_SIZE);
+        svo = svo
+            .combine(mirrored, CombineOp::Union)
+            .expect("mirroring keeps the range and materials");
+    }
+    if let Some(origin) = options.origin {
+        // Materials are on the +[1, 1, 1] vertex, so they go from 1 to the core width.
+        let voxels = options.size.voxels() as i32;
+        if let Some((mins, maxs)) = material_bounds(&svo) {
+            if (mins + origin).coords.min() < 1 || (maxs + origin).coords.max() > voxels {
+                return Err(GenerateError::OriginOutsideCore {
+                    origin,
+                    voxels: voxels as usize,
+                });
+            }
+        }
+        svo = copy_svo(svo, &[origin], options.size.height());
+    }
+    if let Some(tiling) = &options.tile {
+        svo = copy_svo(svo, &tiling.offsets(), options.size.height());
+    }
+    if let Some(bounds) = &options.crop {
+        svo = svo.crop(bounds, DEFAULT_LEAF_SIZE);
+    }
+    Ok(svo)
+}
+
+/// One core's worth of a model split across several cores.
+pub struct SplitPiece {
+    /// Where the core goes, in cores from the piece with the smallest coordinates. Placing each
+    /// piece this many core widths from the first lines the pieces up.
+    pub position: Vector<i32>,
+    pub blueprint: Blueprint,
+}
+
+/// Voxelizes a mesh that is too large for one core into a grid of cores, centred on the mesh.
+/// Each core covers the same region a single core would at this scale, and cores the mesh
+/// doesn't touch are left out. `options.mirror`, `options.symmetry`, `options.origin`,
+/// `options.tile` and `options.crop` aren't used, since they apply to a whole core. The mesh is
+/// voxelized once and the finest LOD is cut up into the cores, so regions collapsed by
+/// `collapse_solid`, which have no finest LOD, are left out.
+pub fn generate_split_blueprints(
+    name: String,
+    mesh: TriMesh,
+    parts: Vec<MeshPart>,
+    isometry: Isometry<f64>,
+    options: &GenerateOptions,
+) -> Result<Vec<SplitPiece>, GenerateError> {
+    let height = options.size.height() - 3;
+    let mesh_aabb = mesh.aabb(&isometry);
+    let core_extents = voxelization_aabb(&mesh_aabb, height, options.scale)?.extents();
+    let counts = mesh_aabb
+        .extents()
+        .component_div(&core_extents)
+        .map(|v| (v.ceil() as i32).max(1));
+    let mins = mesh_aabb.center() - counts.cast::<f64>().component_mul(&core_extents) / 2.0;
+
+    let voxelizer = Voxelizer::new(isometry, mesh)
+        .with_hollow(options.hollow)
+        .with_lattice(options.lattice)
+        .with_interior_material(options.interior_material)
+        .with_collapse_solid(options.collapse_solid)
+        .with_smooth(options.smooth)
+        .with_fill_mode(options.fill_mode)
+        .with_samples(options.samples, options.sample_rule)
+        .with_cancel(options.cancel.clone())
+        .with_parts(parts);
+    // The whole grid is voxelized in one go, as a cube of cores with a power of two side, so the
+    // pieces agree with each other at the seams.
+    let cores = (counts.max() as u32).next_power_of_two();
+    let aabb = Aabb::new(mins, mins + core_extents * cores as f64);
+    let grid_height = height + cores.trailing_zeros() as usize;
+    let svo = voxelizer.create_lods(&aabb, Point::origin(), grid_height, options.material)?;
+    if voxelizer.is_cancelled() {
+        return Err(GenerateError::Cancelled);
+    }
+
+    let core_voxels = options.size.voxels() as i32;
+    let mut pieces = Vec::new();
+    for x in 0..counts.x {
+        for y in 0..counts.y {
+            for z in 0..counts.z {
+                let position = Vector::new(x, y, z);
+                // Every piece is a core of its own, so its voxels start from the origin.
+                let offset = -position * core_voxels;
+                let Some(svo) = copy_voxels(&svo, &[offset], options.size.height()) else {
+                    continue;
+                };
+                if svo.voxel_counts().cells == 0 {
+                    continue;
+                }
+                let blueprint = Blueprint::new(
+                    format!("{}_{}_{}_{}", name, x, y, z),
+                    CoreInfo::from(options.size, options.core_type),
+                    options.material,
+                    svo,
+                );
+                pieces.push(SplitPiece {
+                    position,
+                    blueprint,
+                });
+            }
+        }
+    }
+    Ok(pieces)
+}
+
+/// Voxelizes a mesh into a blueprint. The mesh should already have its topology flags set.
+/// `parts` optionally gives parts of the mesh their own materials, see `Voxelizer::with_parts`.
+pub fn generate_blueprint(
+    name: String,
+    mesh: TriMesh,
+    parts: Vec<MeshPart>,
+    isometry: Isometry<f64>,
+    options: &GenerateOptions,
+) -> Result<Blueprint, GenerateError> {
+    let mesh_aabb = mesh.aabb(&isometry);
+    let svo = voxelize_mesh(mesh, parts, isometry, &mesh_aabb, options)?;
+    Ok(Blueprint::new(
+        name,
+        CoreInfo::from(options.size, options.core_type),
+        options.material,
+        svo,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parry3d_f64::shape::Cuboid;
+
+    #[test]
+    fn bottom_anchor_puts_the_mesh_on_the_floor() {
+        let (vertices, indices) = Cuboid::new(Vector::new(0.4, 0.4, 0.2)).to_trimesh();
+        let mesh = TriMesh::new(vertices, indices);
+        let isometry = Isometry::translation(0.0, 0.0, 3.0);
+        let mesh_aabb = mesh.aabb(&isometry);
+        let scale = ScaleMode::Auto {
+            pad: None,
+            margin: None,
+        };
+        let aabb = voxelization_aabb(&mesh_aabb, 0, scale).unwrap();
+        let aabb = Anchor::Bottom.apply(aabb, &mesh_aabb);
+        let axis = VERTICAL_AXIS as usize;
+        assert!((aabb.mins[axis] - mesh_aabb.mins[axis]).abs() < 1e-9);
+
+        let svo = Voxelizer::new(isometry, mesh)
+            .with_fill_mode(FillMode::Winding)
+            .create_lods(&aabb, Point::origin(), 0, 1971262921)
+            .unwrap();
+        // The lowest layer of voxels is the floor of the core.
+        let occupancy = svo.occupancy();
+        assert_eq!(occupancy.layers[axis].keys().next(), Some(&0));
+    }
+}
@@ -1,32 +1,63 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+use anyhow::{anyhow, bail, Context, Result};
 use base64::Engine;
-use parry3d_f64::bounding_volume::Aabb;
-use parry3d_f64::math::{Isometry, Point, Vector};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{info, warn, LevelFilter};
+use parry3d_f64::bounding_volume::{Aabb, BoundingVolume};
+use parry3d_f64::math::{Isometry, Point, Translation, Vector};
+use parry3d_f64::na::{Quaternion, UnitQuaternion};
 use parry3d_f64::shape::{TriMesh, TriMeshFlags};
-use squarion::{AggregateMetadata, Deserialize, VoxelCellData};
-use tobj::LoadOptions;
-use serde_json::Value;
+use serde_json::{json, Value};
 
-mod blueprint;
-mod squarion;
-mod svo;
-mod voxelization;
-mod import;
+use du_blueprint::binvox::BinvoxFile;
+use du_blueprint::blueprint::*;
+use du_blueprint::import::{JSONImporter, DEFAULT_LEAF_SIZE};
+use du_blueprint::mesh_export::{write_boxes_obj, write_obj};
+use du_blueprint::mesh_import::{
+    is_flat, load_mesh, load_obj_parts_from_reader, load_obj_parts_with_colors, load_ply,
+    merge_parts, mirror_mesh, misoriented_triangle_count, open_edge_count, weld_vertices,
+    Triangulation,
+};
+use du_blueprint::points::PointCloud;
+use du_blueprint::primitive::{Primitive, PrimitiveShape};
+use du_blueprint::squarion::{AggregateMetadata, Deserialize, RangeZYX, Serialize, VoxelCellData};
+use du_blueprint::svo::{Axis, CombineOp, Svo};
+use du_blueprint::vox::VoxFile;
+use du_blueprint::voxelization::{FillMode, MeshPart, SampleRule, Voxelizer};
+use du_blueprint::{
+    generate_blueprint, generate_split_blueprints, smallest_core_size, value_name,
+    voxelization_aabb, voxelize_mesh, Anchor, GenerateOptions, Handedness, ScaleMode, Tiling,
+    UpAxis,
+};
 
-use crate::blueprint::*;
-use crate::voxelization::*;
-use crate::import::JSONImporter;
+// The LOD height previews are voxelized at, so a preview has at most 16 chunks on each axis.
+const PREVIEW_HEIGHT: usize = 4;
 
-use clap::{Args, Parser, Subcommand};
+use clap::builder::{PossibleValue, TypedValueParser};
+use clap::error::ErrorKind;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Show more detail, repeat for even more
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Only show errors and warnings
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
 }
 
 #[derive(Args)]
@@ -38,45 +69,636 @@ struct ScaleInfo {
 
     #[arg(long, default_value_t = 1.0)]
     scale: f64,
+
+    /// Non-uniform scale, as x,y,z
+    #[arg(long, value_parser = parse_vector)]
+    scale_xyz: Option<Vector<f64>>,
+}
+
+impl ScaleInfo {
+    fn mode(&self, pad: Option<u32>, margin: Option<f64>) -> ScaleMode {
+        if self.auto {
+            ScaleMode::Auto { pad, margin }
+        } else if let Some(factors) = self.scale_xyz {
+            ScaleMode::PerAxis(factors)
+        } else {
+            ScaleMode::Uniform(self.scale)
+        }
+    }
+}
+
+fn parse_vector(value: &str) -> Result<Vector<f64>, String> {
+    let components = value
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if components.len() != 3 {
+        return Err(format!(
+            "expected x,y,z but got {} values",
+            components.len()
+        ));
+    }
+    Ok(Vector::from_row_slice(&components))
+}
+
+fn parse_dimensions(value: &str) -> Result<Vector<f64>, String> {
+    let dimensions = match value.trim().parse::<f64>() {
+        Ok(size) => Vector::repeat(size),
+        Err(_) => parse_vector(value)?,
+    };
+    if dimensions.min() <= 0.0 {
+        return Err("every dimension must be positive".to_string());
+    }
+    Ok(dimensions)
+}
+
+fn parse_quaternion(value: &str) -> Result<UnitQuaternion<f64>, String> {
+    let components = value
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if components.len() != 4 {
+        return Err(format!(
+            "expected x,y,z,w but got {} values",
+            components.len()
+        ));
+    }
+    let quaternion = Quaternion::new(components[3], components[0], components[1], components[2]);
+    // Small errors are normal from rounding, anything more is probably the wrong order or not a
+    // rotation at all.
+    if (quaternion.norm() - 1.0).abs() > 0.01 {
+        return Err(format!(
+            "expected a unit quaternion but the magnitude is {}",
+            quaternion.norm()
+        ));
+    }
+    Ok(UnitQuaternion::from_quaternion(quaternion))
+}
+
+fn parse_position(value: &str) -> Result<Point<i32>, String> {
+    let components = value
+        .split(',')
+        .map(|v| v.trim().parse::<i32>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if components.len() != 3 {
+        return Err(format!(
+            "expected x,y,z but got {} values",
+            components.len()
+        ));
+    }
+    Ok(Point::new(components[0], components[1], components[2]))
+}
+
+fn parse_bounds(value: &str) -> Result<RangeZYX, String> {
+    let components = value
+        .split(',')
+        .map(|v| v.trim().parse::<i32>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if components.len() != 6 {
+        return Err(format!(
+            "expected minx,miny,minz,maxx,maxy,maxz but got {} values",
+            components.len()
+        ));
+    }
+    let mins = Point::new(components[0], components[1], components[2]);
+    let maxs = Point::new(components[3], components[4], components[5]);
+    if (maxs - mins).min() < 0 {
+        return Err("the maximum must not be less than the minimum".into());
+    }
+    Ok(RangeZYX::with_extents(mins, maxs - mins))
+}
+
+fn parse_tiling(value: &str) -> Result<Tiling, String> {
+    let components = value
+        .split(',')
+        .map(|v| v.trim().parse::<i32>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if components.len() != 4 {
+        return Err(format!(
+            "expected nx,ny,nz,spacing but got {} values",
+            components.len()
+        ));
+    }
+    if components[..3].iter().any(|n| *n < 1) {
+        return Err("there must be at least one copy on each axis".into());
+    }
+    Ok(Tiling {
+        counts: Vector::new(components[0], components[1], components[2]).map(|n| n as u32),
+        spacing: components[3],
+    })
+}
+
+/// Parses a core type or size by its name, or by the number the game uses for it, so scripts
+/// working from game data don't need to know the names.
+#[derive(Clone)]
+struct GameIdParser<T> {
+    from_id: fn(u64) -> Option<T>,
+    id: fn(&T) -> u64,
+}
+
+fn core_type_parser() -> GameIdParser<CoreType> {
+    GameIdParser {
+        from_id: |id| CoreType::from_kind(id.try_into().ok()?),
+        id: |core_type| core_type.kind() as u64,
+    }
+}
+
+fn core_size_parser() -> GameIdParser<CoreSize> {
+    GameIdParser {
+        from_id: |id| CoreSize::from_size(id.try_into().ok()?),
+        id: |size| size.size() as u64,
+    }
+}
+
+impl<T: ValueEnum + Clone + Send + Sync + 'static> TypedValueParser for GameIdParser<T> {
+    type Value = T;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &OsStr,
+    ) -> Result<T, clap::Error> {
+        let value = value.to_string_lossy();
+        if let Ok(parsed) = T::from_str(&value, true) {
+            return Ok(parsed);
+        }
+        if let Some(parsed) = value.trim().parse().ok().and_then(self.from_id) {
+            return Ok(parsed);
+        }
+        let valid = Vec::from_iter(T::value_variants().iter().filter_map(|variant| {
+            let name = variant.to_possible_value()?;
+            Some(format!("{} ({})", name.get_name(), (self.id)(variant)))
+        }));
+        let arg = arg.map_or("...".to_string(), |arg| arg.to_string());
+        Err(clap::Error::raw(
+            ErrorKind::InvalidValue,
+            format!(
+                "invalid value '{}' for '{}', expected one of {}\n",
+                value,
+                arg,
+                valid.join(", ")
+            ),
+        )
+        .with_cmd(cmd))
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(
+            T::value_variants()
+                .iter()
+                .filter_map(|variant| variant.to_possible_value()),
+        ))
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Generate a blueprint file from an obj file.
+    /// Generate a blueprint file from a mesh file.
     Generate {
-        /// Input obj file name
-        input: PathBuf,
+        /// Input mesh file names (.obj, .stl, .ply, .off, .3mf, .gltf or .glb), or - to read an
+        /// OBJ from stdin. Several inputs are combined into one model
+        #[arg(required = true, num_args = 1..)]
+        inputs: Vec<PathBuf>,
 
-        /// Output blueprint file name
+        /// Output blueprint file name, or - for stdout
         output: PathBuf,
 
-        #[arg(short, long, value_enum)]
+        /// Construct name shown in game. Defaults to the input file name
+        #[arg(long)]
+        name: Option<String>,
+
+        #[arg(short, long, value_parser = core_type_parser())]
         r#type: CoreType,
 
-        #[arg(short, long, value_enum)]
-        size: CoreSize,
+        #[arg(short, long, value_parser = core_size_parser(), required_unless_present = "fit_size")]
+        size: Option<CoreSize>,
 
-        /// Voxel material ID
-        #[arg(short, long, default_value_t = 1971262921)]
-        material: u64,
+        /// Use the smallest core size whose bounds hold the model at the given scale. Only the
+        /// bounds are checked, not how many voxels the model turns into
+        #[arg(long, conflicts_with_all = ["size", "auto"])]
+        fit_size: bool,
+
+        /// Voxel material ID. Repeat it once per input to give each input its own material, in
+        /// the same order; the first is used for the rest of the options
+        #[arg(
+            short,
+            long,
+            visible_alias = "surface-material",
+            default_values_t = [1971262921]
+        )]
+        material: Vec<u64>,
+
+        /// Material ID for solid voxels enclosed on all 6 sides by other solid voxels. Voxels with
+        /// an empty neighbour, including the inside wall of a --hollow shell, keep --material
+        #[arg(long)]
+        interior_material: Option<u64>,
+
+        /// JSON file mapping OBJ material names to voxel material IDs. Parts with an unmapped
+        /// material use --material.
+        #[arg(long)]
+        material_map: Option<PathBuf>,
+
+        /// JSON file mapping "#rrggbb" colors to voxel material IDs. Each face of a PLY file with
+        /// vertex colors gets the material of the nearest color, and so does each OBJ material
+        /// with a diffuse color. Anything without a color uses --material.
+        #[arg(long, conflicts_with = "material_map")]
+        color_palette: Option<PathBuf>,
+
+        /// Voxel material ID for translucent colors, such as glass. With --color-palette, faces
+        /// and OBJ materials whose alpha is below --alpha-threshold get this material instead of
+        /// the nearest palette color.
+        #[arg(long, requires = "color_palette")]
+        glass_material: Option<u64>,
+
+        /// Alpha (0-255) below which a color counts as translucent
+        #[arg(long, default_value_t = 128, requires = "glass_material")]
+        alpha_threshold: u8,
+
+        #[command(flatten)]
+        scale: ScaleInfo,
+
+        /// With --auto, the number of empty voxels left between the model and the sides of the
+        /// core. By default the model fills half the core
+        #[arg(long, requires = "auto")]
+        pad: Option<u32>,
+
+        /// With --auto, the fraction of the core left empty around the model, split between both
+        /// sides of its longest axis, e.g. 0.05. Fills the core like --pad, but the border grows
+        /// with the core size
+        #[arg(long, requires = "auto", conflicts_with = "pad")]
+        auto_margin: Option<f64>,
+
+        /// Where the model sits along the core's vertical axis, z
+        #[arg(long, value_enum, default_value_t = Anchor::Center, conflicts_with = "split")]
+        anchor: Anchor,
+
+        /// The up axis of the model. Y-up models are stood upright before any --rotate
+        #[arg(long, value_enum, default_value_t = UpAxis::Z)]
+        up: UpAxis,
+
+        /// The handedness of the model. Left-handed models are mirrored along their forward axis
+        #[arg(long, value_enum, default_value_t = Handedness::Right)]
+        handedness: Handedness,
+
+        /// Rotation applied to the model, as x,y,z Euler angles in degrees
+        #[arg(long, value_parser = parse_vector, allow_hyphen_values = true)]
+        rotate: Option<Vector<f64>>,
+
+        /// Rotation applied to the model, as an x,y,z,w unit quaternion
+        #[arg(
+            long,
+            value_parser = parse_quaternion,
+            allow_hyphen_values = true,
+            conflicts_with = "rotate"
+        )]
+        quat: Option<UnitQuaternion<f64>>,
+
+        /// Translation applied to the model after rotating, as x,y,z
+        #[arg(long, value_parser = parse_vector, allow_hyphen_values = true)]
+        translate: Option<Vector<f64>>,
+
+        /// Only keep a shell this many voxels thick. Models thinner than twice the thickness
+        /// are left solid.
+        #[arg(long)]
+        hollow: Option<u32>,
+
+        /// When hollowing, keep the inside voxels on planes this many voxels apart along each
+        /// axis, as a lattice that braces the shell
+        #[arg(long, requires = "hollow", value_parser = clap::value_parser!(u32).range(1..))]
+        lattice: Option<u32>,
+
+        /// Fill regions entirely inside the model with a single coarse cell instead of leaving
+        /// them empty
+        #[arg(long)]
+        collapse_solid: bool,
+
+        /// Move surface vertices onto the model surface, for smoother curves but softer edges
+        #[arg(long)]
+        smooth: bool,
+
+        /// How to decide which voxels are inside the model. winding is much slower, but copes
+        /// with holes and non-manifold meshes such as scans
+        #[arg(long, value_enum, default_value_t = FillMode::Oriented)]
+        fill_mode: FillMode,
+
+        /// Test this many points along each axis of a surface voxel, instead of only its centre,
+        /// so walls thinner than a voxel don't vanish. Costs about samples^3 times as long
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=8))]
+        samples: u32,
+
+        /// How a supersampled voxel decides if it's solid. any keeps the thinnest features,
+        /// majority keeps the surface where it is
+        #[arg(long, value_enum, default_value_t = SampleRule::Majority)]
+        sample_rule: SampleRule,
+
+        /// Voxelize the model even if it isn't closed. The inside of an open mesh is guesswork,
+        /// so expect stray or missing voxels
+        #[arg(long)]
+        allow_open: bool,
+
+        /// Merge vertices closer together than this, in model units, to close the tiny cracks
+        /// CAD exports leave between faces. 0 leaves the vertices as loaded
+        #[arg(long, default_value_t = 0.0)]
+        weld_tolerance: f64,
+
+        /// Split OBJ faces with more than three corners by ear clipping instead of as a fan.
+        /// Slower, but large concave faces such as floor plans don't leave holes
+        #[arg(long)]
+        robust_triangulation: bool,
+
+        /// Fail on meshes that can't be consistently oriented, instead of voxelizing them as is,
+        /// and on models that come out with no voxels at all
+        #[arg(long)]
+        strict: bool,
+
+        /// Mirror the model across the middle of the core, perpendicular to this axis. z can't be
+        /// combined with --anchor bottom or top
+        #[arg(long, value_enum)]
+        mirror: Option<Axis>,
+
+        /// The model is symmetric across the middle of the core, perpendicular to this axis. Only
+        /// the positive half is voxelized and then mirrored, which takes about half the time. z
+        /// can't be combined with --anchor bottom or top
+        #[arg(long, value_enum)]
+        symmetry: Option<Axis>,
+
+        /// Move the voxelized model x,y,z voxels (0.25m) within the core, so parts generated
+        /// separately line up when placed at the same position. Fails if it moves any voxels out
+        /// of the core
+        #[arg(long, value_parser = parse_position, allow_hyphen_values = true)]
+        origin: Option<Point<i32>>,
+
+        /// Repeat the model nx,ny,nz times, with copies spacing voxels apart. The grid is centred
+        /// on the model, and where copies overlap the voxels are merged.
+        #[arg(long, value_parser = parse_tiling, conflicts_with = "collapse_solid")]
+        tile: Option<Tiling>,
+
+        /// Remove everything outside of minx,miny,minz,maxx,maxy,maxz, in voxels (0.25m) from
+        /// the core's minimum corner
+        #[arg(long, value_parser = parse_bounds, allow_hyphen_values = true)]
+        crop: Option<RangeZYX>,
+
+        /// Split a model too large for one core into a grid of cores, written to one blueprint
+        /// each. The core position is added to the output name, e.g. out_0_1_0.blueprint
+        #[arg(long, conflicts_with_all = ["fit_size", "mirror", "symmetry", "origin", "tile", "crop"])]
+        split: bool,
+
+        /// Keep the voxelized model in this file, and reuse it on later runs with the same mesh
+        /// and settings. Changing only --material or --interior-material still hits the cache
+        #[arg(long, conflicts_with = "split")]
+        cache: Option<PathBuf>,
+
+        /// Report the voxel counts without writing the blueprint
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Report how many cells and subtrees are repeated
+        #[arg(long)]
+        analyze: bool,
+
+        /// Warn when the construct has more voxels than this. It's a threshold of your choosing,
+        /// not a limit the game enforces
+        #[arg(long)]
+        voxel_limit: Option<u64>,
+
+        /// Number of threads used to voxelize. Defaults to one per CPU core
+        #[arg(long)]
+        threads: Option<usize>,
+
+        /// Gzip the output. This is automatic when the output name ends in .gz
+        #[arg(long)]
+        gzip: bool,
+
+        /// Also record the input, settings, tool version and voxel count in a .meta.json file
+        /// next to each blueprint, e.g. ship.meta.json for ship.blueprint
+        #[arg(long)]
+        manifest: bool,
+    },
+    /// Quickly voxelize a mesh at a coarse LOD, to check its orientation and fit before a full
+    /// generate.
+    Preview {
+        /// Input mesh file name (.obj, .stl, .ply, .off, .3mf, .gltf or .glb)
+        input: PathBuf,
+
+        #[arg(short, long, value_parser = core_size_parser())]
+        size: CoreSize,
 
         #[command(flatten)]
         scale: ScaleInfo,
+
+        /// The up axis of the model. Y-up models are stood upright before any --rotate
+        #[arg(long, value_enum, default_value_t = UpAxis::Z)]
+        up: UpAxis,
+
+        /// The handedness of the model. Left-handed models are mirrored along their forward axis
+        #[arg(long, value_enum, default_value_t = Handedness::Right)]
+        handedness: Handedness,
+
+        /// Rotation applied to the model, as x,y,z Euler angles in degrees
+        #[arg(long, value_parser = parse_vector, allow_hyphen_values = true)]
+        rotate: Option<Vector<f64>>,
+
+        /// Rotation applied to the model, as an x,y,z,w unit quaternion
+        #[arg(
+            long,
+            value_parser = parse_quaternion,
+            allow_hyphen_values = true,
+            conflicts_with = "rotate"
+        )]
+        quat: Option<UnitQuaternion<f64>>,
+
+        /// Translation applied to the model after rotating, as x,y,z
+        #[arg(long, value_parser = parse_vector, allow_hyphen_values = true)]
+        translate: Option<Vector<f64>>,
+
+        /// Write the occupied chunks to this OBJ file instead of printing them
+        #[arg(long)]
+        obj: Option<PathBuf>,
     },
     // Generate a blueprint file from a JSON of voxels (produced by an external voxelizer)
     GenerateFromJson {
         /// Input JSON file name
         input: PathBuf,
 
-        /// Output blueprint file name
+        /// Output blueprint file name, or - for stdout
         output: PathBuf,
 
+        /// Construct name shown in game. Defaults to the input file name
+        #[arg(long)]
+        name: Option<String>,
+
         /// Core type (e.g., Core or CoreUnit)
-        #[arg(short, long, value_enum)]
+        #[arg(short, long, value_parser = core_type_parser())]
         r#type: CoreType,
 
         /// Core size (e.g., Medium, Large)
+        #[arg(short, long, value_parser = core_size_parser())]
+        size: CoreSize,
+
+        /// Voxel material ID
+        #[arg(short, long, default_value_t = 1971262921)]
+        material: u64,
+
+        /// Report the voxel counts without writing the blueprint
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Report how many cells and subtrees are repeated
+        #[arg(long)]
+        analyze: bool,
+
+        /// Warn when the construct has more voxels than this. It's a threshold of your choosing,
+        /// not a limit the game enforces
+        #[arg(long)]
+        voxel_limit: Option<u64>,
+
+        /// Gzip the output. This is automatic when the output name ends in .gz
+        #[arg(long)]
+        gzip: bool,
+
+        /// Don't add the debug material that game blueprints have at index 1
+        #[arg(long)]
+        no_debug_material: bool,
+
+        /// Width of a cell in voxels, a power of two. The game only loads 32
+        #[arg(long, default_value_t = DEFAULT_LEAF_SIZE)]
+        leaf_size: usize,
+
+        /// JSON file mapping material IDs to short names of up to 8 bytes. Unnamed materials are
+        /// called MatNNNNN.
+        #[arg(long)]
+        names: Option<PathBuf>,
+
+        /// Fail on positions outside the core, instead of dropping them with a warning
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// Generate a blueprint file from a MagicaVoxel .vox file
+    GenerateFromVox {
+        /// Input vox file name
+        input: PathBuf,
+
+        /// Output blueprint file name
+        output: PathBuf,
+
+        #[arg(short, long, value_parser = core_type_parser())]
+        r#type: CoreType,
+
+        #[arg(short, long, value_parser = core_size_parser())]
+        size: CoreSize,
+
+        /// Voxel material ID used for palette entries missing from the palette map
+        #[arg(short, long, default_value_t = 1971262921)]
+        material: u64,
+
+        /// JSON file mapping palette indices (1-255) to voxel material IDs
+        #[arg(long)]
+        palette_map: Option<PathBuf>,
+
+        /// Voxel material ID for palette colors whose alpha is below --alpha-threshold, such as
+        /// glass. Indices in the palette map keep their mapped material.
+        #[arg(long)]
+        glass_material: Option<u64>,
+
+        /// Alpha (0-255) below which a palette color counts as translucent
+        #[arg(long, default_value_t = 128, requires = "glass_material")]
+        alpha_threshold: u8,
+    },
+
+    /// Generate a blueprint file from a .binvox file, as written by binvox or cuda_voxelizer
+    GenerateFromBinvox {
+        /// Input binvox file name
+        input: PathBuf,
+
+        /// Output blueprint file name
+        output: PathBuf,
+
+        #[arg(short, long, value_parser = core_type_parser())]
+        r#type: CoreType,
+
+        #[arg(short, long, value_parser = core_size_parser())]
+        size: CoreSize,
+
+        /// Voxel material ID
+        #[arg(short, long, default_value_t = 1971262921)]
+        material: u64,
+    },
+
+    /// Generate a blueprint file from a point cloud, with one voxel per point.
+    GenerateFromPoints {
+        /// Input CSV or XYZ file name, with x,y,z and an optional material ID on each line
+        input: PathBuf,
+
+        /// Output blueprint file name
+        output: PathBuf,
+
+        #[arg(short, long, value_parser = core_type_parser())]
+        r#type: CoreType,
+
+        #[arg(short, long, value_parser = core_size_parser())]
+        size: CoreSize,
+
+        /// Voxel material ID for points without one
+        #[arg(short, long, default_value_t = 1971262921)]
+        material: u64,
+
+        /// Size of a voxel in the point cloud's units
+        #[arg(long, default_value_t = 1.0)]
+        voxel_size: f64,
+
+        /// Also fill every voxel within this many voxels of each point
+        #[arg(long, default_value_t = 0)]
+        radius: u32,
+    },
+
+    /// Generate a blueprint of a simple shape without a mesh, e.g. for placeholders or quick tests.
+    /// The shape is centred in the core.
+    GeneratePrimitive {
+        #[arg(value_enum)]
+        shape: PrimitiveShape,
+
+        /// Output blueprint file name
+        output: PathBuf,
+
+        /// Size of the shape in voxels (0.25m), as one size for every axis or x,y,z
+        #[arg(long, value_parser = parse_dimensions)]
+        dimensions: Vector<f64>,
+
+        #[arg(short, long, value_parser = core_type_parser())]
+        r#type: CoreType,
+
+        #[arg(short, long, value_parser = core_size_parser())]
+        size: CoreSize,
+
+        /// Voxel material ID
+        #[arg(short, long, default_value_t = 1971262921)]
+        material: u64,
+
+        /// Name of the construct in game, instead of the shape
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// Combine the voxels of two blueprint files of the same core size.
+    Combine {
+        /// First input blueprint file name
+        first: PathBuf,
+
+        /// Second input blueprint file name
+        second: PathBuf,
+
+        /// Output blueprint file name
+        output: PathBuf,
+
         #[arg(short, long, value_enum)]
+        op: CombineOp,
+
+        #[arg(short, long, value_parser = core_type_parser())]
+        r#type: CoreType,
+
+        #[arg(short, long, value_parser = core_size_parser())]
         size: CoreSize,
 
         /// Voxel material ID
@@ -84,115 +706,968 @@ enum Commands {
         material: u64,
     },
 
+    /// Decode a blueprint file and report its voxels, optionally writing it back out.
+    DecodeBlueprint {
+        /// Input blueprint file name
+        input: PathBuf,
+
+        /// Output blueprint file name. The blueprint is written back out with any edits applied
+        output: Option<PathBuf>,
+
+        /// Remove everything outside of minx,miny,minz,maxx,maxy,maxz, in voxels (0.25m) from
+        /// the core's minimum corner
+        #[arg(long, value_parser = parse_bounds, allow_hyphen_values = true)]
+        crop: Option<RangeZYX>,
+    },
+
+    /// Report the shape of a blueprint's voxel tree.
+    Stats {
+        /// Input blueprint file name
+        input: PathBuf,
+    },
+
+    /// List the materials a blueprint's voxels can use.
+    Materials {
+        /// Input blueprint file name
+        input: PathBuf,
+    },
+
+    /// Check that every voxel and meta chunk of a blueprint decodes, matches its stored hash and
+    /// survives re-encoding, before loading it in game.
+    Verify {
+        /// Input blueprint file name
+        input: PathBuf,
+    },
+
+    /// List the core types and sizes, with how many voxels each size holds.
+    ListCores,
+
+    /// Export the voxels of a blueprint file in the generate-from-json format.
+    DumpJson {
+        /// Input blueprint file name
+        input: PathBuf,
+
+        /// Output JSON file name
+        output: PathBuf,
+
+        /// Only export the voxels of this material ID
+        #[arg(long)]
+        only_material: Option<u64>,
+    },
+
+    /// Count the voxels of a blueprint in each layer along every axis, and find their centroid,
+    /// e.g. for balancing a ship's centre of mass.
+    Histogram {
+        /// Input blueprint file name
+        input: PathBuf,
+
+        /// Also write the layer counts to this CSV file, as axis,layer,voxels rows
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
+
+    /// Voxelize two versions of a mesh and write the voxels that changed, as a generate-from-json
+    /// patch with an extra "removed" list.
+    Diff {
+        /// The mesh the construct was generated from
+        old: PathBuf,
+
+        /// The edited mesh
+        new: PathBuf,
+
+        /// Output JSON file name, or - for stdout
+        output: PathBuf,
+
+        #[arg(short, long, value_parser = core_size_parser())]
+        size: CoreSize,
+
+        /// Voxel material ID
+        #[arg(short, long, default_value_t = 1971262921)]
+        material: u64,
+
+        #[command(flatten)]
+        scale: ScaleInfo,
+
+        /// Only keep a shell this many voxels thick
+        #[arg(long)]
+        hollow: Option<u32>,
+
+        /// Move surface vertices onto the model surface
+        #[arg(long)]
+        smooth: bool,
+    },
+
     /// Parse a base64 voxel chunk and dump the result to stdout
     ParseVoxel {
+        // Input base64
+        #[arg(required_unless_present = "file")]
+        b64: Option<String>,
+
+        /// Read the base64 from this file instead, for chunks too long for the command line
+        #[arg(long, conflicts_with = "b64")]
+        file: Option<PathBuf>,
+
+        /// Also write the surface of the chunk to this OBJ file
+        #[arg(long)]
+        obj: Option<PathBuf>,
+
+        /// Print the chunk as JSON instead
+        #[arg(long)]
+        json: bool,
+    },
+    /// Set one material in a base64 voxel chunk and print the re-encoded chunk to stdout
+    EditVoxel {
         // Input base64
         b64: String,
+
+        /// Grid position to set, as x,y,z. Materials sit on the +1,+1,+1 corner of their voxel
+        #[arg(long, value_parser = parse_position, allow_hyphen_values = true)]
+        position: Point<i32>,
+
+        /// Material index in the chunk's mapping, as listed by parse-voxel
+        #[arg(long)]
+        material: u8,
     },
     /// Parse a base64 meta chunk and dump the result to stdout
     ParseMeta {
         // Input base64
-        b64: String,
+        #[arg(required_unless_present = "file")]
+        b64: Option<String>,
+
+        /// Read the base64 from this file instead, for chunks too long for the command line
+        #[arg(long, conflicts_with = "b64")]
+        file: Option<PathBuf>,
+
+        /// Print the chunk as JSON instead
+        #[arg(long)]
+        json: bool,
     },
 }
 
-fn main() {
+// The base64 of a chunk, from the command line or from a file. Whitespace is ignored, so the file
+// can end in a newline or be wrapped.
+fn read_base64(b64: Option<String>, file: Option<PathBuf>) -> Result<String> {
+    let b64 = match file {
+        Some(path) => std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read base64 from {}", path.display()))?,
+        None => b64.unwrap_or_default(),
+    };
+    Ok(b64.split_whitespace().collect())
+}
+
+fn print_voxel_report(svo: &Svo<Option<VoxelCellData>>, voxel_limit: Option<u64>) {
+    let counts = svo.voxel_counts();
+    println!("Non-empty cells: {}", counts.cells);
+    println!("Total voxels: {}", counts.total());
+    for (material, count) in &counts.materials {
+        println!(
+            "  {} ({}): {}",
+            material.id,
+            material.short_name.trim_end_matches('\0'),
+            count
+        );
+    }
+    if let Some(limit) = voxel_limit {
+        if counts.total() > limit {
+            println!(
+                "Warning: {} voxels exceeds the limit of {}.",
+                counts.total(),
+                limit
+            );
+        }
+    }
+}
+
+// An empty blueprint imports without complaint, so a bad scale is easy to miss without this.
+fn check_not_empty(is_empty: bool, strict: bool) -> Result<()> {
+    if !is_empty {
+        return Ok(());
+    }
+    let message = "the blueprint contains no voxels. The model may be too small for the core at \
+                   this scale, try --auto or a larger --scale";
+    if strict {
+        bail!(message);
+    }
+    warn!("{}", message);
+    Ok(())
+}
+
+// Goes to stderr, since it can be printed alongside a blueprint written to stdout.
+fn print_dedup_report(svo: &Svo<Option<VoxelCellData>>) {
+    let report = svo.dedup_report();
+    eprintln!(
+        "Cells: {} ({} distinct, {} repeated)",
+        report.cells,
+        report.unique_cells,
+        report.cells - report.unique_cells
+    );
+    eprintln!(
+        "Subtrees: {} ({} distinct, {} repeated)",
+        report.subtrees,
+        report.unique_subtrees,
+        report.subtrees - report.unique_subtrees
+    );
+}
+
+fn construct_name(input: &Path) -> String {
+    input
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+// The first Ctrl-C stops voxelizing, so nothing half finished gets written. A second one quits
+// straight away.
+fn cancel_on_interrupt() -> Result<Arc<AtomicBool>> {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let flag = cancel.clone();
+    ctrlc::set_handler(move || {
+        if flag.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        eprintln!("Stopping after the current chunks, press Ctrl-C again to quit now");
+    })
+    .context("failed to set up the Ctrl-C handler")?;
+    Ok(cancel)
+}
+
+// The up axis is converted first, so --rotate and --quat work in DU's axes.
+fn model_isometry(
+    up: UpAxis,
+    rotate: Option<Vector<f64>>,
+    quat: Option<UnitQuaternion<f64>>,
+    translate: Option<Vector<f64>>,
+) -> Isometry<f64> {
+    let rotation = quat.unwrap_or_else(|| {
+        let rotate = rotate.unwrap_or_else(Vector::zeros).map(f64::to_radians);
+        UnitQuaternion::from_euler_angles(rotate.x, rotate.y, rotate.z)
+    }) * up.rotation();
+    let translation = Translation::from(translate.unwrap_or_else(Vector::zeros));
+    Isometry::from_parts(translation, rotation)
+}
+
+// Logs how large the model comes out, since that isn't obvious from the scale alone.
+fn report_dimensions(mesh_aabb: &Aabb, size: CoreSize, scale: ScaleMode) -> Result<()> {
+    let aabb = voxelization_aabb(mesh_aabb, size.height() - 3, scale)?;
+    // The voxelized region always spans the whole core.
+    let voxels_per_unit = Vector::repeat(size.voxels() as f64).component_div(&aabb.extents());
+    let voxels = mesh_aabb.extents().component_mul(&voxels_per_unit);
+    info!(
+        "Model is {:.0}x{:.0}x{:.0} voxels ({:.2}x{:.2}x{:.2} m)",
+        voxels.x,
+        voxels.y,
+        voxels.z,
+        voxels.x * 0.25,
+        voxels.y * 0.25,
+        voxels.z * 0.25
+    );
+    if let ScaleMode::Auto { .. } = scale {
+        // The same factor --scale takes, which puts 8 voxels (2m) in a model unit at 1.0.
+        info!("Auto scale factor: {:.4}", voxels_per_unit.x / 8.0);
+    }
+    Ok(())
+}
+
+// Anything outside the voxelized region is left out of the blueprint, which is easy to miss with a
+// fixed scale. Auto scaling always fits the model.
+fn warn_if_clipped(
+    mesh_aabb: &Aabb,
+    size: CoreSize,
+    scale: ScaleMode,
+    anchor: Anchor,
+) -> Result<()> {
+    if let ScaleMode::Auto { .. } = scale {
+        return Ok(());
+    }
+    let aabb = anchor.apply(
+        voxelization_aabb(mesh_aabb, size.height() - 3, scale)?,
+        mesh_aabb,
+    );
+    let voxels_per_unit = Vector::repeat(size.voxels() as f64).component_div(&aabb.extents());
+    let below = (aabb.mins - mesh_aabb.mins).sup(&Vector::zeros());
+    let above = (mesh_aabb.maxs - aabb.maxs).sup(&Vector::zeros());
+    let overflow = (below + above).component_mul(&voxels_per_unit);
+    if overflow.max() > 0.0 {
+        warn!(
+            "The model is {:.0}x{:.0}x{:.0} voxels larger than the {} voxel core, the rest is cut \
+             off. Use --auto, a smaller --scale or a larger --size to fit it",
+            overflow.x,
+            overflow.y,
+            overflow.z,
+            size.voxels()
+        );
+    }
+    Ok(())
+}
+
+// Prints the occupied chunks looking down each axis, with the first axis across and the second
+// up the page.
+fn print_occupancy(chunks: &HashSet<Point<i32>>, extent: i32) {
+    let views = [("Top", 0, 1), ("Front", 0, 2), ("Side", 1, 2)];
+    let names = ["x", "y", "z"];
+    for (name, across, up) in views {
+        let projected = HashSet::<(i32, i32)>::from_iter(chunks.iter().map(|p| (p[across], p[up])));
+        println!("{} ({} across, {} up):", name, names[across], names[up]);
+        for row in (0..extent).rev() {
+            let line = String::from_iter((0..extent).map(|column| {
+                if projected.contains(&(column, row)) {
+                    '#'
+                } else {
+                    '.'
+                }
+            }));
+            println!("{}", line);
+        }
+        println!();
+    }
+}
+
+// Without strict, a mesh that can't be oriented is still used, with the orientation left as is.
+fn prepare_mesh(mesh: TriMesh, strict: bool) -> Result<TriMesh> {
+    let flags = TriMeshFlags::ORIENTED
+        | TriMeshFlags::FIX_INTERNAL_EDGES
+        | TriMeshFlags::DELETE_DEGENERATE_TRIANGLES;
+    let mut prepared = mesh.clone();
+    let Err(e) = prepared.set_flags(flags) else {
+        return Ok(prepared);
+    };
+    let misoriented = misoriented_triangle_count(&mesh);
+    if strict {
+        bail!(
+            "failed to prepare mesh topology: {:?}, {} triangles are misoriented",
+            e,
+            misoriented
+        );
+    }
+    warn!(
+        "Failed to prepare mesh topology: {:?}, {} triangles are misoriented. Continuing without \
+         fixing the orientation, so expect stray voxels; --fill-mode winding may do better",
+        e, misoriented
+    );
+    // Fixing internal edges needs a consistent orientation too.
+    let mut prepared = mesh;
+    prepared
+        .set_flags(flags - TriMeshFlags::ORIENTED - TriMeshFlags::FIX_INTERNAL_EDGES)
+        .map_err(|e| anyhow!("failed to prepare mesh topology: {:?}", e))?;
+    Ok(prepared)
+}
+
+fn read_json(path: &Path, what: &str) -> Result<Value> {
+    let file =
+        File::open(path).with_context(|| format!("failed to open {}: {}", what, path.display()))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("failed to parse {}: {}", what, path.display()))
+}
+
+// Splits an OBJ file into parts by material name.
+fn obj_material_parts(
+    parts: Vec<(String, TriMesh)>,
+    map_path: &Path,
+    material: u64,
+) -> Result<Vec<MeshPart>> {
+    let material_map: HashMap<String, u64> =
+        serde_json::from_value(read_json(map_path, "material map")?)
+            .context("material map must map material names to material IDs")?;
+    Ok(Vec::from_iter(parts.into_iter().map(|(name, mesh)| {
+        MeshPart {
+            material: material_map.get(&name).copied().unwrap_or(material),
+            mesh,
+        }
+    })))
+}
+
+fn parse_color(value: &str) -> Option<[u8; 3]> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+// Returns the palette colors, and the material ID of each.
+fn read_palette(palette_path: &Path) -> Result<(Vec<[u8; 3]>, Vec<u64>)> {
+    let palette: HashMap<String, u64> =
+        serde_json::from_value(read_json(palette_path, "color palette")?)
+            .context("color palette must map colors to material IDs")?;
+    if palette.is_empty() {
+        bail!("the color palette is empty: {}", palette_path.display());
+    }
+    palette
+        .iter()
+        .map(|(color, id)| {
+            let color =
+                parse_color(color).ok_or_else(|| anyhow!("bad palette color: {}", color))?;
+            Ok((color, *id))
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(|entries| entries.into_iter().unzip())
+}
+
+// Gives each OBJ part the material of the palette color nearest to the diffuse color of its OBJ
+// material. With a glass material and alpha threshold, translucent OBJ materials get the glass
+// material instead.
+fn obj_color_parts(
+    parts: Vec<(String, TriMesh)>,
+    colors: &HashMap<String, [u8; 4]>,
+    palette_path: &Path,
+    material: u64,
+    glass: Option<(u64, u8)>,
+) -> Result<Vec<MeshPart>> {
+    let (palette, materials) = read_palette(palette_path)?;
+    Ok(Vec::from_iter(parts.into_iter().map(|(name, mesh)| {
+        let material = match (colors.get(&name), glass) {
+            (Some(color), Some((glass, threshold))) if color[3] < threshold => glass,
+            (Some(color), _) => {
+                let distance = |entry: &[u8; 3]| {
+                    (0..3)
+                        .map(|c| (color[c] as i32 - entry[c] as i32).pow(2))
+                        .sum::<i32>()
+                };
+                let nearest = (0..palette.len())
+                    .min_by_key(|i| distance(&palette[*i]))
+                    .expect("the palette isn't empty");
+                materials[nearest]
+            }
+            (None, _) => {
+                warn!(
+                    "OBJ material {} has no diffuse color, using --material",
+                    name
+                );
+                material
+            }
+        };
+        MeshPart { material, mesh }
+    })))
+}
+
+// Splits a PLY file into parts by the nearest palette color, or the glass material for faces
+// below the alpha threshold. Files without vertex colors aren't split, so they use the single
+// material.
+fn ply_color_parts(
+    input: &Path,
+    palette_path: &Path,
+    glass: Option<(u64, u8)>,
+) -> Result<Vec<MeshPart>> {
+    if !input
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("ply"))
+    {
+        bail!("--color-palette only works with PLY and OBJ files");
+    }
+    let (colors, materials) = read_palette(palette_path)?;
+
+    let ply = load_ply(input)
+        .with_context(|| format!("failed to load input mesh: {}", input.display()))?;
+    let (glass_material, alpha_threshold) = glass.unzip();
+    let Some(parts) = ply.split_by_palette(&colors, alpha_threshold.unwrap_or(0)) else {
+        warn!("{} has no vertex colors, using --material", input.display());
+        return Ok(Vec::new());
+    };
+    Ok(Vec::from_iter(parts.into_iter().map(|(entry, mesh)| {
+        let material = match entry {
+            Some(entry) => materials[entry],
+            None => glass_material.expect("faces are only translucent with a glass material"),
+        };
+        MeshPart { material, mesh }
+    })))
+}
+
+// Loads one generate input as a mesh, along with the parts that the material map or color palette
+// give their own materials. `material` is used for anything they don't cover.
+fn load_generate_input(
+    input: &Path,
+    triangulation: Triangulation,
+    material_map: Option<&Path>,
+    color_palette: Option<&Path>,
+    glass: Option<(u64, u8)>,
+    material: u64,
+) -> Result<(TriMesh, Vec<MeshPart>)> {
+    let is_obj = input
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("obj"));
+    if triangulation == Triangulation::EarClipping && !is_obj && input != Path::new("-") {
+        warn!("--robust-triangulation only affects OBJ files");
+    }
+
+    // stdin can only be read once, so OBJ parts are loaded first and the mesh is joined back
+    // together from them.
+    let mut obj_colors = HashMap::new();
+    let obj_parts = if input == Path::new("-") {
+        let parts = load_obj_parts_from_reader(&mut std::io::stdin().lock(), triangulation)
+            .context("failed to read OBJ from stdin")?;
+        Some(parts)
+    } else if material_map.is_some()
+        || (is_obj && (color_palette.is_some() || triangulation == Triangulation::EarClipping))
+    {
+        if material_map.is_some() && !is_obj {
+            bail!("--material-map only works with OBJ files");
+        }
+        let (parts, colors) = load_obj_parts_with_colors(input, triangulation)
+            .with_context(|| format!("failed to load input mesh: {}", input.display()))?;
+        obj_colors = colors;
+        Some(parts)
+    } else {
+        None
+    };
+    let mesh = match &obj_parts {
+        Some(parts) => merge_parts(parts)?,
+        None => load_mesh(input)
+            .with_context(|| format!("failed to load input mesh: {}", input.display()))?,
+    };
+
+    let parts = match (obj_parts, material_map, color_palette) {
+        (Some(obj_parts), Some(path), _) => obj_material_parts(obj_parts, path, material)?,
+        (Some(obj_parts), None, Some(path)) => {
+            obj_color_parts(obj_parts, &obj_colors, path, material, glass)?
+        }
+        (_, None, Some(path)) => ply_color_parts(input, path, glass)?,
+        _ => Vec::new(),
+    };
+    Ok((mesh, parts))
+}
+
+fn read_voxel_data(path: &Path) -> Result<Svo<Option<VoxelCellData>>> {
+    let json_data = read_json(path, "input blueprint")?;
+    decode_voxel_data(&json_data)
+        .with_context(|| format!("failed to decode blueprint: {}", path.display()))
+}
+
+/// Adds the core position to the file name, before any extensions so out.blueprint.gz becomes
+/// out_0_1_0.blueprint.gz.
+fn split_output_path(output: &Path, position: &Vector<i32>) -> PathBuf {
+    let file_name = output.file_name().unwrap_or_default().to_string_lossy();
+    let (stem, extensions) = match file_name.find('.') {
+        Some(i) => file_name.split_at(i),
+        None => (&*file_name, ""),
+    };
+    output.with_file_name(format!(
+        "{}_{}_{}_{}{}",
+        stem, position.x, position.y, position.z, extensions
+    ))
+}
+
+// The manifest of `ship.blueprint` or `ship.blueprint.gz` is `ship.meta.json`.
+fn manifest_path(output: &Path) -> PathBuf {
+    let file_name = output.file_name().unwrap_or_default().to_string_lossy();
+    let stem = file_name.split('.').next().unwrap_or_default();
+    output.with_file_name(format!("{}.meta.json", stem))
+}
+
+// Adds the voxel counts of the blueprint to its manifest and writes it next to the output.
+fn write_manifest(
+    output: &Path,
+    mut manifest: Value,
+    voxel_data: &Svo<Option<VoxelCellData>>,
+) -> Result<()> {
+    let counts = voxel_data.voxel_counts();
+    manifest["cells"] = json!(counts.cells);
+    manifest["voxels"] = json!(counts.total());
+    write_output(
+        &manifest_path(output),
+        &serde_json::to_string_pretty(&manifest)?,
+        false,
+    )
+}
+
+/// Writes the output file, gzipped if `gzip` is set or the file name ends in `.gz`. A path of
+/// `-` writes to stdout instead.
+fn write_output(path: &Path, contents: &str, gzip: bool) -> Result<()> {
+    let gzip = gzip || path.extension().is_some_and(|e| e == "gz");
+    let result = if path == Path::new("-") {
+        write_contents(std::io::stdout().lock(), contents, gzip)
+    } else {
+        File::create(path).and_then(|file| write_contents(file, contents, gzip))
+    };
+    result.with_context(|| format!("failed to write output: {}", path.display()))
+}
+
+fn write_contents(mut writer: impl Write, contents: &str, gzip: bool) -> std::io::Result<()> {
+    if gzip {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        encoder.write_all(contents.as_bytes())?;
+        encoder.finish()?.flush()
+    } else {
+        writer.write_all(contents.as_bytes())?;
+        writer.flush()
+    }
+}
+
+fn main() -> Result<()> {
     let cli = Cli::parse();
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    // Logs and progress bars stay on stderr, so a blueprint written to stdout can be piped.
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .target(env_logger::Target::Stderr)
+        .init();
+
     match cli.command {
         Commands::Generate {
-            input,
+            inputs,
             output,
+            name,
             size,
+            fit_size: _,
             r#type,
             material,
+            interior_material,
+            material_map,
+            color_palette,
+            glass_material,
+            alpha_threshold,
             scale,
+            pad,
+            auto_margin,
+            anchor,
+            up,
+            handedness,
+            rotate,
+            quat,
+            translate,
+            hollow,
+            lattice,
+            collapse_solid,
+            smooth,
+            fill_mode,
+            samples,
+            sample_rule,
+            allow_open,
+            weld_tolerance,
+            robust_triangulation,
+            strict,
+            mirror,
+            symmetry,
+            origin,
+            tile,
+            crop,
+            split,
+            cache,
+            dry_run,
+            analyze,
+            voxel_limit,
+            threads,
+            gzip,
+            manifest,
         } => {
-            let (models, _) = tobj::load_obj(
-                &input,
-                &LoadOptions {
-                    merge_identical_points: true,
-                    triangulate: true,
-                    ..Default::default()
-                },
-            )
-            .unwrap();
+            if split && output == Path::new("-") {
+                bail!("--split writes several blueprints, so it can't write to stdout");
+            }
+            if manifest && output == Path::new("-") {
+                bail!("--manifest is written next to the output, so it can't write to stdout");
+            }
+            if let Some(threads) = threads {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build_global()
+                    .context("failed to set up the thread pool")?;
+            }
 
-            let mut mesh: Option<TriMesh> = None;
-            for model in models {
-                let vertices = Vec::from_iter(
-                    model
-                        .mesh
-                        .positions
-                        .chunks_exact(3)
-                        .map(|x| Point::from_slice(&[x[0] as f64, x[1] as f64, x[2] as f64])),
-                );
-                let indices = Vec::from_iter(
-                    model
-                        .mesh
-                        .indices
-                        .chunks_exact(3)
-                        .map(|c| [c[0], c[1], c[2]]),
+            if material.len() != 1 && material.len() != inputs.len() {
+                bail!(
+                    "--material was given {} times for {} inputs, give it once or once per input",
+                    material.len(),
+                    inputs.len()
                 );
-                let sub_mesh = TriMesh::new(vertices, indices);
+            }
+            let input_materials = Vec::from_iter(
+                (0..inputs.len()).map(|i| material.get(i).copied().unwrap_or(material[0])),
+            );
+            let material = material[0];
+            let input_names = Vec::from_iter(inputs.iter().map(|i| i.display().to_string()));
+            let input_names = input_names.join(", ");
+
+            let triangulation = if robust_triangulation {
+                Triangulation::EarClipping
+            } else {
+                Triangulation::Fan
+            };
+            let glass = glass_material.map(|glass| (glass, alpha_threshold));
+            let loaded = inputs
+                .iter()
+                .zip(&input_materials)
+                .map(|(input, input_material)| {
+                    load_generate_input(
+                        input,
+                        triangulation,
+                        material_map.as_deref(),
+                        color_palette.as_deref(),
+                        glass,
+                        *input_material,
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?;
+            // Parts are only needed if something doesn't use the first material.
+            let has_parts = input_materials.iter().any(|m| *m != material)
+                || loaded
+                    .iter()
+                    .flat_map(|(_, parts)| parts)
+                    .any(|part| part.material != material);
+            let mut mesh: Option<TriMesh> = None;
+            let mut parts = Vec::new();
+            for ((input_mesh, input_parts), input_material) in
+                loaded.into_iter().zip(input_materials)
+            {
+                // An input without parts of its own is one part, so it keeps its material
+                // alongside inputs that have parts.
+                if has_parts {
+                    if input_parts.is_empty() {
+                        parts.push(MeshPart {
+                            material: input_material,
+                            mesh: input_mesh.clone(),
+                        });
+                    } else {
+                        parts.extend(input_parts);
+                    }
+                }
                 match &mut mesh {
-                    Some(mesh) => mesh.append(&sub_mesh),
-                    None => mesh = Some(sub_mesh),
+                    Some(mesh) => mesh.append(&input_mesh),
+                    None => mesh = Some(input_mesh),
                 }
             }
-            let mut mesh = mesh.unwrap();
-            mesh.set_flags(
-                TriMeshFlags::ORIENTED
-                    | TriMeshFlags::FIX_INTERNAL_EDGES
-                    | TriMeshFlags::DELETE_DEGENERATE_TRIANGLES,
-            )
-            .unwrap();
+            let mesh = mesh.expect("there is at least one input");
 
-            // TODO: allow translations and rotations
-            let isometry = Isometry::default();
-
-            let height = size.height() - 3;
-            let aabb = mesh.aabb(&isometry);
-            let svo_aabb = if scale.auto {
-                let scale = Vector::repeat(aabb.extents().max()).component_div(&aabb.extents());
-                aabb.scaled_wrt_center(&scale)
-                    .scaled_wrt_center(&Vector::repeat(2.0))
+            let mesh = if weld_tolerance > 0.0 {
+                let welded = weld_vertices(&mesh, weld_tolerance);
+                info!(
+                    "Welded {} vertices within {} of another",
+                    mesh.vertices().len() - welded.vertices().len(),
+                    weld_tolerance
+                );
+                welded
             } else {
-                let extents = Vector::repeat(4.0 * (1 << height) as f64);
-                Aabb::from_half_extents(aabb.center(), extents / scale.scale)
+                mesh
+            };
+            let mesh = match handedness {
+                Handedness::Left => mirror_mesh(&mesh, up.forward_axis()),
+                Handedness::Right => mesh,
             };
+            let mesh = prepare_mesh(mesh, strict)?;
+            // A flat mesh is all open edges, but it comes out one voxel thick either way.
+            let open_edges = if is_flat(&mesh) {
+                0
+            } else {
+                open_edge_count(&mesh)
+            };
+            if open_edges > 0 {
+                // The winding number copes with holes, so it's only worth a warning there.
+                if allow_open || fill_mode == FillMode::Winding {
+                    warn!("The model isn't closed, {} edges are open", open_edges);
+                } else {
+                    bail!(
+                        "the model isn't closed, {} edges are open. Fix the mesh, or pass \
+                         --fill-mode winding or --allow-open",
+                        open_edges
+                    );
+                }
+            }
 
-            let voxelizer = Voxelizer::new(isometry, mesh);
-            let svo = voxelizer.create_lods(&svo_aabb, Point::origin(), height, material);
-            let bp = Blueprint::new(
-                input
-                    .clone()
-                    .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string(),
-                CoreInfo::from(size, r#type),
+            let parts = match handedness {
+                Handedness::Left => Vec::from_iter(parts.into_iter().map(|part| MeshPart {
+                    mesh: mirror_mesh(&part.mesh, up.forward_axis()),
+                    material: part.material,
+                })),
+                Handedness::Right => parts,
+            };
+
+            let isometry = model_isometry(up, rotate, quat, translate);
+
+            let scale = scale.mode(pad, auto_margin);
+            let size = match size {
+                Some(size) => size,
+                None => {
+                    let size = smallest_core_size(&mesh.aabb(&isometry), scale)
+                        .context("the model doesn't fit in the largest core at this scale")?;
+                    info!("Using core size {:?}", size);
+                    size
+                }
+            };
+            let options = GenerateOptions {
+                size,
+                core_type: r#type,
                 material,
-                svo,
+                interior_material,
+                scale,
+                anchor,
+                hollow,
+                lattice,
+                crop,
+                collapse_solid,
+                smooth,
+                fill_mode,
+                samples,
+                sample_rule,
+                mirror,
+                symmetry,
+                origin: origin.map(|origin| origin.coords),
+                tile,
+                cancel: Some(cancel_on_interrupt()?),
+                cache,
+            };
+            report_dimensions(&mesh.aabb(&isometry), size, scale)?;
+            if !split {
+                warn_if_clipped(&mesh.aabb(&isometry), size, scale, anchor)?;
+            }
+            let manifest = manifest.then(|| {
+                json!({
+                    "tool_version": env!("CARGO_PKG_VERSION"),
+                    "inputs": Vec::from_iter(inputs.iter().map(|i| i.display().to_string())),
+                    "up": value_name(&up),
+                    "handedness": value_name(&handedness),
+                    // As [i, j, k, w].
+                    "rotation": isometry.rotation.coords.as_slice(),
+                    "translation": isometry.translation.vector.as_slice(),
+                    "options": options.to_json(),
+                })
+            });
+            if split {
+                let pieces = generate_split_blueprints(
+                    name.unwrap_or_else(|| construct_name(&inputs[0])),
+                    mesh,
+                    parts,
+                    isometry,
+                    &options,
+                )
+                .with_context(|| format!("failed to voxelize: {}", input_names))?;
+                check_not_empty(pieces.is_empty(), strict)?;
+                let core_width = size.voxels() as f64 * 0.25;
+                info!("Split into {} cores, {}m apart", pieces.len(), core_width);
+                for piece in pieces {
+                    let position = piece.position;
+                    let path = split_output_path(&output, &position);
+                    info!(
+                        "{}: offset {}m, {}m, {}m",
+                        path.display(),
+                        position.x as f64 * core_width,
+                        position.y as f64 * core_width,
+                        position.z as f64 * core_width
+                    );
+                    if analyze {
+                        print_dedup_report(piece.blueprint.voxel_data());
+                    }
+                    if dry_run {
+                        println!("{}:", path.display());
+                        print_voxel_report(piece.blueprint.voxel_data(), voxel_limit);
+                        continue;
+                    }
+                    write_output(
+                        &path,
+                        &piece.blueprint.to_construct_json().to_string(),
+                        gzip,
+                    )?;
+                    if let Some(manifest) = &manifest {
+                        let mut manifest = manifest.clone();
+                        manifest["position"] = json!(position.as_slice());
+                        write_manifest(&path, manifest, piece.blueprint.voxel_data())?;
+                    }
+                }
+                return Ok(());
+            }
+            let name = name.unwrap_or_else(|| construct_name(&inputs[0]));
+            let bp = generate_blueprint(name, mesh, parts, isometry, &options)
+                .with_context(|| format!("failed to voxelize: {}", input_names))?;
+            check_not_empty(bp.voxel_data().root.is_empty(), strict)?;
+            if analyze {
+                print_dedup_report(bp.voxel_data());
+            }
+            if dry_run {
+                print_voxel_report(bp.voxel_data(), voxel_limit);
+                return Ok(());
+            }
+            write_output(&output, &bp.to_construct_json().to_string(), gzip)?;
+            if let Some(manifest) = manifest {
+                write_manifest(&output, manifest, bp.voxel_data())?;
+            }
+        }
+        Commands::Preview {
+            input,
+            size,
+            scale,
+            up,
+            handedness,
+            rotate,
+            quat,
+            translate,
+            obj,
+        } => {
+            let mesh = load_mesh(&input)
+                .with_context(|| format!("failed to load input mesh: {}", input.display()))?;
+            let mesh = match handedness {
+                Handedness::Left => mirror_mesh(&mesh, up.forward_axis()),
+                Handedness::Right => mesh,
+            };
+            let mesh = prepare_mesh(mesh, false)?;
+            let isometry = model_isometry(up, rotate, quat, translate);
+
+            // The region is the same as a full generate, just cut into fewer, larger chunks.
+            let aabb = voxelization_aabb(
+                &mesh.aabb(&isometry),
+                size.height() - 3,
+                scale.mode(None, None),
+            )
+            .with_context(|| format!("failed to voxelize: {}", input.display()))?;
+            let height = (size.height() - 3).min(PREVIEW_HEIGHT);
+            let svo = Voxelizer::new(isometry, mesh)
+                .create_lods(&aabb, Point::origin(), height, 1971262921)
+                .with_context(|| format!("failed to voxelize: {}", input.display()))?;
+            let mut chunks = HashSet::new();
+            svo.cata(|range, v, _| {
+                if range.size.x == 1 && v.is_some() {
+                    chunks.insert(range.origin);
+                }
+            });
+
+            let extent = 1 << height;
+            let voxels = 128 << (size.height() - 5);
+            println!(
+                "{} of {}x{}x{} chunks occupied, each {} voxels across",
+                chunks.len(),
+                extent,
+                extent,
+                extent,
+                voxels / extent
             );
-            File::create(output)
-                .unwrap()
-                .write(bp.to_construct_json().to_string().as_bytes())
-                .unwrap();
+            match obj {
+                Some(path) => {
+                    let mut writer = BufWriter::new(File::create(&path)?);
+                    write_boxes_obj(&chunks, (voxels / extent) as f64 * 0.25, &mut writer)?;
+                }
+                None => print_occupancy(&chunks, extent),
+            }
         }
         Commands::GenerateFromJson {
             input,
             output,
+            name,
             r#type,
             size,
             material,
+            dry_run,
+            analyze,
+            voxel_limit,
+            gzip,
+            no_debug_material,
+            leaf_size,
+            names,
+            strict,
         } => {
-            // Load the JSON file
-            let json_data: Value = {
-                let file = std::fs::File::open(&input).expect("Failed to open input JSON file");
-                serde_json::from_reader(file).expect("Failed to parse JSON file")
+            let names: HashMap<u64, String> = match names {
+                Some(path) => serde_json::from_value(read_json(&path, "material names")?)
+                    .context("material names must map material IDs to names")?,
+                None => HashMap::new(),
             };
 
+            // Open the JSON file, it gets streamed in by the importer
+            let file = File::open(&input)
+                .with_context(|| format!("failed to open input JSON: {}", input.display()))?;
+
             // Derive the height from the CoreSize
             let height = size.height();
 
@@ -200,36 +1675,423 @@ fn main() {
             let mut json_importer = JSONImporter;
 
             // Create the SVO using the JSONImporter
-            let svo = json_importer.process_json_and_create_svo(&json_data, height);
+            let svo = json_importer
+                .process_json_and_create_svo(
+                    BufReader::new(file),
+                    height,
+                    leaf_size,
+                    !no_debug_material,
+                    &names,
+                    strict,
+                )
+                .with_context(|| format!("failed to parse input JSON: {}", input.display()))?;
+            if analyze {
+                print_dedup_report(&svo);
+            }
+            if dry_run {
+                print_voxel_report(&svo, voxel_limit);
+                return Ok(());
+            }
 
             // Create the Blueprint using the generated SVO
             let bp = Blueprint::new(
-                input
-                    .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string(),
+                name.unwrap_or_else(|| construct_name(&input)),
                 CoreInfo::from(size, r#type),
                 material,
                 svo,
             );
 
             // Write the blueprint to the output file
-            std::fs::File::create(output)
-                .unwrap()
-                .write_all(bp.to_construct_json().to_string().as_bytes())
-                .expect("Failed to write blueprint to output file");
-        },
-        Commands::ParseVoxel { b64 } => {
-            let bytes = base64::prelude::BASE64_STANDARD.decode(b64).unwrap();
+            write_output(&output, &bp.to_construct_json().to_string(), gzip)?;
+        }
+        Commands::GenerateFromVox {
+            input,
+            output,
+            r#type,
+            size,
+            material,
+            palette_map,
+            glass_material,
+            alpha_threshold,
+        } => {
+            let bytes = std::fs::read(&input)
+                .with_context(|| format!("failed to open input vox: {}", input.display()))?;
+            let vox = VoxFile::deserialize(&mut bytes.as_slice())
+                .with_context(|| format!("failed to parse input vox: {}", input.display()))?;
+
+            let mut palette_map: HashMap<u8, u64> = match palette_map {
+                Some(path) => serde_json::from_value(read_json(&path, "palette map")?)
+                    .context("palette map must map palette indices to material IDs")?,
+                None => HashMap::new(),
+            };
+            if let Some(glass) = glass_material {
+                for index in vox.translucent_indices(alpha_threshold) {
+                    palette_map.entry(index).or_insert(glass);
+                }
+            }
+
+            let svo = vox
+                .create_svo(&palette_map, material, size.height())
+                .context("failed to convert the vox file")?;
+            let bp = Blueprint::new(
+                construct_name(&input),
+                CoreInfo::from(size, r#type),
+                material,
+                svo,
+            );
+            write_output(&output, &bp.to_construct_json().to_string(), false)?;
+        }
+        Commands::GenerateFromBinvox {
+            input,
+            output,
+            r#type,
+            size,
+            material,
+        } => {
+            let bytes = std::fs::read(&input)
+                .with_context(|| format!("failed to open input binvox: {}", input.display()))?;
+            let binvox = BinvoxFile::deserialize(&mut bytes.as_slice())
+                .with_context(|| format!("failed to parse input binvox: {}", input.display()))?;
+            let svo = binvox
+                .create_svo(material, size.height())
+                .context("failed to convert the binvox file")?;
+            let bp = Blueprint::new(
+                construct_name(&input),
+                CoreInfo::from(size, r#type),
+                material,
+                svo,
+            );
+            write_output(&output, &bp.to_construct_json().to_string(), false)?;
+        }
+        Commands::GenerateFromPoints {
+            input,
+            output,
+            r#type,
+            size,
+            material,
+            voxel_size,
+            radius,
+        } => {
+            let file = File::open(&input)
+                .with_context(|| format!("failed to open input points: {}", input.display()))?;
+            let points = PointCloud::read(BufReader::new(file))
+                .with_context(|| format!("failed to parse input points: {}", input.display()))?;
+            let svo = points
+                .create_svo(material, voxel_size, radius, size.height())
+                .context("failed to convert the point cloud")?;
+            let bp = Blueprint::new(
+                construct_name(&input),
+                CoreInfo::from(size, r#type),
+                material,
+                svo,
+            );
+            write_output(&output, &bp.to_construct_json().to_string(), false)?;
+        }
+        Commands::GeneratePrimitive {
+            shape,
+            output,
+            dimensions,
+            r#type,
+            size,
+            material,
+            name,
+        } => {
+            let primitive = Primitive {
+                shape,
+                size: dimensions,
+            };
+            let svo = primitive
+                .create_svo(material, size.height())
+                .context("failed to fill the shape")?;
+            let name = name.unwrap_or_else(|| {
+                shape
+                    .to_possible_value()
+                    .map_or(String::new(), |v| v.get_name().to_string())
+            });
+            let bp = Blueprint::new(name, CoreInfo::from(size, r#type), material, svo);
+            write_output(&output, &bp.to_construct_json().to_string(), false)?;
+        }
+        Commands::Combine {
+            first,
+            second,
+            output,
+            op,
+            r#type,
+            size,
+            material,
+        } => {
+            let svo = read_voxel_data(&first)?
+                .combine(read_voxel_data(&second)?, op)
+                .context("failed to combine blueprints")?;
+            let bp = Blueprint::new(
+                construct_name(&first),
+                CoreInfo::from(size, r#type),
+                material,
+                svo,
+            );
+            write_output(&output, &bp.to_construct_json().to_string(), false)?;
+        }
+        Commands::DecodeBlueprint {
+            input,
+            output,
+            crop,
+        } => {
+            let json_data = read_json(&input, "input blueprint")?;
+            let mut bp = Blueprint::from_construct_json(&json_data)
+                .with_context(|| format!("failed to decode blueprint: {}", input.display()))?;
+            if let Some(bounds) = &crop {
+                bp = bp.map_voxel_data(|svo| svo.crop(bounds, DEFAULT_LEAF_SIZE));
+            }
+            println!("Blueprint: {}", bp.name());
+            print_voxel_report(bp.voxel_data(), None);
+            if let Some(output) = output {
+                write_output(&output, &bp.to_construct_json().to_string(), false)?;
+            }
+        }
+        Commands::Stats { input } => {
+            let stats = read_voxel_data(&input)?.stats();
+            println!("Depth: {}", stats.depth);
+            println!("Internal nodes: {}", stats.internal_nodes);
+            println!(
+                "Leaves: {} ({} non-empty)",
+                stats.leaves, stats.non_empty_leaves
+            );
+            println!("Voxels: {}", stats.voxels);
+            println!("Approximate memory: {} KiB", stats.memory / 1024);
+        }
+        Commands::Materials { input } => {
+            let svo = read_voxel_data(&input)?;
+            let mut materials = BTreeMap::new();
+            svo.cata(|_, v, _| {
+                if let Some(cell_data) = v {
+                    for (_, material) in cell_data.mapping().iter() {
+                        let short_name = material.short_name.trim_end_matches('\0');
+                        materials.insert(material.id, short_name.to_string());
+                    }
+                }
+            });
+            for (id, short_name) in materials {
+                println!("{} {}", id, short_name);
+            }
+        }
+        Commands::Verify { input } => {
+            let json_data = read_json(&input, "input blueprint")?;
+            let (cells, problems) = verify_voxel_data(&json_data)
+                .with_context(|| format!("failed to verify blueprint: {}", input.display()))?;
+            for problem in &problems {
+                println!(
+                    "Cell {} at {}, {}, {}: {}",
+                    problem.height,
+                    problem.coords.x,
+                    problem.coords.y,
+                    problem.coords.z,
+                    problem.problem
+                );
+            }
+            if !problems.is_empty() {
+                bail!("{} problems in {} cells", problems.len(), cells);
+            }
+            // The cells are fine on their own, so check they also form a tree.
+            decode_voxel_data(&json_data)
+                .with_context(|| format!("failed to decode blueprint: {}", input.display()))?;
+            println!("{} cells OK", cells);
+        }
+        Commands::ListCores => {
+            let name = |value: Option<clap::builder::PossibleValue>| {
+                value.map_or(String::new(), |v| v.get_name().to_string())
+            };
+            println!("Core types:");
+            for core_type in CoreType::value_variants() {
+                let sizes = Vec::from_iter(
+                    CoreSize::value_variants()
+                        .iter()
+                        .filter(|size| core_type.has_size(**size))
+                        .map(|size| name(size.to_possible_value())),
+                );
+                println!(
+                    "  {:<8} id {} sizes {}",
+                    name(core_type.to_possible_value()),
+                    core_type.kind(),
+                    sizes.join(", ")
+                );
+            }
+            println!("Core sizes:");
+            for size in CoreSize::value_variants() {
+                println!(
+                    "  {:<7} id {:<5} height {:<3} {} voxels ({} m) on each axis",
+                    name(size.to_possible_value()),
+                    size.size(),
+                    size.height(),
+                    size.voxels(),
+                    size.voxels() as f64 * 0.25
+                );
+            }
+        }
+        Commands::DumpJson {
+            input,
+            output,
+            only_material,
+        } => {
+            let mut svo = read_voxel_data(&input)?;
+            if let Some(material) = only_material {
+                svo = svo.retain_material(material);
+                if svo.root.is_empty() {
+                    warn!("{} has no voxels of material {}", input.display(), material);
+                }
+            }
+            write_output(&output, &svo.to_import_json().to_string(), false)?;
+        }
+        Commands::Histogram { input, csv } => {
+            let svo = read_voxel_data(&input)?;
+            let occupancy = svo.occupancy();
+            let Some(centroid) = occupancy.centroid() else {
+                bail!("{} has no voxels", input.display());
+            };
+            let names = ["x", "y", "z"];
+            for (name, layers) in names.iter().zip(&occupancy.layers) {
+                println!("Voxels in each {} layer:", name);
+                for (layer, count) in layers {
+                    println!("  {:>6} {}", layer, count);
+                }
+            }
+            // Positions in game are relative to the middle of the core.
+            let cell_voxels = DEFAULT_LEAF_SIZE as f64;
+            let core_center = (svo.range.origin.coords.cast::<f64>()
+                + svo.range.size.cast::<f64>() / 2.0)
+                * cell_voxels;
+            let meters = (centroid.coords - core_center) * 0.25;
+            println!(
+                "{} voxels, centroid at {:.2}, {:.2}, {:.2} voxels ({:.2}, {:.2}, {:.2} m from the \
+                 middle of the core)",
+                occupancy.total, centroid.x, centroid.y, centroid.z, meters.x, meters.y, meters.z
+            );
+            if let Some(path) = csv {
+                let mut contents = String::from("axis,layer,voxels\n");
+                for (name, layers) in names.iter().zip(&occupancy.layers) {
+                    for (layer, count) in layers {
+                        contents.push_str(&format!("{},{},{}\n", name, layer, count));
+                    }
+                }
+                write_output(&path, &contents, false)?;
+            }
+        }
+        Commands::Diff {
+            old,
+            new,
+            output,
+            size,
+            material,
+            scale,
+            hollow,
+            smooth,
+        } => {
+            let load = |path: &Path| {
+                load_mesh(path)
+                    .with_context(|| format!("failed to load input mesh: {}", path.display()))
+                    .and_then(|mesh| prepare_mesh(mesh, false))
+            };
+            let (old_mesh, new_mesh) = (load(&old)?, load(&new)?);
+
+            let scale = scale.mode(None, None);
+            let options = GenerateOptions {
+                size,
+                // Not used, the diff isn't a blueprint.
+                core_type: CoreType::Static,
+                material,
+                interior_material: None,
+                scale,
+                anchor: Anchor::Center,
+                hollow,
+                lattice: None,
+                crop: None,
+                collapse_solid: false,
+                smooth,
+                fill_mode: FillMode::default(),
+                samples: 1,
+                sample_rule: SampleRule::default(),
+                mirror: None,
+                symmetry: None,
+                origin: None,
+                tile: None,
+                cancel: None,
+                cache: None,
+            };
+            // Both meshes are placed using their combined bounds, so unchanged regions line up.
+            let isometry = Isometry::identity();
+            let mesh_aabb = old_mesh.aabb(&isometry).merged(&new_mesh.aabb(&isometry));
+            let voxelize = |mesh, path: &Path| {
+                voxelize_mesh(mesh, Vec::new(), isometry, &mesh_aabb, &options)
+                    .with_context(|| format!("failed to voxelize: {}", path.display()))
+            };
+            let old_svo = voxelize(old_mesh, &old)?;
+            let new_svo = voxelize(new_mesh, &new)?;
+            let diff = old_svo.diff(&new_svo)?;
+            info!(
+                "{} voxels added or changed, {} removed",
+                diff.materials.values().map(Vec::len).sum::<usize>(),
+                diff.removed.len()
+            );
+            write_output(&output, &diff.to_import_json().to_string(), false)?;
+        }
+        Commands::ParseVoxel {
+            b64,
+            file,
+            obj,
+            json,
+        } => {
+            let bytes = base64::prelude::BASE64_STANDARD
+                .decode(read_base64(b64, file)?)
+                .context("failed to decode base64 voxel chunk")?;
             let voxel = VoxelCellData::decompress(&bytes);
-            println!("{:#?}", voxel);
+            if json {
+                // The chunk is still needed for --obj, so it can't be moved into the error.
+                let voxel = voxel
+                    .as_ref()
+                    .map_err(|e| anyhow!("failed to parse voxel chunk: {}", e))?;
+                println!("{}", serde_json::to_string_pretty(&voxel.to_json())?);
+            } else {
+                println!("{:#?}", voxel);
+            }
+            if let Some(path) = obj {
+                let voxel = voxel.context("failed to parse voxel chunk")?;
+                File::create(&path)
+                    .map(BufWriter::new)
+                    .and_then(|mut file| write_obj(&voxel, &mut file).and_then(|_| file.flush()))
+                    .with_context(|| format!("failed to write OBJ: {}", path.display()))?;
+            }
         }
-        Commands::ParseMeta { b64 } => {
-            let bytes = base64::prelude::BASE64_STANDARD.decode(b64).unwrap();
+        Commands::EditVoxel {
+            b64,
+            position,
+            material,
+        } => {
+            let bytes = base64::prelude::BASE64_STANDARD
+                .decode(b64)
+                .context("failed to decode base64 voxel chunk")?;
+            let mut voxel =
+                VoxelCellData::decompress(&bytes).context("failed to parse voxel chunk")?;
+            if !voxel.grid.range().contains_point(position) {
+                bail!("position is outside of the chunk's grid");
+            }
+            if voxel.mapping().get(material).is_none() {
+                bail!("the chunk has no material with index {}", material);
+            }
+            voxel.set_material_at_position(position, material);
+            let bytes = voxel.compress().context("failed to encode voxel chunk")?;
+            println!("{}", base64::prelude::BASE64_STANDARD.encode(bytes));
+        }
+        Commands::ParseMeta { b64, file, json } => {
+            let bytes = base64::prelude::BASE64_STANDARD
+                .decode(read_base64(b64, file)?)
+                .context("failed to decode base64 meta chunk")?;
             let meta = AggregateMetadata::decompress(&bytes);
-            println!("{:#?}", meta);
+            if json {
+                let meta = meta.context("failed to parse meta chunk")?;
+                println!("{}", serde_json::to_string_pretty(&meta.to_json())?);
+            } else {
+                println!("{:#?}", meta);
+            }
         }
     }
+    Ok(())
 }
@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Result, Write};
+
+use parry3d_f64::math::{Point, Vector};
+
+use crate::squarion::*;
+
+// The corners of each face of a unit voxel, wound counter-clockwise when seen from outside,
+// along with the direction the face points in.
+const FACES: [([i32; 3], [[i32; 3]; 4]); 6] = [
+    ([1, 0, 0], [[1, 0, 0], [1, 1, 0], [1, 1, 1], [1, 0, 1]]),
+    ([-1, 0, 0], [[0, 0, 0], [0, 0, 1], [0, 1, 1], [0, 1, 0]]),
+    ([0, 1, 0], [[0, 1, 0], [0, 1, 1], [1, 1, 1], [1, 1, 0]]),
+    ([0, -1, 0], [[0, 0, 0], [1, 0, 0], [1, 0, 1], [0, 0, 1]]),
+    ([0, 0, 1], [[0, 0, 1], [1, 0, 1], [1, 1, 1], [0, 1, 1]]),
+    ([0, 0, -1], [[0, 0, 0], [0, 1, 0], [1, 1, 0], [1, 0, 0]]),
+];
+
+/// Writes the surface of a voxel cell as a Wavefront OBJ, in meters. Every face of a voxel
+/// that borders an empty voxel is emitted, with its corners moved by the stored vertex offsets.
+pub fn write_obj(cell: &VoxelCellData, writer: &mut impl Write) -> Result<()> {
+    let grid = &cell.grid;
+    let mut vertex_indices = HashMap::new();
+    let mut vertices = Vec::new();
+    let mut faces = BTreeMap::<u8, Vec<[usize; 4]>>::new();
+
+    grid.for_each_material(|pos, material| {
+        for (direction, corners) in &FACES {
+            // Materials are placed on the +[1, 1, 1] vertex of their voxel.
+            let neighbor = pos + Vector::from(*direction);
+            if grid.material_at(&neighbor).is_some() {
+                continue;
+            }
+            let voxel = pos - Vector::repeat(1);
+            let face = corners.map(|corner| {
+                let point = voxel + Vector::from(corner);
+                *vertex_indices.entry(point).or_insert_with(|| {
+                    vertices.push(vertex_position(grid, &point));
+                    vertices.len()
+                })
+            });
+            faces.entry(material).or_default().push(face);
+        }
+    });
+
+    for vertex in &vertices {
+        writeln!(writer, "v {} {} {}", vertex.x, vertex.y, vertex.z)?;
+    }
+    for (material, faces) in &faces {
+        match cell.mapping().get(*material) {
+            Some(material_id) => writeln!(writer, "usemtl {}", material_id.id)?,
+            None => writeln!(writer, "usemtl {}", material)?,
+        }
+        for [a, b, c, d] in faces {
+            writeln!(writer, "f {} {} {} {}", a, b, c, d)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a set of equal sized boxes as a Wavefront OBJ, with `size` the length of a box's side
+/// in meters. Faces shared by two boxes are left out, so only the outline is drawn.
+pub fn write_boxes_obj(
+    boxes: &HashSet<Point<i32>>,
+    size: f64,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let mut vertex_indices = HashMap::new();
+    let mut vertices = Vec::new();
+    let mut faces = Vec::new();
+    for pos in boxes {
+        for (direction, corners) in &FACES {
+            if boxes.contains(&(pos + Vector::from(*direction))) {
+                continue;
+            }
+            let face = corners.map(|corner| {
+                let point = pos + Vector::from(corner);
+                *vertex_indices.entry(point).or_insert_with(|| {
+                    vertices.push(point.map(|v| v as f64 * size));
+                    vertices.len()
+                })
+            });
+            faces.push(face);
+        }
+    }
+
+    for vertex in &vertices {
+        writeln!(writer, "v {} {} {}", vertex.x, vertex.y, vertex.z)?;
+    }
+    for [a, b, c, d] in &faces {
+        writeln!(writer, "f {} {} {} {}", a, b, c, d)?;
+    }
+    Ok(())
+}
+
+// Offsets are in 1/84ths of a voxel, centered on 126.
+fn vertex_position(grid: &VertexGrid, point: &Point<i32>) -> Point<f64> {
+    let offset = match grid.voxel_at(point) {
+        Some(offset) => Vector::from(offset.map(|v| (v as f64 - 126.0) / 84.0)),
+        None => Vector::zeros(),
+    };
+    (point.map(|v| v as f64) + offset) * 0.25
+}
@@ -0,0 +1,1106 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader, Error, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use parry3d_f64::math::Point;
+use parry3d_f64::na::Matrix4;
+use parry3d_f64::shape::TriMesh;
+use tobj::LoadOptions;
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum MeshImportError {
+    Internal(Error),
+    Obj(tobj::LoadError),
+    Gltf(gltf::Error),
+    Zip(zip::result::ZipError),
+    Xml(roxmltree::Error),
+    BadData(String),
+    Empty,
+}
+
+impl std::fmt::Display for MeshImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeshImportError::Internal(e) => write!(f, "{}", e),
+            MeshImportError::Obj(e) => write!(f, "{}", e),
+            MeshImportError::Gltf(e) => write!(f, "{}", e),
+            MeshImportError::Zip(e) => write!(f, "{}", e),
+            MeshImportError::Xml(e) => write!(f, "{}", e),
+            MeshImportError::BadData(message) => write!(f, "{}", message),
+            MeshImportError::Empty => write!(f, "mesh has no triangles"),
+        }
+    }
+}
+
+impl std::error::Error for MeshImportError {}
+
+impl From<Error> for MeshImportError {
+    fn from(value: Error) -> Self {
+        MeshImportError::Internal(value)
+    }
+}
+
+impl From<tobj::LoadError> for MeshImportError {
+    fn from(value: tobj::LoadError) -> Self {
+        MeshImportError::Obj(value)
+    }
+}
+
+impl From<gltf::Error> for MeshImportError {
+    fn from(value: gltf::Error) -> Self {
+        MeshImportError::Gltf(value)
+    }
+}
+
+impl From<zip::result::ZipError> for MeshImportError {
+    fn from(value: zip::result::ZipError) -> Self {
+        MeshImportError::Zip(value)
+    }
+}
+
+impl From<roxmltree::Error> for MeshImportError {
+    fn from(value: roxmltree::Error) -> Self {
+        MeshImportError::Xml(value)
+    }
+}
+
+fn lowercase_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+}
+
+/// Loads a mesh, picking the format from the file extension. Anything that isn't
+/// recognized is treated as an obj file. Gzipped obj files (.obj.gz) are decompressed first.
+pub fn load_mesh(path: &Path) -> Result<TriMesh, MeshImportError> {
+    match lowercase_extension(path).as_deref() {
+        Some("stl") => load_stl(path),
+        Some("gltf") | Some("glb") => load_gltf(path),
+        Some("ply") => Ok(load_ply(path)?.mesh()),
+        Some("off") => load_off(path),
+        Some("3mf") => load_3mf(path),
+        Some("gz") => load_obj_gz(path),
+        _ => load_obj(path),
+    }
+}
+
+// The other loaders read from a path, so only obj files can be compressed.
+fn load_obj_gz(path: &Path) -> Result<TriMesh, MeshImportError> {
+    let inner = Path::new(path.file_stem().unwrap_or_default());
+    if let Some(extension @ ("stl" | "gltf" | "glb" | "ply" | "off" | "3mf")) =
+        lowercase_extension(inner).as_deref()
+    {
+        return Err(MeshImportError::BadData(format!(
+            "compressed .{} files aren't supported, only .obj.gz",
+            extension
+        )));
+    }
+    let mut reader = BufReader::new(GzDecoder::new(fs::File::open(path)?));
+    merge_parts(&load_obj_parts_from_reader(
+        &mut reader,
+        Triangulation::Fan,
+    )?)
+}
+
+pub fn load_obj(path: &Path) -> Result<TriMesh, MeshImportError> {
+    let mut mesh: Option<TriMesh> = None;
+    for (_, part) in load_obj_parts(path)? {
+        append_mesh(&mut mesh, part);
+    }
+    mesh.ok_or(MeshImportError::Empty)
+}
+
+/// How obj faces with more than three corners are split into triangles.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Triangulation {
+    /// A fan from the first corner. Fast, but concave faces get slivers that overlap or are
+    /// deleted as degenerate, leaving holes.
+    #[default]
+    Fan,
+    /// Ear clipping, which handles concave faces.
+    EarClipping,
+}
+
+// tobj only knows how to fan, so for ear clipping the faces are kept whole and split later.
+fn obj_load_options(triangulation: Triangulation) -> LoadOptions {
+    LoadOptions {
+        merge_identical_points: true,
+        triangulate: triangulation == Triangulation::Fan,
+        ..Default::default()
+    }
+}
+
+/// Loads an obj file as one mesh per material, keyed by the material name. Models without a
+/// material are keyed by their object name instead. Parts are in the order they first appear.
+pub fn load_obj_parts(path: &Path) -> Result<Vec<(String, TriMesh)>, MeshImportError> {
+    Ok(load_obj_parts_with_colors(path, Triangulation::Fan)?.0)
+}
+
+/// Same as `load_obj_parts`, along with the diffuse color (`Kd`) of every material in the
+/// material library that has one, keyed by material name. The alpha comes from the dissolve
+/// (`d`) of the material, and is opaque if it has none.
+pub fn load_obj_parts_with_colors(
+    path: &Path,
+    triangulation: Triangulation,
+) -> Result<(Vec<(String, TriMesh)>, HashMap<String, [u8; 4]>), MeshImportError> {
+    let (models, materials) = tobj::load_obj(path, &obj_load_options(triangulation))?;
+    let materials = materials.unwrap_or_default();
+    let colors = HashMap::from_iter(materials.iter().filter_map(|material| {
+        let [r, g, b] = material.diffuse?;
+        let color = [r, g, b, material.dissolve.unwrap_or(1.0)]
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        Some((material.name.clone(), color))
+    }));
+    Ok((obj_parts(models, materials)?, colors))
+}
+
+/// Like `load_obj_parts`, but reads the obj file from `reader`, e.g. stdin. There is no path
+/// to find the material library from, so parts are always keyed by object name.
+pub fn load_obj_parts_from_reader(
+    reader: &mut impl BufRead,
+    triangulation: Triangulation,
+) -> Result<Vec<(String, TriMesh)>, MeshImportError> {
+    let (models, _) = tobj::load_obj_buf(reader, &obj_load_options(triangulation), |_| {
+        Err(tobj::LoadError::OpenFileFailed)
+    })?;
+    obj_parts(models, Vec::new())
+}
+
+/// Joins parts back into a single mesh.
+pub fn merge_parts(parts: &[(String, TriMesh)]) -> Result<TriMesh, MeshImportError> {
+    let mut mesh: Option<TriMesh> = None;
+    for (_, part) in parts {
+        match &mut mesh {
+            Some(mesh) => mesh.append(part),
+            None => mesh = Some(part.clone()),
+        }
+    }
+    mesh.ok_or(MeshImportError::Empty)
+}
+
+// A missing material library only loses the names, so this falls back to the object names.
+fn obj_parts(
+    models: Vec<tobj::Model>,
+    materials: Vec<tobj::Material>,
+) -> Result<Vec<(String, TriMesh)>, MeshImportError> {
+    let mut parts: Vec<(String, TriMesh)> = Vec::new();
+    for model in models {
+        let vertices = Vec::from_iter(
+            model
+                .mesh
+                .positions
+                .chunks_exact(3)
+                .map(|x| Point::from_slice(&[x[0] as f64, x[1] as f64, x[2] as f64])),
+        );
+        let indices = triangulate_faces(&vertices, &model.mesh.indices, &model.mesh.face_arities);
+        if indices.is_empty() {
+            continue;
+        }
+        let name = match model.mesh.material_id.and_then(|i| materials.get(i)) {
+            Some(material) => material.name.clone(),
+            None => model.name,
+        };
+        let mesh = TriMesh::new(vertices, indices);
+        match parts.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, part)) => part.append(&mesh),
+            None => parts.push((name, mesh)),
+        }
+    }
+    if parts.is_empty() {
+        return Err(MeshImportError::Empty);
+    }
+    Ok(parts)
+}
+
+// Without face arities every face is already a triangle.
+fn triangulate_faces(
+    vertices: &[Point<f64>],
+    indices: &[u32],
+    face_arities: &[u32],
+) -> Vec<[u32; 3]> {
+    if face_arities.is_empty() {
+        return Vec::from_iter(indices.chunks_exact(3).map(|c| [c[0], c[1], c[2]]));
+    }
+    let mut triangles = Vec::new();
+    let mut start = 0;
+    for arity in face_arities {
+        let end = start + *arity as usize;
+        ear_clip(vertices, &indices[start..end], &mut triangles);
+        start = end;
+    }
+    triangles
+}
+
+// Splits one planar face into triangles with the same winding, by repeatedly cutting off a corner
+// that doesn't contain any other corner.
+fn ear_clip(vertices: &[Point<f64>], face: &[u32], triangles: &mut Vec<[u32; 3]>) {
+    if face.len() < 3 {
+        return;
+    }
+    // Newell's method gives the face normal even for concave faces. Dropping the axis it points
+    // along most flattens the face to 2D, and flipping the other two axes for a negative normal
+    // makes the corners counter-clockwise.
+    let mut normal = Vector::<f64>::zeros();
+    for (i, a) in face.iter().enumerate() {
+        let a = vertices[*a as usize];
+        let b = vertices[face[(i + 1) % face.len()] as usize];
+        normal += Vector::new(
+            (a.y - b.y) * (a.z + b.z),
+            (a.z - b.z) * (a.x + b.x),
+            (a.x - b.x) * (a.y + b.y),
+        );
+    }
+    let axis = normal.iamax();
+    let sign = normal[axis].signum();
+    let point = |index: u32| {
+        let p = vertices[index as usize];
+        [p[(axis + 1) % 3], p[(axis + 2) % 3] * sign]
+    };
+    let cross = |o: [f64; 2], a: [f64; 2], b: [f64; 2]| {
+        (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+    };
+
+    let mut remaining = face.to_vec();
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let corner = |i: usize| {
+            (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            )
+        };
+        let ear = (0..n).find(|i| {
+            let (prev, current, next) = corner(*i);
+            let (a, b, c) = (point(prev), point(current), point(next));
+            if cross(a, b, c) <= 0.0 {
+                return false;
+            }
+            !remaining.iter().any(|other| {
+                let p = point(*other);
+                ![prev, current, next].contains(other)
+                    && cross(a, b, p) >= 0.0
+                    && cross(b, c, p) >= 0.0
+                    && cross(c, a, p) >= 0.0
+            })
+        });
+        // Self-intersecting faces can run out of ears, so those are just cut anywhere.
+        let ear = ear.unwrap_or(0);
+        let (prev, current, next) = corner(ear);
+        triangles.push([prev, current, next]);
+        remaining.remove(ear);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+}
+
+fn append_mesh(mesh: &mut Option<TriMesh>, sub_mesh: TriMesh) {
+    match mesh {
+        Some(mesh) => mesh.append(&sub_mesh),
+        None => *mesh = Some(sub_mesh),
+    }
+}
+
+/// Counts the edges that aren't shared by exactly two triangles. A closed mesh has none, and
+/// anything else can't be reliably filled. Edges are matched by position, since formats like
+/// glTF split vertices along hard edges.
+pub fn open_edge_count(mesh: &TriMesh) -> usize {
+    let key = |i: u32| {
+        let p = mesh.vertices()[i as usize];
+        [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]
+    };
+    let mut edges = HashMap::<_, u32>::new();
+    for triangle in mesh.indices() {
+        for i in 0..3 {
+            let (a, b) = (key(triangle[i]), key(triangle[(i + 1) % 3]));
+            *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+    edges.values().filter(|count| **count != 2).count()
+}
+
+/// Whether the mesh has no thickness along one of its axes, like a flat sign or decal. Such a
+/// mesh is open, but it has no inside to get wrong, so it voxelizes without `open_edge_count`
+/// mattering.
+pub fn is_flat(mesh: &TriMesh) -> bool {
+    mesh.local_aabb().extents().min() == 0.0
+}
+
+/// Merges vertices that are within `tolerance` of each other, closing the tiny cracks CAD
+/// exports leave between faces. Each vertex joins the first earlier vertex in range, and
+/// triangles that collapse to a line or a point are dropped.
+pub fn weld_vertices(mesh: &TriMesh, tolerance: f64) -> TriMesh {
+    // Vertices are bucketed on a grid as wide as the tolerance, so any vertex in range is in one
+    // of the 27 buckets around it.
+    let bucket = |p: &Point<f64>| p.coords.map(|c| (c / tolerance).floor() as i64);
+    let mut buckets = HashMap::<[i64; 3], Vec<u32>>::new();
+    let mut vertices = Vec::<Point<f64>>::new();
+    let mut remap = Vec::with_capacity(mesh.vertices().len());
+    for vertex in mesh.vertices() {
+        let key = bucket(vertex);
+        let mut found = None;
+        'search: for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let neighbour = [key.x + x, key.y + y, key.z + z];
+                    let Some(candidates) = buckets.get(&neighbour) else {
+                        continue;
+                    };
+                    found = candidates
+                        .iter()
+                        .find(|i| (vertices[**i as usize] - vertex).norm() <= tolerance)
+                        .copied();
+                    if found.is_some() {
+                        break 'search;
+                    }
+                }
+            }
+        }
+        let index = found.unwrap_or_else(|| {
+            vertices.push(*vertex);
+            let index = (vertices.len() - 1) as u32;
+            buckets.entry(key.into()).or_default().push(index);
+            index
+        });
+        remap.push(index);
+    }
+    let indices = Vec::from_iter(
+        mesh.indices()
+            .iter()
+            .map(|triangle| triangle.map(|i| remap[i as usize]))
+            .filter(|[a, b, c]| a != b && b != c && a != c),
+    );
+    TriMesh::new(vertices, indices)
+}
+
+/// Reflects the mesh across the plane through the origin perpendicular to `axis`. The triangles
+/// are rewound so they still face outwards.
+pub fn mirror_mesh(mesh: &TriMesh, axis: usize) -> TriMesh {
+    let vertices = Vec::from_iter(mesh.vertices().iter().map(|v| {
+        let mut v = *v;
+        v[axis] = -v[axis];
+        v
+    }));
+    let indices = Vec::from_iter(mesh.indices().iter().map(|[a, b, c]| [*a, *c, *b]));
+    TriMesh::new(vertices, indices)
+}
+
+/// Counts the triangles that face the opposite way to a neighbour, i.e. share an edge that runs in
+/// the same direction in both. These are what stop a mesh from being oriented.
+pub fn misoriented_triangle_count(mesh: &TriMesh) -> usize {
+    let key = |i: u32| {
+        let p = mesh.vertices()[i as usize];
+        [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]
+    };
+    let mut edges = HashMap::<_, Vec<usize>>::new();
+    for (index, triangle) in mesh.indices().iter().enumerate() {
+        for i in 0..3 {
+            let edge = (key(triangle[i]), key(triangle[(i + 1) % 3]));
+            edges.entry(edge).or_default().push(index);
+        }
+    }
+    let bad = HashSet::<usize>::from_iter(
+        edges
+            .into_values()
+            .filter(|triangles| triangles.len() > 1)
+            .flatten(),
+    );
+    bad.len()
+}
+
+/// Loads every mesh in the default scene, with the node transforms baked into the vertices.
+pub fn load_gltf(path: &Path) -> Result<TriMesh, MeshImportError> {
+    let (document, buffers, _) = gltf::import(path)?;
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or(MeshImportError::Empty)?;
+
+    let mut mesh = None;
+    for node in scene.nodes() {
+        load_gltf_node(&node, &Matrix4::identity(), &buffers, &mut mesh);
+    }
+    mesh.ok_or(MeshImportError::Empty)
+}
+
+fn load_gltf_node(
+    node: &gltf::Node,
+    parent_transform: &Matrix4<f64>,
+    buffers: &[gltf::buffer::Data],
+    mesh: &mut Option<TriMesh>,
+) {
+    let local_transform = Matrix4::from(node.transform().matrix()).cast::<f64>();
+    let transform = parent_transform * local_transform;
+    // A mirrored transform flips the winding order, which would turn the mesh inside out.
+    let flip_winding = transform.fixed_view::<3, 3>(0, 0).determinant() < 0.0;
+
+    if let Some(gltf_mesh) = node.mesh() {
+        for primitive in gltf_mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let vertices = Vec::from_iter(positions.map(|p| {
+                transform.transform_point(&Point::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            }));
+            let flat_indices = match reader.read_indices() {
+                Some(indices) => Vec::from_iter(indices.into_u32()),
+                None => Vec::from_iter(0..vertices.len() as u32),
+            };
+            let indices = Vec::from_iter(flat_indices.chunks_exact(3).map(|c| {
+                if flip_winding {
+                    [c[0], c[2], c[1]]
+                } else {
+                    [c[0], c[1], c[2]]
+                }
+            }));
+            if indices.is_empty() {
+                continue;
+            }
+            append_mesh(mesh, TriMesh::new(vertices, indices));
+        }
+    }
+
+    for child in node.children() {
+        load_gltf_node(&child, &transform, buffers, mesh);
+    }
+}
+
+pub fn load_stl(path: &Path) -> Result<TriMesh, MeshImportError> {
+    let bytes = fs::read(path)?;
+    let triangles = if is_binary_stl(&bytes) {
+        parse_binary_stl(&bytes)?
+    } else if bytes.starts_with(b"solid") {
+        parse_ascii_stl(&bytes)?
+    } else {
+        return Err(MeshImportError::BadData(
+            "file is neither a binary nor an ASCII STL".into(),
+        ));
+    };
+    if triangles.is_empty() {
+        return Err(MeshImportError::Empty);
+    }
+    Ok(weld_triangles(&triangles))
+}
+
+/// Loads an ASCII OFF file. Polygons are fanned into triangles, and any face colors are ignored.
+pub fn load_off(path: &Path) -> Result<TriMesh, MeshImportError> {
+    let text = fs::read_to_string(path)?;
+    let bad_data = |message: &str| MeshImportError::BadData(format!("bad OFF file: {}", message));
+    let mut lines = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty());
+
+    // The counts are allowed on the same line as the magic.
+    let mut header = lines.next().unwrap_or_default().split_whitespace();
+    if header.next() != Some("OFF") {
+        return Err(MeshImportError::BadData("file is not an OFF file".into()));
+    }
+    let mut counts = Vec::from_iter(header);
+    if counts.is_empty() {
+        counts = Vec::from_iter(lines.next().unwrap_or_default().split_whitespace());
+    }
+    let count = |i: usize| -> Result<usize, MeshImportError> {
+        let count = counts.get(i).ok_or_else(|| bad_data("missing counts"))?;
+        count.parse().map_err(|_| bad_data("bad counts"))
+    };
+    let (vertex_count, face_count) = (count(0)?, count(1)?);
+
+    let mut vertices = Vec::with_capacity(vertex_count);
+    for _ in 0..vertex_count {
+        let line = lines.next().ok_or_else(|| bad_data("missing vertices"))?;
+        let coords = line
+            .split_whitespace()
+            .take(3)
+            .map(|t| t.parse::<f64>().map_err(|_| bad_data(line)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if coords.len() != 3 {
+            return Err(bad_data(line));
+        }
+        vertices.push(Point::new(coords[0], coords[1], coords[2]));
+    }
+
+    let mut indices = Vec::with_capacity(face_count);
+    for _ in 0..face_count {
+        let line = lines.next().ok_or_else(|| bad_data("missing faces"))?;
+        let mut tokens = line.split_whitespace().map(|t| t.parse::<u32>());
+        let size = tokens
+            .next()
+            .and_then(|t| t.ok())
+            .ok_or_else(|| bad_data(line))?;
+        // Anything after the indices is a color.
+        let face = tokens
+            .take(size as usize)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| bad_data(line))?;
+        if face.len() != size as usize || face.iter().any(|i| *i as usize >= vertex_count) {
+            return Err(bad_data(line));
+        }
+        for i in 1..face.len().saturating_sub(1) {
+            indices.push([face[0], face[i], face[i + 1]]);
+        }
+    }
+    if indices.is_empty() {
+        return Err(MeshImportError::Empty);
+    }
+    Ok(TriMesh::new(vertices, indices))
+}
+
+// The model part of a 3MF file, with the objects keyed by id.
+struct ThreeMfModel {
+    objects: HashMap<u32, ThreeMfObject>,
+    // The objects to build, and where.
+    build: Vec<(u32, Matrix4<f64>)>,
+}
+
+#[derive(Default)]
+struct ThreeMfObject {
+    vertices: Vec<Point<f64>>,
+    triangles: Vec<[u32; 3]>,
+    // Other objects placed inside this one.
+    components: Vec<(u32, Matrix4<f64>)>,
+}
+
+// Components can nest, so this stops a file with a cycle from recursing forever.
+const MAX_3MF_DEPTH: usize = 32;
+
+/// Loads every object in the build of a 3MF file, with the item and component transforms baked
+/// into the vertices. Only the geometry is read, colors and materials are ignored.
+pub fn load_3mf(path: &Path) -> Result<TriMesh, MeshImportError> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    let model_path = find_3mf_model(&mut archive)?;
+    let mut xml = String::new();
+    archive.by_name(&model_path)?.read_to_string(&mut xml)?;
+    let model = parse_3mf_model(&xml)?;
+
+    let mut mesh = None;
+    for (object_id, transform) in &model.build {
+        model.append_object(*object_id, transform, 0, &mut mesh)?;
+    }
+    mesh.ok_or(MeshImportError::Empty)
+}
+
+// The package relationships say where the model is. Almost every file uses the default path,
+// so that's the fallback.
+fn find_3mf_model(archive: &mut zip::ZipArchive<fs::File>) -> Result<String, MeshImportError> {
+    let mut rels = String::new();
+    if let Ok(mut file) = archive.by_name("_rels/.rels") {
+        file.read_to_string(&mut rels)?;
+    }
+    if !rels.is_empty() {
+        let document = roxmltree::Document::parse(&rels)?;
+        let target = document
+            .descendants()
+            .filter(|n| n.tag_name().name() == "Relationship")
+            .find(|n| n.attribute("Type").is_some_and(|t| t.ends_with("/3dmodel")))
+            .and_then(|n| n.attribute("Target"));
+        if let Some(target) = target {
+            return Ok(target.trim_start_matches('/').to_string());
+        }
+    }
+    Ok("3D/3dmodel.model".to_string())
+}
+
+fn bad_3mf(message: String) -> MeshImportError {
+    MeshImportError::BadData(format!("bad 3MF file: {}", message))
+}
+
+fn parse_3mf_attribute<T: std::str::FromStr>(
+    node: &roxmltree::Node,
+    name: &str,
+) -> Result<T, MeshImportError> {
+    let value = node
+        .attribute(name)
+        .ok_or_else(|| bad_3mf(format!("{} is missing {}", node.tag_name().name(), name)))?;
+    value
+        .trim()
+        .parse()
+        .map_err(|_| bad_3mf(format!("bad {} {:?}", name, value)))
+}
+
+fn parse_3mf_model(xml: &str) -> Result<ThreeMfModel, MeshImportError> {
+    let number = parse_3mf_attribute::<f64>;
+    let id = parse_3mf_attribute::<u32>;
+    let transform = |node: &roxmltree::Node| match node.attribute("transform") {
+        Some(value) => parse_3mf_transform(value).ok_or_else(|| bad_3mf(value.to_string())),
+        None => Ok(Matrix4::identity()),
+    };
+
+    let document = roxmltree::Document::parse(xml)?;
+    let mut objects = HashMap::new();
+    let mut build = Vec::new();
+    for node in document.descendants() {
+        match node.tag_name().name() {
+            "object" => {
+                let object_id = id(&node, "id")?;
+                let mut object = ThreeMfObject::default();
+                for child in node.descendants() {
+                    match child.tag_name().name() {
+                        "vertex" => object.vertices.push(Point::new(
+                            number(&child, "x")?,
+                            number(&child, "y")?,
+                            number(&child, "z")?,
+                        )),
+                        "triangle" => {
+                            let triangle =
+                                [id(&child, "v1")?, id(&child, "v2")?, id(&child, "v3")?];
+                            object.triangles.push(triangle);
+                        }
+                        "component" => object
+                            .components
+                            .push((id(&child, "objectid")?, transform(&child)?)),
+                        _ => (),
+                    }
+                }
+                let vertex_count = object.vertices.len() as u32;
+                if object
+                    .triangles
+                    .iter()
+                    .flatten()
+                    .any(|i| *i >= vertex_count)
+                {
+                    return Err(bad_3mf(format!("object {} has a bad triangle", object_id)));
+                }
+                objects.insert(object_id, object);
+            }
+            "item" => build.push((id(&node, "objectid")?, transform(&node)?)),
+            _ => (),
+        }
+    }
+    Ok(ThreeMfModel { objects, build })
+}
+
+// 3MF transforms are the first three columns of a row-major 4x4 matrix that multiplies row
+// vectors, so they're transposed into the column vector form nalgebra uses.
+fn parse_3mf_transform(value: &str) -> Option<Matrix4<f64>> {
+    let m = value
+        .split_whitespace()
+        .map(|t| t.parse::<f64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if m.len() != 12 {
+        return None;
+    }
+    Some(Matrix4::new(
+        m[0], m[3], m[6], m[9], m[1], m[4], m[7], m[10], m[2], m[5], m[8], m[11], 0.0, 0.0, 0.0,
+        1.0,
+    ))
+}
+
+impl ThreeMfModel {
+    fn append_object(
+        &self,
+        object_id: u32,
+        transform: &Matrix4<f64>,
+        depth: usize,
+        mesh: &mut Option<TriMesh>,
+    ) -> Result<(), MeshImportError> {
+        if depth > MAX_3MF_DEPTH {
+            return Err(bad_3mf("components are nested too deeply".into()));
+        }
+        let object = self
+            .objects
+            .get(&object_id)
+            .ok_or_else(|| bad_3mf(format!("no object {}", object_id)))?;
+
+        if !object.triangles.is_empty() {
+            // A mirrored transform flips the winding order, which would turn the mesh inside out.
+            let flip_winding = transform.fixed_view::<3, 3>(0, 0).determinant() < 0.0;
+            let vertices =
+                Vec::from_iter(object.vertices.iter().map(|p| transform.transform_point(p)));
+            let indices = Vec::from_iter(object.triangles.iter().map(|&[a, b, c]| {
+                if flip_winding {
+                    [a, c, b]
+                } else {
+                    [a, b, c]
+                }
+            }));
+            append_mesh(mesh, TriMesh::new(vertices, indices));
+        }
+        for (component_id, component_transform) in &object.components {
+            let transform = transform * component_transform;
+            self.append_object(*component_id, &transform, depth + 1, mesh)?;
+        }
+        Ok(())
+    }
+}
+
+// Some exporters write binary files that start with "solid" anyway, so the size check
+// is the only reliable way to tell the two variants apart.
+fn is_binary_stl(bytes: &[u8]) -> bool {
+    if bytes.len() < 84 {
+        return false;
+    }
+    let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+    bytes.len() == 84 + count * 50
+}
+
+fn parse_binary_stl(bytes: &[u8]) -> Result<Vec<[[f32; 3]; 3]>, MeshImportError> {
+    let read_f32 =
+        |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+    let mut triangles = Vec::with_capacity(count);
+    for i in 0..count {
+        // Each record is a normal, three vertices and a 2 byte attribute count. We skip the
+        // normal since the winding order is what the voxelizer cares about.
+        let record = 84 + i * 50 + 12;
+        let vertex = |v: usize| {
+            let offset = record + v * 12;
+            [read_f32(offset), read_f32(offset + 4), read_f32(offset + 8)]
+        };
+        triangles.push([vertex(0), vertex(1), vertex(2)]);
+    }
+    Ok(triangles)
+}
+
+fn parse_ascii_stl(bytes: &[u8]) -> Result<Vec<[[f32; 3]; 3]>, MeshImportError> {
+    let text = std::str::from_utf8(bytes)
+        .map_err(|_| MeshImportError::BadData("ASCII STL is not valid UTF-8".into()))?;
+    let mut triangles = Vec::new();
+    let mut facet = Vec::with_capacity(3);
+    for line in text.lines() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("vertex") => {
+                let mut vertex = [0.0; 3];
+                for v in vertex.iter_mut() {
+                    *v = tokens
+                        .next()
+                        .and_then(|t| t.parse::<f32>().ok())
+                        .ok_or_else(|| MeshImportError::BadData(format!("bad vertex: {}", line)))?;
+                }
+                facet.push(vertex);
+            }
+            Some("endloop") => {
+                // Polygons with more than three vertices are allowed by some exporters, so fan them.
+                for i in 1..facet.len().saturating_sub(1) {
+                    triangles.push([facet[0], facet[i], facet[i + 1]]);
+                }
+                facet.clear();
+            }
+            _ => (),
+        }
+    }
+    Ok(triangles)
+}
+
+// STL has no shared vertices, so weld exact duplicates to get a connected mesh.
+fn weld_triangles(triangles: &[[[f32; 3]; 3]]) -> TriMesh {
+    let mut lookup = HashMap::<[u32; 3], u32>::new();
+    let mut vertices = Vec::new();
+    let mut indices = Vec::with_capacity(triangles.len());
+    for triangle in triangles {
+        let face = triangle.map(|v| {
+            // Adding 0.0 folds -0.0 into 0.0 so they hash the same.
+            let key = v.map(|c| (c + 0.0).to_bits());
+            *lookup.entry(key).or_insert_with(|| {
+                vertices.push(Point::new(v[0] as f64, v[1] as f64, v[2] as f64));
+                (vertices.len() - 1) as u32
+            })
+        });
+        indices.push(face);
+    }
+    TriMesh::new(vertices, indices)
+}
+
+/// The triangles of a PLY file, with the RGBA vertex colors if it has them.
+pub struct PlyMesh {
+    pub vertices: Vec<Point<f64>>,
+    pub triangles: Vec<[u32; 3]>,
+    pub colors: Option<Vec<[u8; 4]>>,
+}
+
+impl PlyMesh {
+    pub fn mesh(&self) -> TriMesh {
+        TriMesh::new(self.vertices.clone(), self.triangles.clone())
+    }
+
+    /// Splits the mesh into one part per palette entry, giving each face the entry nearest to
+    /// its average vertex color. Faces whose average alpha is below `alpha_threshold` go in a
+    /// part of their own with no entry. Entries without any faces are left out. Returns `None`
+    /// if the file has no colors or the palette is empty.
+    pub fn split_by_palette(
+        &self,
+        palette: &[[u8; 3]],
+        alpha_threshold: u8,
+    ) -> Option<Vec<(Option<usize>, TriMesh)>> {
+        let colors = self.colors.as_ref()?;
+        let mut parts = BTreeMap::<Option<usize>, (Vec<Point<f64>>, Vec<[u32; 3]>)>::new();
+        let mut lookup = HashMap::<(Option<usize>, u32), u32>::new();
+        for triangle in &self.triangles {
+            let mut color = [0u32; 4];
+            for index in triangle {
+                for (sum, c) in color.iter_mut().zip(colors[*index as usize]) {
+                    *sum += c as u32;
+                }
+            }
+            let color = color.map(|c| (c / 3) as i32);
+            let nearest = (0..palette.len()).min_by_key(|i| {
+                let entry = palette[*i];
+                (0..3)
+                    .map(|c| (color[c] - entry[c] as i32).pow(2))
+                    .sum::<i32>()
+            })?;
+            let entry = (color[3] >= alpha_threshold as i32).then_some(nearest);
+
+            // Each part only gets the vertices it uses.
+            let (vertices, triangles) = parts.entry(entry).or_default();
+            triangles.push(triangle.map(|index| {
+                *lookup.entry((entry, index)).or_insert_with(|| {
+                    vertices.push(self.vertices[index as usize]);
+                    (vertices.len() - 1) as u32
+                })
+            }));
+        }
+        Some(Vec::from_iter(parts.into_iter().map(
+            |(entry, (vertices, triangles))| (entry, TriMesh::new(vertices, triangles)),
+        )))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyScalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyScalar {
+    fn parse(name: &str) -> Result<PlyScalar, MeshImportError> {
+        match name {
+            "char" | "int8" => Ok(PlyScalar::I8),
+            "uchar" | "uint8" => Ok(PlyScalar::U8),
+            "short" | "int16" => Ok(PlyScalar::I16),
+            "ushort" | "uint16" => Ok(PlyScalar::U16),
+            "int" | "int32" => Ok(PlyScalar::I32),
+            "uint" | "uint32" => Ok(PlyScalar::U32),
+            "float" | "float32" => Ok(PlyScalar::F32),
+            "double" | "float64" => Ok(PlyScalar::F64),
+            _ => Err(MeshImportError::BadData(format!(
+                "unknown PLY property type: {}",
+                name
+            ))),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyScalar::I8 | PlyScalar::U8 => 1,
+            PlyScalar::I16 | PlyScalar::U16 => 2,
+            PlyScalar::I32 | PlyScalar::U32 | PlyScalar::F32 => 4,
+            PlyScalar::F64 => 8,
+        }
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    scalar: PlyScalar,
+    // The type of the length prefix, for list properties.
+    count: Option<PlyScalar>,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+// Reads values out of the body of a PLY file, in whichever format it uses.
+struct PlyBody<'a> {
+    format: PlyFormat,
+    bytes: &'a [u8],
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl PlyBody<'_> {
+    fn read(&mut self, scalar: PlyScalar) -> Result<f64, MeshImportError> {
+        let truncated = || MeshImportError::BadData("PLY file is truncated".into());
+        if self.format == PlyFormat::Ascii {
+            let token = self.tokens.next().ok_or_else(truncated)?;
+            return token
+                .parse::<f64>()
+                .map_err(|_| MeshImportError::BadData(format!("bad PLY value: {}", token)));
+        }
+
+        let size = scalar.size();
+        if self.bytes.len() < size {
+            return Err(truncated());
+        }
+        let (value, rest) = self.bytes.split_at(size);
+        self.bytes = rest;
+        // Everything is read as little endian, so big endian values are flipped first.
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(value);
+        if self.format == PlyFormat::BinaryBigEndian {
+            raw[..size].reverse();
+        }
+        let value = match scalar {
+            PlyScalar::I8 => raw[0] as i8 as f64,
+            PlyScalar::U8 => raw[0] as f64,
+            PlyScalar::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            PlyScalar::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            PlyScalar::I32 => i32::from_le_bytes(raw[..4].try_into().unwrap()) as f64,
+            PlyScalar::U32 => u32::from_le_bytes(raw[..4].try_into().unwrap()) as f64,
+            PlyScalar::F32 => f32::from_le_bytes(raw[..4].try_into().unwrap()) as f64,
+            PlyScalar::F64 => f64::from_le_bytes(raw),
+        };
+        Ok(value)
+    }
+}
+
+fn parse_ply_header(header: &str) -> Result<(PlyFormat, Vec<PlyElement>), MeshImportError> {
+    let bad_header = |line: &str| MeshImportError::BadData(format!("bad PLY header: {}", line));
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in header.lines().skip(1) {
+        let tokens = Vec::from_iter(line.split_whitespace());
+        match tokens.as_slice() {
+            ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(PlyFormat::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(PlyFormat::BinaryBigEndian),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| bad_header(line))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, scalar, name] => {
+                let element = elements.last_mut().ok_or_else(|| bad_header(line))?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    scalar: PlyScalar::parse(scalar)?,
+                    count: Some(PlyScalar::parse(count)?),
+                });
+            }
+            ["property", scalar, name] => {
+                let element = elements.last_mut().ok_or_else(|| bad_header(line))?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    scalar: PlyScalar::parse(scalar)?,
+                    count: None,
+                });
+            }
+            ["comment", ..] | ["obj_info", ..] | ["end_header"] | [] => (),
+            _ => return Err(bad_header(line)),
+        }
+    }
+    let format = format.ok_or_else(|| bad_header("missing format"))?;
+    Ok((format, elements))
+}
+
+/// Loads a PLY file, ASCII or binary. Polygons are fanned into triangles, and the vertex colors
+/// are kept if there are `red`, `green` and `blue` properties. Colors without an `alpha`
+/// property are opaque.
+pub fn load_ply(path: &Path) -> Result<PlyMesh, MeshImportError> {
+    let bytes = fs::read(path)?;
+    if !bytes.starts_with(b"ply") {
+        return Err(MeshImportError::BadData("file is not a PLY file".into()));
+    }
+    let header_end = bytes
+        .windows(10)
+        .position(|w| w == b"end_header")
+        .ok_or_else(|| MeshImportError::BadData("PLY header is missing end_header".into()))?;
+    // The body starts on the line after end_header.
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map_or(bytes.len(), |p| header_end + p + 1);
+    let header = std::str::from_utf8(&bytes[..header_end])
+        .map_err(|_| MeshImportError::BadData("PLY header is not valid UTF-8".into()))?;
+    let (format, elements) = parse_ply_header(header)?;
+
+    let body_bytes = &bytes[body_start..];
+    let text = match format {
+        PlyFormat::Ascii => std::str::from_utf8(body_bytes)
+            .map_err(|_| MeshImportError::BadData("ASCII PLY is not valid UTF-8".into()))?,
+        _ => "",
+    };
+    let mut body = PlyBody {
+        format,
+        bytes: body_bytes,
+        tokens: text.split_ascii_whitespace(),
+    };
+
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::new();
+    let mut colors = Vec::new();
+    let mut has_colors = false;
+    let mut color_scale = 1.0;
+    for element in &elements {
+        if element.name == "vertex" {
+            let color = |name: &str| element.properties.iter().find(|p| p.name == name);
+            has_colors = ["red", "green", "blue"]
+                .into_iter()
+                .all(|n| color(n).is_some());
+            // Float colors are in the [0, 1] range.
+            if color("red").is_some_and(|p| matches!(p.scalar, PlyScalar::F32 | PlyScalar::F64)) {
+                color_scale = 255.0;
+            }
+        }
+        let mut values = HashMap::<&str, f64>::new();
+        let mut polygon = Vec::new();
+        for _ in 0..element.count {
+            for property in &element.properties {
+                let Some(count) = property.count else {
+                    values.insert(&property.name, body.read(property.scalar)?);
+                    continue;
+                };
+                let count = body.read(count)? as usize;
+                polygon.clear();
+                for _ in 0..count {
+                    polygon.push(body.read(property.scalar)? as u32);
+                }
+            }
+            match element.name.as_str() {
+                "vertex" => {
+                    let value = |name| values.get(name).copied().unwrap_or(0.0);
+                    vertices.push(Point::new(value("x"), value("y"), value("z")));
+                    if has_colors {
+                        let alpha = values.get("alpha").map_or(255.0, |a| a * color_scale);
+                        let [r, g, b] =
+                            ["red", "green", "blue"].map(|name| value(name) * color_scale);
+                        colors.push([r, g, b, alpha].map(|c| c.round().clamp(0.0, 255.0) as u8));
+                    }
+                }
+                "face" => {
+                    for i in 1..polygon.len().saturating_sub(1) {
+                        triangles.push([polygon[0], polygon[i], polygon[i + 1]]);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    if triangles.is_empty() {
+        return Err(MeshImportError::Empty);
+    }
+    let vertex_count = vertices.len() as u32;
+    if triangles.iter().flatten().any(|i| *i >= vertex_count) {
+        return Err(MeshImportError::BadData(
+            "PLY face refers to a missing vertex".into(),
+        ));
+    }
+    Ok(PlyMesh {
+        vertices,
+        triangles,
+        colors: has_colors.then_some(colors),
+    })
+}
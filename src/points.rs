@@ -0,0 +1,170 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, Error};
+
+use log::warn;
+use parry3d_f64::math::{Point, Vector};
+
+use crate::import::{JSONImporter, TooManyMaterials, DEFAULT_LEAF_SIZE, DEFAULT_VERTEX_OFFSET};
+use crate::squarion::*;
+use crate::svo::*;
+
+#[derive(Debug)]
+pub enum PointCloudError {
+    Internal(Error),
+    /// A line that isn't at least three numbers, with its line number.
+    BadLine(usize, String),
+    TooManyMaterials(TooManyMaterials),
+    Empty,
+}
+
+impl std::fmt::Display for PointCloudError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PointCloudError::Internal(e) => write!(f, "{}", e),
+            PointCloudError::BadLine(number, line) => {
+                write!(f, "bad point on line {}: {}", number, line)
+            }
+            PointCloudError::TooManyMaterials(e) => write!(f, "{}", e),
+            PointCloudError::Empty => write!(f, "point cloud has no points"),
+        }
+    }
+}
+
+impl std::error::Error for PointCloudError {}
+
+impl From<Error> for PointCloudError {
+    fn from(value: Error) -> Self {
+        PointCloudError::Internal(value)
+    }
+}
+
+impl From<TooManyMaterials> for PointCloudError {
+    fn from(value: TooManyMaterials) -> Self {
+        PointCloudError::TooManyMaterials(value)
+    }
+}
+
+/// Points read from a CSV or XYZ file, with an optional material ID each.
+pub struct PointCloud {
+    pub points: Vec<(Point<f64>, Option<u64>)>,
+}
+
+impl PointCloud {
+    /// Reads one `x,y,z` point per line. Values can be separated by commas or whitespace. A
+    /// fourth value is the point's material ID, but only when there are exactly four, since
+    /// XYZ files often carry colors or intensities instead. Blank lines, `#` comments and a
+    /// header line are skipped.
+    pub fn read(reader: impl BufRead) -> Result<PointCloud, PointCloudError> {
+        let mut points = Vec::new();
+        let mut first = true;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = Vec::from_iter(
+                line.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|v| !v.is_empty()),
+            );
+            let coords = values
+                .iter()
+                .take(3)
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>();
+            let is_header = first && coords.is_err();
+            first = false;
+            if is_header {
+                continue;
+            }
+            let bad_line = || PointCloudError::BadLine(i + 1, line.to_string());
+            let coords = coords.map_err(|_| bad_line())?;
+            if coords.len() != 3 {
+                return Err(bad_line());
+            }
+            let material = match values.len() {
+                4 => Some(values[3].parse::<u64>().map_err(|_| bad_line())?),
+                _ => None,
+            };
+            points.push((Point::new(coords[0], coords[1], coords[2]), material));
+        }
+        if points.is_empty() {
+            return Err(PointCloudError::Empty);
+        }
+        Ok(PointCloud { points })
+    }
+
+    /// Sets the voxel under each point, with the cloud's minimum corner at the core's minimum
+    /// corner. `voxel_size` is in the cloud's units, and with a `radius` every voxel within that
+    /// many voxels of a point is set as well. Where points share a voxel, the first one wins.
+    /// Points that land outside the core are dropped.
+    pub fn create_svo(
+        &self,
+        default_material: u64,
+        voxel_size: f64,
+        radius: u32,
+        height: usize,
+    ) -> Result<Svo<Option<VoxelCellData>>, PointCloudError> {
+        let mins = self
+            .points
+            .iter()
+            .fold(Point::from(Vector::repeat(f64::MAX)), |mins, (p, _)| {
+                mins.inf(p)
+            });
+        let core_size = 128 << (height - 5);
+        let radius = radius as i32;
+        let mut splat = Vec::new();
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                for z in -radius..=radius {
+                    let offset = Vector::new(x, y, z);
+                    if offset.norm_squared() <= radius * radius {
+                        splat.push(offset);
+                    }
+                }
+            }
+        }
+
+        let mut voxels = HashMap::new();
+        let mut dropped = 0;
+        for (point, material) in &self.points {
+            let voxel = ((point - mins) / voxel_size).map(|v| v.floor() as i32);
+            for offset in &splat {
+                let voxel = Point::from(voxel + offset);
+                if voxel.iter().all(|v| (0..core_size).contains(v)) {
+                    voxels
+                        .entry(voxel)
+                        .or_insert(material.unwrap_or(default_material));
+                } else {
+                    dropped += 1;
+                }
+            }
+        }
+        if dropped > 0 {
+            warn!("Dropped {} voxels outside of the core", dropped);
+        }
+
+        let unique_ids = Vec::from_iter(BTreeSet::from_iter(voxels.values().copied()));
+        let mut importer = JSONImporter;
+        let (material_mapper, material_id_to_index) =
+            importer.create_material_mapper(&unique_ids, true)?;
+        let mut svo = importer.create_empty_lods(
+            Point::origin(),
+            height,
+            DEFAULT_LEAF_SIZE,
+            &material_mapper,
+        );
+        for (pos, material) in voxels {
+            // Materials live on the +[1, 1, 1] vertex, the same place the JSON importer puts
+            // them.
+            importer.set_material_at_all_lods(
+                &mut svo,
+                pos + Vector::repeat(1),
+                material_id_to_index[&material],
+                DEFAULT_VERTEX_OFFSET,
+                height,
+            );
+        }
+        Ok(importer.finish_svo(svo))
+    }
+}
@@ -0,0 +1,98 @@
+use clap::ValueEnum;
+use log::warn;
+use parry3d_f64::math::{Point, Vector};
+
+use crate::import::{
+    JSONImporter, TooManyMaterials, DEFAULT_LEAF_SIZE, DEFAULT_VERTEX_OFFSET, POSITION_BATCH_SIZE,
+};
+use crate::squarion::*;
+use crate::svo::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum PrimitiveShape {
+    /// An ellipsoid touching the middle of each face of the bounds.
+    Sphere,
+    Box,
+    /// Upright along z, with round ends on x and y.
+    Cylinder,
+}
+
+/// A shape filled from its implicit function instead of voxelizing a mesh.
+pub struct Primitive {
+    pub shape: PrimitiveShape,
+    /// The size of the bounds on each axis, in voxels.
+    pub size: Vector<f64>,
+}
+
+impl Primitive {
+    /// Checks if a point, relative to the centre of the shape, is inside it.
+    pub fn contains(&self, point: &Vector<f64>) -> bool {
+        let p = point.component_div(&(self.size / 2.0));
+        match self.shape {
+            PrimitiveShape::Sphere => p.norm_squared() <= 1.0,
+            PrimitiveShape::Box => p.amax() <= 1.0,
+            PrimitiveShape::Cylinder => p.xy().norm_squared() <= 1.0 && p.z.abs() <= 1.0,
+        }
+    }
+
+    /// Fills the shape into a core of the given LOD height, centred in it. A voxel is filled
+    /// when its centre is inside the shape.
+    pub fn create_svo(
+        &self,
+        material: u64,
+        height: usize,
+    ) -> Result<Svo<Option<VoxelCellData>>, TooManyMaterials> {
+        let core_size = 128 << (height - 5);
+        if self.size.max() > core_size as f64 {
+            warn!(
+                "The shape is larger than the {} voxel core, so it will be clipped",
+                core_size
+            );
+        }
+        let centre = Vector::repeat(core_size as f64 / 2.0);
+        let mins = (centre - self.size / 2.0).map(|v| (v.floor() as i32).max(0));
+        let maxs = (centre + self.size / 2.0).map(|v| (v.ceil() as i32).min(core_size));
+
+        let mut importer = JSONImporter;
+        let (material_mapper, material_id_to_index) =
+            importer.create_material_mapper(&[material], true)?;
+        let material = material_id_to_index[&material];
+        let mut svo = importer.create_empty_lods(
+            Point::origin(),
+            height,
+            DEFAULT_LEAF_SIZE,
+            &material_mapper,
+        );
+        let mut batch = Vec::with_capacity(POSITION_BATCH_SIZE);
+        for x in mins.x..maxs.x {
+            for y in mins.y..maxs.y {
+                for z in mins.z..maxs.z {
+                    let voxel = Vector::new(x, y, z);
+                    if !self.contains(&(voxel.cast::<f64>().add_scalar(0.5) - centre)) {
+                        continue;
+                    }
+                    // Materials live on the +[1, 1, 1] vertex of their voxel.
+                    batch.push(Point::from(voxel.add_scalar(1)));
+                    if batch.len() == POSITION_BATCH_SIZE {
+                        importer.set_materials_at_all_lods(
+                            &mut svo,
+                            &batch,
+                            material,
+                            DEFAULT_VERTEX_OFFSET,
+                            height,
+                        );
+                        batch.clear();
+                    }
+                }
+            }
+        }
+        importer.set_materials_at_all_lods(
+            &mut svo,
+            &batch,
+            material,
+            DEFAULT_VERTEX_OFFSET,
+            height,
+        );
+        Ok(importer.finish_svo(svo))
+    }
+}
@@ -1,16 +1,20 @@
 use core::str;
 use std::array::TryFromSliceError;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::c_char;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::io::Write;
 use std::io::{Error, Read};
+use std::mem::size_of;
 use std::ops::Range;
 use std::str::Utf8Error;
+use std::sync::Arc;
 
+use log::debug;
 use parry3d_f64::math::{Point, Vector};
 use rangemap::RangeMap;
+use serde_json::{json, Value};
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -19,6 +23,17 @@ pub enum SerializeError {
     BadData,
 }
 
+impl std::fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SerializeError::Internal(e) => write!(f, "{}", e),
+            SerializeError::BadData => write!(f, "bad data"),
+        }
+    }
+}
+
+impl std::error::Error for SerializeError {}
+
 impl From<Error> for SerializeError {
     fn from(value: Error) -> Self {
         SerializeError::Internal(value)
@@ -34,6 +49,23 @@ pub enum DeserializeError {
     BadVersion(u32, u32),
 }
 
+impl std::fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeserializeError::Internal(e) => write!(f, "{}", e),
+            DeserializeError::BadData => write!(f, "bad data"),
+            DeserializeError::BadMagic(actual, expected) => {
+                write!(f, "bad magic {:#x}, expected {:#x}", actual, expected)
+            }
+            DeserializeError::BadVersion(actual, expected) => {
+                write!(f, "bad version {}, expected {}", actual, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
 fn assert_magic(actual: u32, expected: u32) -> Result<(), DeserializeError> {
     if actual != expected {
         Err(DeserializeError::BadMagic(actual, expected))
@@ -73,6 +105,8 @@ const COMPRESSED_MAGIC: u32 = 0xfb14b6f9;
 pub trait Serialize {
     fn serialize(&self, writer: &mut impl Write) -> Result<(), SerializeError>;
 
+    /// Serializes and LZ4 compresses, the inverse of `Deserialize::decompress`. This is the
+    /// format of the base64 chunks in blueprints.
     fn compress(&self) -> Result<Vec<u8>, SerializeError> {
         let mut result = Vec::new();
         COMPRESSED_MAGIC.serialize(&mut result)?;
@@ -523,7 +557,11 @@ pub struct RangeZYX {
 }
 
 impl RangeZYX {
-    // The order here matches the order in Aabb::split_at_center()
+    /// The octants returned by `split_at_center`, as `[x, y, z]` with 0 for the lower half of
+    /// an axis and 1 for the upper half. The children of an internal SVO node are in the same
+    /// order, so child `i` covers octant `OFFSETS[i]`. This goes around the bottom layer
+    /// (z = 0) counter-clockwise from the origin, then the top layer the same way, which
+    /// matches the order of `Aabb::split_at_center()`.
     pub const OFFSETS: [[i32; 3]; 8] = [
         [0, 0, 0],
         [1, 0, 0],
@@ -609,6 +647,12 @@ impl RangeZYX {
         self.origin + self.relative_position_from_index(index)
     }
 
+    /// The index of the octant with the given offset in `OFFSETS`, the inverse of indexing it.
+    /// `None` if any of the offsets isn't 0 or 1.
+    pub fn octant_index(offset: [i32; 3]) -> Option<usize> {
+        Self::OFFSETS.iter().position(|o| *o == offset)
+    }
+
     pub fn split_at_center(&self) -> [RangeZYX; 8] {
         let half_extents = self.size / 2;
         Self::OFFSETS.map(|[x, y, z]| {
@@ -633,7 +677,12 @@ impl Deserialize for RangeZYX {
     }
 }
 
-#[derive(Default)]
+/// Voxels of padding on each side of a `VertexGrid`'s inner range. The grid has one more vertex
+/// on top of that at the upper end, since the materials of the last voxels sit on their
+/// +[1, 1, 1] vertex. A 32 voxel cell is stored as 35 voxels.
+pub const GRID_BORDER: i32 = 1;
+
+#[derive(Default, Clone)]
 pub struct VertexGrid {
     range: RangeZYX,
     inner_range: RangeZYX,
@@ -648,6 +697,11 @@ impl VertexGrid {
     const VERSION: u32 = 9;
 
     pub fn new(range: RangeZYX, inner_range: RangeZYX) -> VertexGrid {
+        assert_eq!(
+            range.size,
+            inner_range.size + Vector::repeat(2 * GRID_BORDER + 1),
+            "the grid range must be the inner range padded by GRID_BORDER"
+        );
         VertexGrid {
             range,
             inner_range,
@@ -655,6 +709,14 @@ impl VertexGrid {
         }
     }
 
+    /// The range of a grid around `inner_range`, see `GRID_BORDER`.
+    pub fn padded_range(inner_range: &RangeZYX) -> RangeZYX {
+        RangeZYX::with_extents(
+            inner_range.origin - Vector::repeat(GRID_BORDER),
+            inner_range.size + Vector::repeat(2 * GRID_BORDER + 1),
+        )
+    }
+
     pub fn is_empty(&self) -> bool {
         self.sparse_materials.is_empty()
     }
@@ -673,32 +735,206 @@ impl VertexGrid {
             .for_each_index_range(subrange, |r| self.sparse_vertices.insert(r, voxel))
     }
 
-    pub fn calculate_metadata(&self, material_mapper: &MaterialMapper) -> HeavyMetadata {
-        let mut min_pos = Point::new(i32::MAX, i32::MAX, i32::MAX);
-        let mut max_pos = Point::new(i32::MIN, i32::MIN, i32::MIN);
-        let mut material_counts = BTreeMap::<MaterialId, u64>::new();
+    pub fn range(&self) -> &RangeZYX {
+        &self.range
+    }
+
+    pub fn inner_range(&self) -> &RangeZYX {
+        &self.inner_range
+    }
+
+    /// Adds the materials and vertices of `other`, which must cover the same range and use the
+    /// same material indices. Where both are set, ours are kept.
+    pub fn union(&mut self, other: &VertexGrid) {
+        let mut materials = other.sparse_materials.clone();
+        for (range, material) in self.sparse_materials.iter() {
+            materials.insert(range.clone(), *material);
+        }
+        let mut vertices = other.sparse_vertices.clone();
+        for (range, voxel) in self.sparse_vertices.iter() {
+            vertices.insert(range.clone(), *voxel);
+        }
+        self.sparse_materials = materials;
+        self.sparse_vertices = vertices;
+    }
+
+    /// Only keeps the materials that are also set in `other`, which must cover the same range.
+    pub fn intersect(&mut self, other: &VertexGrid) {
+        let mut materials = RangeMap::new();
+        for (range, material) in self.sparse_materials.iter() {
+            for (other_range, _) in other.sparse_materials.overlapping(range) {
+                let overlap = range_intersection(range, other_range);
+                if !overlap.is_empty() {
+                    materials.insert(overlap, *material);
+                }
+            }
+        }
+        self.sparse_materials = materials;
+    }
+
+    /// Removes every material whose position doesn't pass `keep`.
+    pub fn retain_materials<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(Point<i32>) -> bool,
+    {
+        let mut removed = Vec::new();
+        for (range, _) in self.sparse_materials.iter() {
+            for i in range.clone() {
+                if !keep(self.range.position_from_index(i)) {
+                    removed.push(i);
+                }
+            }
+        }
+        for i in removed {
+            self.sparse_materials.remove(i..i + 1);
+        }
+    }
+
+    /// Removes every material other than `material`, which is an index into the cell's mapping.
+    pub fn retain_material(&mut self, material: u8) {
+        let removed = Vec::from_iter(
+            self.sparse_materials
+                .iter()
+                .filter(|(_, vertex_material)| vertex_material.material != material)
+                .map(|(range, _)| range.clone()),
+        );
+        for range in removed {
+            self.sparse_materials.remove(range);
+        }
+    }
+
+    /// Removes the materials that are set in `other`, which must cover the same range.
+    pub fn subtract(&mut self, other: &VertexGrid) {
+        for (range, _) in other.sparse_materials.iter() {
+            self.sparse_materials.remove(range.clone());
+        }
+    }
+
+    /// Reflects the grid across the vertex plane at `plane` on `axis` (0 is x, 1 is y and 2 is
+    /// z). The mirrored inner range keeps the same padding around it, so the result lines up
+    /// with a cell generated at that position. Padding that would fall outside is dropped.
+    pub fn mirror(&self, axis: usize, plane: i32) -> VertexGrid {
+        let mut inner_range = self.inner_range;
+        inner_range.origin[axis] =
+            2 * plane - self.inner_range.origin[axis] - self.inner_range.size[axis];
+        let mut range = self.range;
+        range.origin[axis] =
+            inner_range.origin[axis] - (self.inner_range.origin[axis] - self.range.origin[axis]);
+
+        let mut grid = VertexGrid::new(range, inner_range);
+        for (indices, material) in self.sparse_materials.iter() {
+            for i in indices.clone() {
+                let mut pos = self.range.position_from_index(i);
+                // Materials are on the +[1, 1, 1] vertex of their voxel, and the mirrored voxel's
+                // +1 vertex is on the other side.
+                pos[axis] = 2 * plane - pos[axis] + 1;
+                if grid.range.contains_point(pos) {
+                    grid.set_materials(&RangeZYX::single(pos), *material);
+                }
+            }
+        }
+        for (indices, voxel) in self.sparse_vertices.iter() {
+            for i in indices.clone() {
+                let mut pos = self.range.position_from_index(i);
+                pos[axis] = 2 * plane - pos[axis];
+                if grid.range.contains_point(pos) {
+                    let mut voxel = *voxel;
+                    // Offsets are centred on 126.
+                    voxel.position[axis] = 252u8.saturating_sub(voxel.position[axis]);
+                    grid.set_voxel(&pos, voxel);
+                }
+            }
+        }
+        grid
+    }
+
+    /// The material index at `pos`, if there is one.
+    pub fn material_at(&self, pos: &Point<i32>) -> Option<u8> {
+        if !self.range.contains_point(*pos) {
+            return None;
+        }
+        let index = self.range.index_from_position(*pos);
+        self.sparse_materials.get(&index).map(|m| m.material)
+    }
+
+    /// The vertex offset at `pos`, if there is one.
+    pub fn voxel_at(&self, pos: &Point<i32>) -> Option<[u8; 3]> {
+        if !self.range.contains_point(*pos) {
+            return None;
+        }
+        let index = self.range.index_from_position(*pos);
+        self.sparse_vertices.get(&index).map(|v| v.position)
+    }
+
+    /// Roughly how many bytes the grid takes up in memory.
+    pub fn memory_size(&self) -> usize {
+        let entry = size_of::<Range<usize>>();
+        size_of::<VertexGrid>()
+            + self.sparse_materials.iter().count() * (entry + size_of::<VertexMaterial>())
+            + self.sparse_vertices.iter().count() * (entry + size_of::<VertexVoxel>())
+    }
 
+    /// Calls `func` with the position and material index of every material in the inner range.
+    pub fn for_each_material<F>(&self, mut func: F)
+    where
+        F: FnMut(Point<i32>, u8),
+    {
         self.range.for_each_index_range(&self.inner_range, |r| {
             for (subrange, vertex_material) in self.sparse_materials.overlapping(&r) {
                 for i in range_intersection(subrange, &r) {
-                    let pos = self.range.position_from_index(i);
-                    min_pos = min_pos.inf(&pos);
-                    max_pos = max_pos.sup(&pos);
-
-                    let mat_u8 = vertex_material.material;
-                    // Map mat_u8 to MaterialId via material_mapper
-                    if let Some(material_id) = material_mapper.reverse_mapping.get(&mat_u8) {
-                        *material_counts.entry(material_id.clone()).or_insert(0) += 1;
-                    }
+                    func(self.range.position_from_index(i), vertex_material.material);
+                }
+            }
+        });
+    }
+
+    /// Calls `func` with the position and offset of every vertex in the inner range.
+    pub fn for_each_voxel<F>(&self, mut func: F)
+    where
+        F: FnMut(Point<i32>, [u8; 3]),
+    {
+        self.range.for_each_index_range(&self.inner_range, |r| {
+            for (subrange, vertex_voxel) in self.sparse_vertices.overlapping(&r) {
+                for i in range_intersection(subrange, &r) {
+                    func(self.range.position_from_index(i), vertex_voxel.position);
                 }
             }
         });
+    }
+
+    /// Replaces material indices using `remap`. Indices missing from it are left alone.
+    pub fn remap_materials(&mut self, remap: &HashMap<u8, u8>) {
+        let materials = std::mem::take(&mut self.sparse_materials);
+        for (range, material) in materials {
+            let material = remap
+                .get(&material.material)
+                .copied()
+                .unwrap_or(material.material);
+            self.sparse_materials
+                .insert(range, VertexMaterial::new(material));
+        }
+    }
+
+    pub fn calculate_metadata(&self, material_mapper: &MaterialMapper) -> HeavyMetadata {
+        let mut min_pos = Point::new(i32::MAX, i32::MAX, i32::MAX);
+        let mut max_pos = Point::new(i32::MIN, i32::MIN, i32::MIN);
+        let mut material_counts = BTreeMap::<MaterialId, u64>::new();
+
+        self.for_each_material(|pos, mat_u8| {
+            min_pos = min_pos.inf(&pos);
+            max_pos = max_pos.sup(&pos);
+
+            // Map mat_u8 to MaterialId via material_mapper
+            if let Some(material_id) = material_mapper.reverse_mapping.get(&mat_u8) {
+                *material_counts.entry(material_id.clone()).or_insert(0) += 1;
+            }
+        });
 
         if material_counts.is_empty() {
             return HeavyMetadata::default();
         }
 
-        println!(
+        debug!(
             "Creating bounding box: {:?}, {:?}, materials {:?}",
             min_pos, max_pos, material_counts
         );
@@ -767,6 +1003,19 @@ impl Serialize for VertexGrid {
     }
 }
 
+impl VertexGrid {
+    // Same as `serialize`, but without the position of the grid.
+    fn serialize_relative(&self, writer: &mut impl Write) -> Result<(), SerializeError> {
+        self.range.size.serialize(writer)?;
+        (self.inner_range.origin - self.range.origin).serialize(writer)?;
+        self.inner_range.size.serialize(writer)?;
+        let length = self.range.volume() as usize;
+        VertexMaterial::serialize_sparse(&self.sparse_materials, length, writer)?;
+        VertexVoxel::serialize_sparse(&self.sparse_vertices, length, writer)?;
+        Ok(())
+    }
+}
+
 impl Deserialize for VertexGrid {
     fn deserialize(reader: &mut impl Read) -> Result<Self, DeserializeError> {
         let magic = u32::deserialize(reader)?;
@@ -788,6 +1037,9 @@ impl Deserialize for VertexGrid {
     }
 }
 
+/// Material short names are always this many bytes, padded with `\0`.
+pub const SHORT_NAME_LENGTH: usize = 8;
+
 #[derive(Debug, Default, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MaterialId {
     pub id: u64,
@@ -797,7 +1049,7 @@ pub struct MaterialId {
 impl Serialize for MaterialId {
     fn serialize(&self, writer: &mut impl Write) -> Result<(), SerializeError> {
         self.id.serialize(writer)?;
-        if self.short_name.len() != 8 {
+        if self.short_name.len() != SHORT_NAME_LENGTH {
             return Err(SerializeError::BadData);
         }
         writer.write_all(self.short_name.as_bytes())?;
@@ -808,7 +1060,7 @@ impl Serialize for MaterialId {
 impl Deserialize for MaterialId {
     fn deserialize(reader: &mut impl Read) -> Result<Self, DeserializeError> {
         let id = u64::deserialize(reader)?;
-        let mut short_name_data = [0; 8];
+        let mut short_name_data = [0; SHORT_NAME_LENGTH];
         reader.read_exact(&mut short_name_data)?;
         Ok(MaterialId {
             id,
@@ -828,6 +1080,38 @@ impl MaterialMapper {
         self.mapping.insert(material.clone(), id);
         self.reverse_mapping.insert(id, material);
     }
+
+    pub fn get(&self, id: u8) -> Option<&MaterialId> {
+        self.reverse_mapping.get(&id)
+    }
+
+    /// Every material, ordered by index.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &MaterialId)> {
+        self.reverse_mapping
+            .iter()
+            .map(|(index, material)| (*index, material))
+    }
+
+    /// Adds the materials of `other` that we don't have yet, and returns how to translate the
+    /// indices of `other` into ours. Returns `None` if we run out of indices.
+    pub fn merge(&mut self, other: &MaterialMapper) -> Option<HashMap<u8, u8>> {
+        let mut remap = HashMap::new();
+        for (index, material) in &other.reverse_mapping {
+            let new_index = match self.mapping.get(material) {
+                Some(new_index) => *new_index,
+                None => {
+                    let new_index = match self.reverse_mapping.last_key_value() {
+                        Some((last, _)) => last.checked_add(1)?,
+                        None => 1,
+                    };
+                    self.insert(new_index, material.clone());
+                    new_index
+                }
+            };
+            remap.insert(*index, new_index);
+        }
+        Some(remap)
+    }
 }
 
 impl Serialize for MaterialMapper {
@@ -850,7 +1134,8 @@ impl Deserialize for MaterialMapper {
 #[derive(Debug)]
 pub struct VoxelCellData {
     pub grid: VertexGrid,
-    mapping: MaterialMapper,
+    // Usually every cell of a construct has the same mapping, so it is shared instead of copied.
+    mapping: Arc<MaterialMapper>,
     is_diff: u8,
 }
 
@@ -858,14 +1143,38 @@ impl VoxelCellData {
     const MAGIC: u32 = 0x27b8a013;
     const VERSION: u32 = 6;
 
-    pub fn new(grid: VertexGrid, mapping: MaterialMapper) -> VoxelCellData {
+    pub fn new(grid: VertexGrid, mapping: impl Into<Arc<MaterialMapper>>) -> VoxelCellData {
         VoxelCellData {
             grid,
-            mapping,
+            mapping: mapping.into(),
             is_diff: 1,
         }
     }
 
+    pub fn mapping(&self) -> &MaterialMapper {
+        &self.mapping
+    }
+
+    /// Replaces the material mapping, translating the grid's material indices to match it.
+    pub fn remap_materials(&mut self, mapping: Arc<MaterialMapper>, remap: &HashMap<u8, u8>) {
+        self.grid.remap_materials(remap);
+        self.mapping = mapping;
+    }
+
+    /// Reflects the cell across the vertex plane at `plane` on `axis`, see `VertexGrid::mirror`.
+    pub fn mirror(&self, axis: usize, plane: i32) -> VoxelCellData {
+        VoxelCellData::new(self.grid.mirror(axis, plane), self.mapping.clone())
+    }
+
+    /// Hashes the contents of the cell, but not where it is, so identical cells in different
+    /// places have the same hash.
+    pub fn content_hash(&self) -> i64 {
+        let mut bytes = Vec::new();
+        self.grid.serialize_relative(&mut bytes).unwrap();
+        self.mapping.serialize(&mut bytes).unwrap();
+        hash(&bytes)
+    }
+
     pub fn calculate_metadata(&self, hash: i64) -> AggregateMetadata {
         let mut light_current = LightMetadata::default();
         let heavy_current = self.grid.calculate_metadata(&self.mapping);
@@ -908,7 +1217,7 @@ impl Deserialize for VoxelCellData {
         let is_diff = u8::deserialize(reader)?;
         Ok(VoxelCellData {
             grid,
-            mapping,
+            mapping: Arc::new(mapping),
             is_diff,
         })
     }
@@ -941,6 +1250,11 @@ pub struct LightMetadata {
 }
 
 impl LightMetadata {
+    /// The hash of the voxel chunk this metadata was made from.
+    pub fn hash_voxel(&self) -> Option<i64> {
+        self.hash_voxel
+    }
+
     fn combine(&self, other: &LightMetadata) -> LightMetadata {
         LightMetadata {
             vox: match (self.vox, other.vox) {
@@ -1237,3 +1551,194 @@ impl Deserialize for AggregateMetadata {
 pub fn hash(bytes: &[u8]) -> i64 {
     xxhash_rust::xxh64::xxh64(&bytes, 0xa1b2c3d4e5f6e7d8) as i64
 }
+
+// JSON dumps of the decoded data, for inspecting chunks with other tools. These aren't read
+// back, so they favour readability over matching the binary layout.
+
+impl RangeZYX {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "origin": [self.origin.x, self.origin.y, self.origin.z],
+            "size": [self.size.x, self.size.y, self.size.z],
+        })
+    }
+}
+
+impl VertexGrid {
+    /// Materials are `[x, y, z, index]` and vertices are `[x, y, z, offset_x, offset_y,
+    /// offset_z]`, covering the whole range including the padding.
+    pub fn to_json(&self) -> Value {
+        let mut materials = Vec::new();
+        for (indices, material) in self.sparse_materials.iter() {
+            for i in indices.clone() {
+                let pos = self.range.position_from_index(i);
+                materials.push(json!([pos.x, pos.y, pos.z, material.material]));
+            }
+        }
+        let mut vertices = Vec::new();
+        for (indices, voxel) in self.sparse_vertices.iter() {
+            for i in indices.clone() {
+                let pos = self.range.position_from_index(i);
+                let [x, y, z] = voxel.position;
+                vertices.push(json!([pos.x, pos.y, pos.z, x, y, z]));
+            }
+        }
+        json!({
+            "range": self.range.to_json(),
+            "inner_range": self.inner_range.to_json(),
+            "materials": materials,
+            "vertices": vertices,
+        })
+    }
+}
+
+impl MaterialMapper {
+    pub fn to_json(&self) -> Value {
+        Value::Array(Vec::from_iter(self.reverse_mapping.iter().map(
+            |(index, material)| {
+                json!({
+                    "index": index,
+                    "id": material.id,
+                    "short_name": material.short_name.trim_end_matches('\0'),
+                })
+            },
+        )))
+    }
+}
+
+impl VoxelCellData {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "grid": self.grid.to_json(),
+            "mapping": self.mapping.to_json(),
+            "is_diff": self.is_diff,
+        })
+    }
+}
+
+impl LightMetadata {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "vox": self.vox,
+            "mod": self.r#mod,
+            "hash_voxel": self.hash_voxel,
+            "hash_decor": self.hash_decor,
+            "entropy": self.entropy,
+        })
+    }
+}
+
+impl HeavyMetadata {
+    pub fn to_json(&self) -> Value {
+        let material_stats = self.material_stats.as_ref().map(|stats| {
+            Vec::from_iter(stats.iter().map(|(material, amount)| {
+                json!({
+                    "id": material.id,
+                    "short_name": material.short_name.trim_end_matches('\0'),
+                    "amount": amount.to_f64(),
+                })
+            }))
+        });
+        let inertia = self.inertia.as_ref().map(|inertia| {
+            let center = inertia.gravity_center;
+            json!({
+                "mass": inertia.mass,
+                "gravity_center": [center.x, center.y, center.z],
+                "inertia_tensor": inertia.inertia_tensor,
+            })
+        });
+        json!({
+            "bounding_box": self.bounding_box.as_ref().map(RangeZYX::to_json),
+            "material_stats": material_stats,
+            "inertia": inertia,
+            "server_timestamp": self.server_timestamp,
+            "server_previous_version": self.server_previous_version,
+        })
+    }
+}
+
+impl AggregateMetadata {
+    pub fn to_json(&self) -> Value {
+        let light_children = Vec::from_iter(self.light_children.iter().map(LightMetadata::to_json));
+        let heavy_children = Vec::from_iter(self.heavy_children.iter().map(HeavyMetadata::to_json));
+        json!({
+            "light_current": self.light_current.to_json(),
+            "light_children": light_children,
+            "heavy_current": self.heavy_current.to_json(),
+            "heavy_children": heavy_children,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::prelude::*;
+
+    #[test]
+    fn split_at_center_follows_the_octant_offsets() {
+        let range = RangeZYX::with_extent(Point::new(-8, 0, 16), 8);
+        let octants = range.split_at_center();
+        for (i, offset) in RangeZYX::OFFSETS.iter().enumerate() {
+            let offset = Vector::from(*offset);
+            assert_eq!(
+                octants[i].origin,
+                range.origin + offset * (range.size.x / 2)
+            );
+            assert_eq!(octants[i].size, range.size / 2);
+        }
+    }
+
+    #[test]
+    fn octant_index_inverts_the_offsets() {
+        for (i, offset) in RangeZYX::OFFSETS.iter().enumerate() {
+            assert_eq!(RangeZYX::octant_index(*offset), Some(i));
+        }
+    }
+
+    #[test]
+    fn octant_index_rejects_other_offsets() {
+        assert_eq!(RangeZYX::octant_index([2, 0, 0]), None);
+        assert_eq!(RangeZYX::octant_index([0, -1, 1]), None);
+    }
+
+    // Decodes a base64 chunk the way a blueprint is read, and checks that compressing it again
+    // gives back exactly the same bytes.
+    fn assert_round_trip<T: Serialize + Deserialize>(chunk: &str) {
+        let bytes = BASE64_STANDARD.decode(chunk).unwrap();
+        let decoded = T::decompress(&bytes).unwrap();
+        assert_eq!(decoded.compress().unwrap(), bytes);
+    }
+
+    #[test]
+    fn untouched_chunks_compress_to_the_same_bytes() {
+        let inner_range = RangeZYX::with_extent(Point::new(0, 0, 0), 32);
+        let mut grid = VertexGrid::new(VertexGrid::padded_range(&inner_range), inner_range);
+        grid.set_materials(
+            &RangeZYX::with_extent(Point::new(1, 1, 1), 4),
+            VertexMaterial::new(2),
+        );
+        grid.set_voxels(
+            &RangeZYX::with_extent(Point::new(0, 0, 0), 6),
+            VertexVoxel::new([126, 126, 126]),
+        );
+        grid.set_voxel(&Point::new(3, 2, 1), VertexVoxel::new([100, 140, 126]));
+        let mut mapping = MaterialMapper::default();
+        mapping.insert(
+            2,
+            MaterialId {
+                id: 1971262921,
+                short_name: "Material".into(),
+            },
+        );
+        let cell_data = VoxelCellData::new(grid, mapping).compress().unwrap();
+        let metadata = VoxelCellData::decompress(&cell_data)
+            .unwrap()
+            .calculate_metadata(hash(&cell_data))
+            .compress()
+            .unwrap();
+
+        assert_round_trip::<VoxelCellData>(&BASE64_STANDARD.encode(cell_data));
+        assert_round_trip::<AggregateMetadata>(&BASE64_STANDARD.encode(metadata));
+    }
+}
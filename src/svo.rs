@@ -1,32 +1,122 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read, Write};
+use std::mem::size_of;
 use std::{array, fmt::Debug};
 
-use parry3d_f64::math::Point;
+use clap::ValueEnum;
+use parry3d_f64::math::{Point, Vector};
+use rayon::prelude::*;
 
+use crate::import::{MAX_CORE_VOXELS, MAX_HEIGHT, MIN_HEIGHT};
 use crate::squarion::*;
 
+#[derive(Debug)]
+pub enum SvoError {
+    /// The root extent can't be halved evenly down to single cells.
+    ExtentNotPowerOfTwo(usize),
+    /// A node would have no volume, usually because a single cell was split.
+    EmptyRange(RangeZYX),
+    /// The LOD height doesn't match any core size.
+    InvalidHeight(usize),
+    /// The LOD height is above the given maximum, which builds a core of `MAX_CORE_VOXELS`.
+    HeightTooLarge(usize, usize),
+    /// The leaf size isn't a power of two, or is larger than the core.
+    InvalidLeafSize(usize, usize),
+}
+
+impl std::fmt::Display for SvoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SvoError::ExtentNotPowerOfTwo(extent) => {
+                write!(f, "extent must be a power of two, got {}", extent)
+            }
+            SvoError::EmptyRange(range) => write!(
+                f,
+                "range at {:?} with size {:?} is empty on some axis",
+                range.origin.coords.as_slice(),
+                range.size.as_slice()
+            ),
+            SvoError::InvalidHeight(height) => write!(
+                f,
+                "height must be between {} and {}, got {}",
+                MIN_HEIGHT, MAX_HEIGHT, height
+            ),
+            SvoError::HeightTooLarge(height, max) => write!(
+                f,
+                "height {} exceeds supported maximum {}, a core {} voxels wide",
+                height, max, MAX_CORE_VOXELS
+            ),
+            SvoError::InvalidLeafSize(leaf_size, core_size) => write!(
+                f,
+                "leaf size must be a power of two no larger than the {} voxel core, got {}",
+                core_size, leaf_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SvoError {}
+
 #[derive(Debug)]
 pub enum SvoNode<T> {
     Leaf(T),
+    /// The children are in octant order, see `RangeZYX::OFFSETS`.
     Internal(T, Box<[SvoNode<T>; 8]>),
 }
 
+/// What `Svo::try_from_fn` builds at a range: a leaf, or an internal node that is split into its
+/// eight octants.
 pub enum SvoReturn<T> {
     Leaf(T),
     Internal(T),
 }
 
 impl<T> SvoNode<T> {
-    fn from_fn<F>(range: &RangeZYX, func: &F) -> Self
+    fn try_from_fn<F>(range: &RangeZYX, func: &F) -> Result<Self, SvoError>
     where
         F: Fn(&RangeZYX) -> SvoReturn<T>,
     {
-        assert!(range.size.min() != 0);
+        if range.size.min() == 0 {
+            return Err(SvoError::EmptyRange(*range));
+        }
         match func(range) {
-            SvoReturn::Leaf(v) => SvoNode::Leaf(v),
-            SvoReturn::Internal(v) => SvoNode::Internal(
-                v,
-                Box::new(range.split_at_center().map(|o| Self::from_fn(&o, func))),
-            ),
+            SvoReturn::Leaf(v) => Ok(SvoNode::Leaf(v)),
+            SvoReturn::Internal(v) => {
+                let children = range
+                    .split_at_center()
+                    .iter()
+                    .map(|o| Self::try_from_fn(o, func))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SvoNode::Internal(
+                    v,
+                    Box::new(children.try_into().unwrap_or_else(|_| unreachable!())),
+                ))
+            }
+        }
+    }
+
+    fn try_par_from_fn<F>(range: &RangeZYX, func: &F) -> Result<Self, SvoError>
+    where
+        T: Send,
+        F: Fn(&RangeZYX) -> SvoReturn<T> + Sync,
+    {
+        if range.size.min() == 0 {
+            return Err(SvoError::EmptyRange(*range));
+        }
+        match func(range) {
+            SvoReturn::Leaf(v) => Ok(SvoNode::Leaf(v)),
+            SvoReturn::Internal(v) => {
+                // Collecting keeps the octant order, so the result doesn't depend on scheduling.
+                let children = range
+                    .split_at_center()
+                    .par_iter()
+                    .map(|o| Self::try_par_from_fn(o, func))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SvoNode::Internal(
+                    v,
+                    Box::new(children.try_into().unwrap_or_else(|_| unreachable!())),
+                ))
+            }
         }
     }
 
@@ -44,6 +134,22 @@ impl<T> SvoNode<T> {
         }
     }
 
+    fn for_each_mut<F>(&mut self, range: &RangeZYX, func: &mut F)
+    where
+        F: FnMut(&RangeZYX, &mut T),
+    {
+        match self {
+            SvoNode::Leaf(v) => func(range, v),
+            SvoNode::Internal(v, children) => {
+                func(range, v);
+                let octants = range.split_at_center();
+                for (child, octant) in children.iter_mut().zip(octants.iter()) {
+                    child.for_each_mut(octant, func);
+                }
+            }
+        }
+    }
+
     fn into_cata<F, R>(self, range: &RangeZYX, func: &mut F) -> R
     where
         F: FnMut(&RangeZYX, T, Option<[R; 8]>) -> R,
@@ -63,27 +169,71 @@ impl<T> SvoNode<T> {
             }
         }
     }
-
 }
 
+/// A tree of values over a cube. `range` is the cube the root covers, with a power of two
+/// extent when built with `try_from_fn`, and every internal node covers the eight octants of its
+/// range from `RangeZYX::split_at_center`, in that order.
 pub struct Svo<T> {
     pub root: SvoNode<T>,
     pub range: RangeZYX,
 }
 
 impl<T> Svo<T> {
-    pub fn from_fn<F>(origin: Point<i32>, extent: usize, func: &F) -> Self
+    /// Builds the tree from the root down, splitting every node `func` returns as internal.
+    /// Fails if `extent` isn't a power of two, or if `func` splits a single cell.
+    pub fn try_from_fn<F>(origin: Point<i32>, extent: usize, func: &F) -> Result<Self, SvoError>
     where
         F: Fn(&RangeZYX) -> SvoReturn<T>,
     {
-        assert!(extent.is_power_of_two());
+        if !extent.is_power_of_two() {
+            return Err(SvoError::ExtentNotPowerOfTwo(extent));
+        }
         let range = RangeZYX::with_extent(origin, extent as i32);
-        Self {
-            root: SvoNode::from_fn(&range, func),
+        Ok(Self {
+            root: SvoNode::try_from_fn(&range, func)?,
             range,
+        })
+    }
+
+    /// Same as `try_from_fn`, but the octants are built in parallel on the rayon thread pool.
+    pub fn try_par_from_fn<F>(origin: Point<i32>, extent: usize, func: &F) -> Result<Self, SvoError>
+    where
+        T: Send,
+        F: Fn(&RangeZYX) -> SvoReturn<T> + Sync,
+    {
+        if !extent.is_power_of_two() {
+            return Err(SvoError::ExtentNotPowerOfTwo(extent));
         }
+        let range = RangeZYX::with_extent(origin, extent as i32);
+        Ok(Self {
+            root: SvoNode::try_par_from_fn(&range, func)?,
+            range,
+        })
+    }
+
+    /// Same as `try_from_fn`, for callers that know the extent and splits are valid. Panics
+    /// otherwise.
+    pub fn from_fn<F>(origin: Point<i32>, extent: usize, func: &F) -> Self
+    where
+        F: Fn(&RangeZYX) -> SvoReturn<T>,
+    {
+        Self::try_from_fn(origin, extent, func).unwrap_or_else(|e| panic!("{}", e))
     }
 
+    /// Same as `try_par_from_fn`, for callers that know the extent and splits are valid. Panics
+    /// otherwise.
+    pub fn par_from_fn<F>(origin: Point<i32>, extent: usize, func: &F) -> Self
+    where
+        T: Send,
+        F: Fn(&RangeZYX) -> SvoReturn<T> + Sync,
+    {
+        Self::try_par_from_fn(origin, extent, func).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Folds the tree from the leaves up. `func` gets the range and value of each node, along
+    /// with the results of its children in octant order, or `None` for a leaf. Returns the
+    /// result for the root.
     pub fn cata<F, R>(&self, mut func: F) -> R
     where
         F: FnMut(&RangeZYX, &T, Option<[R; 8]>) -> R,
@@ -91,6 +241,15 @@ impl<T> Svo<T> {
         self.root.cata(&self.range, &mut func)
     }
 
+    /// Visits every node, parents before children.
+    pub fn for_each_mut<F>(&mut self, mut func: F)
+    where
+        F: FnMut(&RangeZYX, &mut T),
+    {
+        self.root.for_each_mut(&self.range, &mut func)
+    }
+
+    /// Converts every value with `func`, keeping the shape of the tree and its range.
     pub fn into_map<F, R>(self, mut func: F) -> Svo<R>
     where
         F: FnMut(T) -> R,
@@ -103,49 +262,141 @@ impl<T> Svo<T> {
             range: self.range,
         }
     }
+}
 
+// Nodes are written parents first, each as a tag (0 for a leaf, 1 for internal) followed by the
+// value and then the children in octant order. The ranges aren't stored, they follow from the root.
+impl<T> Serialize for SvoNode<T>
+where
+    T: Serialize,
+{
+    fn serialize(&self, writer: &mut impl Write) -> Result<(), SerializeError> {
+        match self {
+            SvoNode::Leaf(v) => {
+                0u8.serialize(writer)?;
+                v.serialize(writer)
+            }
+            SvoNode::Internal(v, children) => {
+                1u8.serialize(writer)?;
+                v.serialize(writer)?;
+                for child in children.iter() {
+                    child.serialize(writer)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<T> Deserialize for SvoNode<T>
+where
+    T: Deserialize,
+{
+    fn deserialize(reader: &mut impl Read) -> Result<Self, DeserializeError> {
+        match u8::deserialize(reader)? {
+            0 => Ok(SvoNode::Leaf(T::deserialize(reader)?)),
+            1 => {
+                let v = T::deserialize(reader)?;
+                let children = (0..8)
+                    .map(|_| SvoNode::deserialize(reader))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let children = children
+                    .into_boxed_slice()
+                    .try_into()
+                    .map_err(|_| DeserializeError::BadData)?;
+                Ok(SvoNode::Internal(v, children))
+            }
+            _ => Err(DeserializeError::BadData),
+        }
+    }
+}
+
+impl<T> Serialize for Svo<T>
+where
+    T: Serialize,
+{
+    fn serialize(&self, writer: &mut impl Write) -> Result<(), SerializeError> {
+        self.range.serialize(writer)?;
+        self.root.serialize(writer)
+    }
+}
+
+impl<T> Deserialize for Svo<T>
+where
+    T: Deserialize,
+{
+    fn deserialize(reader: &mut impl Read) -> Result<Self, DeserializeError> {
+        let range = RangeZYX::deserialize(reader)?;
+        let root = SvoNode::deserialize(reader)?;
+        Ok(Svo { root, range })
+    }
+}
+
+// Pruning walks the tree with explicit stacks instead of recursing, since very deep trees can
+// overflow the call stack.
+enum PruneFrame {
+    Visit(SvoNode<Option<VoxelCellData>>),
+    Build(VoxelCellData),
 }
 
 impl SvoNode<Option<VoxelCellData>> {
     /// Checks if the current SvoNode is empty.
     pub fn is_empty(&self) -> bool {
-        match self {
-            SvoNode::Leaf(None) => true, // A leaf with no data is considered empty
-            SvoNode::Internal(None, children) => {
-                children.iter().all(|child| child.is_empty()) // Internal node is empty if all children are empty
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            match node {
+                SvoNode::Leaf(None) => (), // A leaf with no data is considered empty
+                SvoNode::Internal(None, children) => stack.extend(children.iter()), // Internal node is empty if all children are empty
+                _ => return false, // Any node with data is not empty
             }
-            _ => false, // Any node with data is not empty
         }
+        true
     }
 
-    /// Recursively prunes empty grids in the SvoNode
+    /// Prunes empty grids in the SvoNode, children before their parents.
     fn prune_empty_grids(self) -> SvoNode<Option<VoxelCellData>> {
-        match self {
-            // If it's a leaf with no data, return None (pruned)
-            SvoNode::Leaf(Some(cell_data)) => {
-                if cell_data.grid.is_empty() {
-                    SvoNode::Leaf(None) // Prune if the grid is empty
-                } else {
-                    SvoNode::Leaf(Some(cell_data)) // Keep the data if grid is not empty
+        let mut stack = vec![PruneFrame::Visit(self)];
+        let mut pruned = Vec::new();
+        while let Some(frame) = stack.pop() {
+            match frame {
+                // If it's a leaf with no data, return None (pruned)
+                PruneFrame::Visit(SvoNode::Leaf(Some(cell_data))) => {
+                    if cell_data.grid.is_empty() {
+                        pruned.push(SvoNode::Leaf(None)) // Prune if the grid is empty
+                    } else {
+                        pruned.push(SvoNode::Leaf(Some(cell_data))) // Keep the data if grid is not empty
+                    }
                 }
-            }
 
-            // Internal node with children, recursively prune children
-            SvoNode::Internal(Some(cell_data), children) => {
-                let pruned_children: Box<[SvoNode<Option<VoxelCellData>>; 8]> =
-                    Box::new(children.map(|child| child.prune_empty_grids()));
+                // Internal node with children, prune the children first. They are pushed in reverse
+                // so they come out of `pruned` in order.
+                PruneFrame::Visit(SvoNode::Internal(Some(cell_data), children)) => {
+                    stack.push(PruneFrame::Build(cell_data));
+                    stack.extend((*children).into_iter().rev().map(PruneFrame::Visit));
+                }
 
-                // If all children are pruned, return None
-                if pruned_children.iter().all(|child| child.is_empty()) {
-                    SvoNode::Leaf(None) // Prune internal node if all children are empty
-                } else {
-                    SvoNode::Internal(Some(cell_data), pruned_children) // Keep node if at least one child is not empty
+                // If the node is already None, just return it
+                PruneFrame::Visit(SvoNode::Leaf(None) | SvoNode::Internal(None, _)) => {
+                    pruned.push(SvoNode::Leaf(None))
                 }
-            }
 
-            // If the node is already None, just return it
-            SvoNode::Leaf(None) | SvoNode::Internal(None, _) => SvoNode::Leaf(None),
+                PruneFrame::Build(cell_data) => {
+                    let children: [SvoNode<Option<VoxelCellData>>; 8] = pruned
+                        .split_off(pruned.len() - 8)
+                        .try_into()
+                        .unwrap_or_else(|_| unreachable!());
+
+                    // If all children are pruned, return None
+                    if children.iter().all(|child| child.is_empty()) {
+                        pruned.push(SvoNode::Leaf(None)) // Prune internal node if all children are empty
+                    } else {
+                        // Keep node if at least one child is not empty
+                        pruned.push(SvoNode::Internal(Some(cell_data), Box::new(children)))
+                    }
+                }
+            }
         }
+        pruned.pop().unwrap()
     }
 }
 
@@ -157,4 +408,576 @@ impl Svo<Option<VoxelCellData>> {
             range: self.range, // Keep the range unchanged
         }
     }
-}
\ No newline at end of file
+}
+
+impl Svo<Option<VoxelCellData>> {
+    /// Removes every voxel outside of `bounds`, which is in finest LOD voxels. Cells are
+    /// `leaf_size` voxels across. Cells that end up empty are pruned.
+    pub fn crop(mut self, bounds: &RangeZYX, leaf_size: usize) -> Self {
+        let leaf_size = leaf_size as i32;
+        self.for_each_mut(|range, v| {
+            let Some(cell_data) = v else { return };
+            let scale = range.size.x;
+            let cell_bounds = RangeZYX {
+                origin: range.origin * leaf_size,
+                size: range.size * leaf_size,
+            };
+            let overlap = cell_bounds.intersection(bounds);
+            if overlap.volume() == 0 {
+                *v = None;
+            } else if overlap.volume() != cell_bounds.volume() {
+                // Materials are placed on the +[1, 1, 1] vertex of their voxel.
+                cell_data.grid.retain_materials(|pos| {
+                    bounds.contains_point((pos - Vector::repeat(1)) * scale)
+                });
+            }
+        });
+        self.prune_empty_grids()
+    }
+
+    /// Removes every voxel that isn't of `material`, a material ID. Cells that end up empty are
+    /// pruned.
+    pub fn retain_material(mut self, material: u64) -> Self {
+        self.for_each_mut(|_, v| {
+            let Some(cell_data) = v else { return };
+            let index = cell_data
+                .mapping()
+                .iter()
+                .find(|(_, id)| id.id == material)
+                .map(|(index, _)| index);
+            match index {
+                Some(index) => cell_data.grid.retain_material(index),
+                None => *v = None,
+            }
+        });
+        self.prune_empty_grids()
+    }
+}
+
+/// How much of a construct is repeated. Elements can't be shared in the blueprint format, so
+/// this only measures the redundancy.
+#[derive(Debug, Default)]
+pub struct DedupReport {
+    pub cells: usize,
+    pub unique_cells: usize,
+    /// Internal nodes with at least one non-empty cell below them.
+    pub subtrees: usize,
+    pub unique_subtrees: usize,
+}
+
+impl Svo<Option<VoxelCellData>> {
+    /// Hashes every cell and subtree by content, ignoring where they are, and counts how many
+    /// are distinct.
+    pub fn dedup_report(&self) -> DedupReport {
+        let mut report = DedupReport::default();
+        let mut cells = HashSet::new();
+        let mut subtrees = HashSet::new();
+        self.cata(|_, v, cs| {
+            let cell_hash = v.as_ref().map(VoxelCellData::content_hash);
+            if let Some(cell_hash) = cell_hash {
+                report.cells += 1;
+                cells.insert(cell_hash);
+            }
+            let Some(cs) = cs else { return cell_hash };
+            if cell_hash.is_none() && cs.iter().all(Option::is_none) {
+                return None;
+            }
+            let mut bytes = Vec::new();
+            for h in std::iter::once(cell_hash).chain(cs) {
+                bytes.extend_from_slice(&h.unwrap_or(0).to_le_bytes());
+            }
+            let subtree_hash = hash(&bytes);
+            report.subtrees += 1;
+            subtrees.insert(subtree_hash);
+            Some(subtree_hash)
+        });
+        report.unique_cells = cells.len();
+        report.unique_subtrees = subtrees.len();
+        report
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VoxelCounts {
+    pub cells: usize,
+    pub materials: BTreeMap<MaterialId, u64>,
+}
+
+impl VoxelCounts {
+    pub fn total(&self) -> u64 {
+        self.materials.values().sum()
+    }
+}
+
+impl Svo<Option<VoxelCellData>> {
+    /// Counts the non-empty cells and the voxels of each material at the finest LOD.
+    pub fn voxel_counts(&self) -> VoxelCounts {
+        let mut counts = VoxelCounts::default();
+        self.cata(|range, v, cs| {
+            let Some(cell_data) = v else { return };
+            if cs.is_some() || range.size.x != 1 || cell_data.grid.is_empty() {
+                return;
+            }
+            counts.cells += 1;
+            cell_data.grid.for_each_material(|_, material| {
+                if let Some(material_id) = cell_data.mapping().get(material) {
+                    *counts.materials.entry(material_id.clone()).or_insert(0) += 1;
+                }
+            });
+        });
+        counts
+    }
+
+    /// Counts the voxels of the finest LOD in each layer along every axis, for finding where the
+    /// mass of a construct is.
+    pub fn occupancy(&self) -> Occupancy {
+        let mut occupancy = Occupancy::default();
+        self.cata(|range, v, cs| {
+            let Some(cell_data) = v else { return };
+            if cs.is_some() || range.size.x != 1 {
+                return;
+            }
+            cell_data.grid.for_each_material(|pos, material| {
+                if cell_data.mapping().get(material).is_none() {
+                    return;
+                }
+                // Materials are on the +[1, 1, 1] vertex of their voxel.
+                let voxel = pos - Vector::repeat(1);
+                for axis in 0..3 {
+                    *occupancy.layers[axis].entry(voxel[axis]).or_insert(0) += 1;
+                }
+                occupancy.sum += voxel.coords.map(|v| v as f64 + 0.5);
+                occupancy.total += 1;
+            });
+        });
+        occupancy
+    }
+}
+
+/// How the voxels of an SVO are spread out, see `Svo::occupancy`.
+#[derive(Debug, Default)]
+pub struct Occupancy {
+    /// The voxel count of each layer along x, y and z, by the layer's voxel coordinate.
+    pub layers: [BTreeMap<i32, u64>; 3],
+    pub total: u64,
+    sum: Vector<f64>,
+}
+
+impl Occupancy {
+    /// The mean position of the voxel centres, in voxels, or `None` if there are no voxels.
+    pub fn centroid(&self) -> Option<Point<f64>> {
+        (self.total > 0).then(|| Point::from(self.sum / self.total as f64))
+    }
+}
+
+/// An axis of the construct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+// `center` is twice the position of the mirror plane, in chunks, so it is always whole.
+fn mirror_node(node: &CellNode, scale: i32, axis: usize, center: i32, leaf_size: i32) -> CellNode {
+    // Cells are `leaf_size` voxels across, at `scale` chunks per cell.
+    let plane = center * leaf_size / (2 * scale);
+    let (value, children) = match node {
+        SvoNode::Leaf(v) => (v, None),
+        SvoNode::Internal(v, children) => (v, Some(children)),
+    };
+    let value = value.as_ref().map(|c| c.mirror(axis, plane));
+    let Some(children) = children else {
+        return SvoNode::Leaf(value);
+    };
+    let children = array::from_fn(|i| {
+        // The mirrored child sits on the other side of the plane.
+        let mut offset = RangeZYX::OFFSETS[i];
+        offset[axis] = 1 - offset[axis];
+        let source = RangeZYX::octant_index(offset).expect("flipping an axis keeps it 0 or 1");
+        mirror_node(&children[source], scale / 2, axis, center, leaf_size)
+    });
+    SvoNode::Internal(value, Box::new(children))
+}
+
+impl Svo<Option<VoxelCellData>> {
+    /// Reflects the voxels across the plane through the middle of the construct that is
+    /// perpendicular to `axis`. Cells are `leaf_size` voxels across.
+    pub fn mirrored(&self, axis: Axis, leaf_size: usize) -> Self {
+        let axis = axis as usize;
+        let center = 2 * self.range.origin[axis] + self.range.size[axis];
+        let leaf_size = leaf_size as i32;
+        Svo {
+            root: mirror_node(&self.root, self.range.size.x, axis, center, leaf_size),
+            range: self.range,
+        }
+    }
+}
+
+/// The shape of an SVO, for finding out where a blueprint's size comes from.
+#[derive(Debug, Default)]
+pub struct SvoStats {
+    /// Levels below the root, so a lone root has a depth of 0.
+    pub depth: usize,
+    pub internal_nodes: usize,
+    pub leaves: usize,
+    pub non_empty_leaves: usize,
+    /// Materials set at the finest LOD.
+    pub voxels: u64,
+    /// A rough estimate of the bytes used by the nodes and their grids.
+    pub memory: usize,
+}
+
+impl Svo<Option<VoxelCellData>> {
+    pub fn stats(&self) -> SvoStats {
+        self.cata(|range, v, cs| {
+            let mut stats = SvoStats {
+                memory: size_of::<CellNode>(),
+                ..Default::default()
+            };
+            if let Some(cell_data) = v {
+                stats.memory += cell_data.grid.memory_size();
+            }
+            let Some(cs) = cs else {
+                stats.leaves = 1;
+                if let Some(cell_data) = v.as_ref().filter(|c| !c.grid.is_empty()) {
+                    stats.non_empty_leaves = 1;
+                    if range.size.x == 1 {
+                        cell_data.grid.for_each_material(|_, _| stats.voxels += 1);
+                    }
+                }
+                return stats;
+            };
+            stats.internal_nodes = 1;
+            for child in cs {
+                stats.depth = stats.depth.max(child.depth + 1);
+                stats.internal_nodes += child.internal_nodes;
+                stats.leaves += child.leaves;
+                stats.non_empty_leaves += child.non_empty_leaves;
+                stats.voxels += child.voxels;
+                stats.memory += child.memory;
+            }
+            stats
+        })
+    }
+}
+
+/// A boolean operation between the voxels of two SVOs.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum CombineOp {
+    /// Keep the voxels of either. The first wins where both are set.
+    Union,
+    /// Keep the voxels of the first that are also in the second.
+    Intersection,
+    /// Keep the voxels of the first that are not in the second.
+    Difference,
+}
+
+#[derive(Debug)]
+pub enum CombineError {
+    /// The trees have different LOD heights, see `CoreSize::height`.
+    HeightMismatch(u32, u32),
+    RangeMismatch,
+    TooManyMaterials,
+}
+
+impl std::fmt::Display for CombineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CombineError::HeightMismatch(a, b) => {
+                write!(f, "the trees have different heights, {} and {}", a, b)
+            }
+            CombineError::RangeMismatch => write!(f, "the voxel data covers different ranges"),
+            CombineError::TooManyMaterials => write!(f, "too many materials in a single cell"),
+        }
+    }
+}
+
+impl std::error::Error for CombineError {}
+
+type CellNode = SvoNode<Option<VoxelCellData>>;
+
+fn combine_cells(
+    a: Option<VoxelCellData>,
+    b: Option<VoxelCellData>,
+    op: CombineOp,
+) -> Result<Option<VoxelCellData>, CombineError> {
+    let (a, b) = match (a, b, op) {
+        (Some(a), Some(b), _) => (a, b),
+        (Some(a), None, CombineOp::Union | CombineOp::Difference) => return Ok(Some(a)),
+        (None, Some(b), CombineOp::Union) => return Ok(Some(b)),
+        _ => return Ok(None),
+    };
+    let (a_range, b_range) = (a.grid.range(), b.grid.range());
+    if a_range.origin != b_range.origin || a_range.size != b_range.size {
+        return Err(CombineError::RangeMismatch);
+    }
+
+    let mut mapping = a.mapping().clone();
+    let remap = mapping
+        .merge(b.mapping())
+        .ok_or(CombineError::TooManyMaterials)?;
+    let mut other = b.grid;
+    other.remap_materials(&remap);
+
+    let mut grid = a.grid;
+    match op {
+        CombineOp::Union => grid.union(&other),
+        CombineOp::Intersection => grid.intersect(&other),
+        CombineOp::Difference => grid.subtract(&other),
+    }
+    Ok(Some(VoxelCellData::new(grid, mapping)))
+}
+
+// A leaf is treated as if it had empty children, so trees of different shapes line up.
+fn into_children(node: CellNode) -> (Option<VoxelCellData>, Option<[CellNode; 8]>) {
+    match node {
+        SvoNode::Leaf(v) => (v, None),
+        SvoNode::Internal(v, children) => (v, Some(*children)),
+    }
+}
+
+fn combine_nodes(a: CellNode, b: CellNode, op: CombineOp) -> Result<CellNode, CombineError> {
+    let (a, a_children) = into_children(a);
+    let (b, b_children) = into_children(b);
+    let value = combine_cells(a, b, op)?;
+    if a_children.is_none() && b_children.is_none() {
+        return Ok(SvoNode::Leaf(value));
+    }
+
+    let empty = || array::from_fn(|_| SvoNode::Leaf(None));
+    let a_children = a_children.unwrap_or_else(empty);
+    let b_children = b_children.unwrap_or_else(empty);
+    let children = a_children
+        .into_iter()
+        .zip(b_children)
+        .map(|(a, b)| combine_nodes(a, b, op))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(SvoNode::Internal(
+        value,
+        Box::new(children.try_into().unwrap_or_else(|_| unreachable!())),
+    ))
+}
+
+fn check_same_range(a: &RangeZYX, b: &RangeZYX) -> Result<(), CombineError> {
+    // The root is measured in cells, which are 32 voxels, or 2^5.
+    let height = |range: &RangeZYX| range.size.x.max(1).trailing_zeros() + 5;
+    if height(a) != height(b) {
+        return Err(CombineError::HeightMismatch(height(a), height(b)));
+    }
+    if a.origin != b.origin || a.size != b.size {
+        return Err(CombineError::RangeMismatch);
+    }
+    Ok(())
+}
+
+impl Svo<Option<VoxelCellData>> {
+    /// Combines the voxels of two SVOs covering the same range, cell by cell. Cells that end up
+    /// empty are pruned.
+    pub fn combine(self, other: Self, op: CombineOp) -> Result<Self, CombineError> {
+        check_same_range(&self.range, &other.range)?;
+        let svo = Svo {
+            root: combine_nodes(self.root, other.root, op)?,
+            range: self.range,
+        };
+        Ok(svo.prune_empty_grids())
+    }
+}
+
+/// The finest LOD voxels that changed between two SVOs. Positions are voxels from the core's
+/// minimum corner, the same as `JSONImporter::process_json_and_create_svo` reads.
+#[derive(Debug, Default)]
+pub struct SvoDiff {
+    /// Voxels that were added or changed material, by material id.
+    pub materials: BTreeMap<u64, Vec<Point<i32>>>,
+    /// Vertices that moved, along with the ones around every added voxel, since setting a
+    /// material resets them.
+    pub vertices: Vec<(Point<i32>, [u8; 3])>,
+    pub removed: Vec<Point<i32>>,
+}
+
+// Material ids by position, for the inner range of a cell.
+fn cell_materials(cell_data: Option<&VoxelCellData>) -> BTreeMap<[i32; 3], u64> {
+    let mut materials = BTreeMap::new();
+    if let Some(cell_data) = cell_data {
+        cell_data.grid.for_each_material(|pos, material| {
+            if let Some(material_id) = cell_data.mapping().get(material) {
+                materials.insert(pos.into(), material_id.id);
+            }
+        });
+    }
+    materials
+}
+
+fn diff_cells(old: Option<&VoxelCellData>, new: Option<&VoxelCellData>, diff: &mut SvoDiff) {
+    let old_materials = cell_materials(old);
+    let new_materials = cell_materials(new);
+    let mut added = HashSet::new();
+    for (pos, id) in &new_materials {
+        if old_materials.get(pos) != Some(id) {
+            added.insert(*pos);
+            // Materials are on the +[1, 1, 1] vertex of their voxel.
+            let voxel = Point::from(*pos) - Vector::repeat(1);
+            diff.materials.entry(*id).or_default().push(voxel);
+        }
+    }
+    for pos in old_materials.keys() {
+        if !new_materials.contains_key(pos) {
+            diff.removed.push(Point::from(*pos) - Vector::repeat(1));
+        }
+    }
+
+    let Some(new) = new else { return };
+    new.grid.for_each_voxel(|pos, offset| {
+        let moved = old.and_then(|old| old.grid.voxel_at(&pos)) != Some(offset);
+        let next_to_added = RangeZYX::OFFSETS
+            .iter()
+            .any(|d| added.contains(&[pos.x + d[0], pos.y + d[1], pos.z + d[2]]));
+        if moved || next_to_added {
+            diff.vertices.push((pos, offset));
+        }
+    });
+}
+
+fn diff_nodes(
+    old: Option<&CellNode>,
+    new: Option<&CellNode>,
+    range: &RangeZYX,
+    diff: &mut SvoDiff,
+) {
+    let split = |node: Option<&CellNode>| match node {
+        Some(SvoNode::Leaf(v)) => (v.as_ref(), None),
+        Some(SvoNode::Internal(v, children)) => (v.as_ref(), Some(children)),
+        None => (None, None),
+    };
+    let (old_cell, old_children) = split(old);
+    let (new_cell, new_children) = split(new);
+    if range.size.x == 1 {
+        diff_cells(old_cell, new_cell, diff);
+        return;
+    }
+    if old_children.is_none() && new_children.is_none() {
+        return;
+    }
+    for (i, octant) in range.split_at_center().iter().enumerate() {
+        diff_nodes(
+            old_children.map(|c| &c[i]),
+            new_children.map(|c| &c[i]),
+            octant,
+            diff,
+        );
+    }
+}
+
+impl Svo<Option<VoxelCellData>> {
+    /// Walks both trees in lockstep and collects the finest LOD voxels that differ from `self` in
+    /// `newer`. Cells collapsed to a coarser LOD have no finest voxels, so they count as empty.
+    pub fn diff(&self, newer: &Self) -> Result<SvoDiff, CombineError> {
+        check_same_range(&self.range, &newer.range)?;
+        let mut diff = SvoDiff::default();
+        diff_nodes(Some(&self.root), Some(&newer.root), &self.range, &mut diff);
+        Ok(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Splits down to single cells, with the volume of its range at every node.
+    fn volume_tree(extent: usize) -> Svo<u64> {
+        Svo::from_fn(Point::origin(), extent, &|range: &RangeZYX| {
+            if range.size.x == 1 {
+                SvoReturn::Leaf(range.volume())
+            } else {
+                SvoReturn::Internal(range.volume())
+            }
+        })
+    }
+
+    // Every node's range and whether it is a leaf, parents before children.
+    fn shape<T>(svo: &Svo<T>) -> Vec<(Point<i32>, Vector<i32>, bool)> {
+        let mut nodes = Vec::new();
+        svo.cata(|range: &RangeZYX, _: &T, children: Option<[(); 8]>| {
+            nodes.push((range.origin, range.size, children.is_none()));
+        });
+        nodes
+    }
+
+    #[test]
+    fn from_fn_splits_down_to_single_cells() {
+        let svo = volume_tree(4);
+        let leaves = svo.cata(
+            |range: &RangeZYX, _: &u64, children: Option<[u64; 8]>| match children {
+                Some(children) => children.iter().sum(),
+                None => {
+                    assert_eq!(range.size, Vector::repeat(1));
+                    1
+                }
+            },
+        );
+        assert_eq!(leaves, 64);
+    }
+
+    #[test]
+    fn cata_recomputes_totals() {
+        let svo = volume_tree(4);
+        // Each node holds its volume, so it should equal the sum of the leaves below it.
+        let total = svo.cata(|_: &RangeZYX, v: &u64, children: Option<[u64; 8]>| {
+            let Some(children) = children else {
+                return *v;
+            };
+            let total: u64 = children.iter().sum();
+            assert_eq!(total, *v);
+            total
+        });
+        assert_eq!(total, 64);
+    }
+
+    #[test]
+    fn into_map_keeps_the_structure() {
+        let svo = volume_tree(4);
+        let before = shape(&svo);
+        let mapped = svo.into_map(|v| format!("{} voxels", v));
+        assert_eq!(shape(&mapped), before);
+        assert_eq!(mapped.range.size, Vector::repeat(4));
+        let SvoNode::Internal(root, children) = &mapped.root else {
+            panic!("the root should be split");
+        };
+        assert_eq!(root, "64 voxels");
+        assert!(matches!(&children[0], SvoNode::Internal(v, _) if v == "8 voxels"));
+    }
+
+    #[test]
+    fn try_from_fn_rejects_extents_that_are_not_powers_of_two() {
+        let svo = Svo::try_from_fn(Point::origin(), 6, &|_: &RangeZYX| SvoReturn::Leaf(()));
+        assert!(matches!(svo, Err(SvoError::ExtentNotPowerOfTwo(6))));
+    }
+
+    #[test]
+    fn try_from_fn_rejects_splitting_a_single_cell() {
+        let svo = Svo::try_from_fn(Point::origin(), 1, &|_: &RangeZYX| SvoReturn::Internal(()));
+        assert!(matches!(svo, Err(SvoError::EmptyRange(_))));
+    }
+
+    #[test]
+    fn children_are_in_octant_order() {
+        let origin = Point::new(-2, 4, 6);
+        let svo = Svo::from_fn(origin, 2, &|range: &RangeZYX| {
+            if range.size.x == 1 {
+                SvoReturn::Leaf(range.origin)
+            } else {
+                SvoReturn::Internal(range.origin)
+            }
+        });
+        let SvoNode::Internal(_, children) = &svo.root else {
+            panic!("the root should be split");
+        };
+        for (child, offset) in children.iter().zip(RangeZYX::OFFSETS) {
+            let SvoNode::Leaf(child_origin) = child else {
+                panic!("the children should be leaves");
+            };
+            assert_eq!(*child_origin, origin + Vector::from(offset));
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::Read;
+
+use parry3d_f64::math::{Point, Vector};
+
+use crate::import::{JSONImporter, TooManyMaterials, DEFAULT_LEAF_SIZE, DEFAULT_VERTEX_OFFSET};
+use crate::squarion::*;
+use crate::svo::*;
+
+pub struct VoxModel {
+    pub size: Vector<i32>,
+    pub voxels: Vec<(Point<i32>, u8)>,
+}
+
+/// A MagicaVoxel .vox file. Only the voxel and palette chunks are read; the scene graph is
+/// ignored, so every model is placed at the origin.
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    // Indexed by the palette index stored with each voxel, so entry 0 is unused.
+    pub palette: [[u8; 4]; 256],
+}
+
+impl VoxFile {
+    const MAGIC: u32 = 0x20584f56; // "VOX "
+
+    /// Game material ids for each palette index used by the models. Indices missing from
+    /// `palette_map` get `default_material`.
+    pub fn material_ids(
+        &self,
+        palette_map: &HashMap<u8, u64>,
+        default_material: u64,
+    ) -> HashMap<u8, u64> {
+        let used = BTreeSet::from_iter(
+            self.models
+                .iter()
+                .flat_map(|m| m.voxels.iter().map(|(_, index)| *index)),
+        );
+        HashMap::from_iter(used.into_iter().map(|index| {
+            let id = palette_map.get(&index).copied().unwrap_or(default_material);
+            (index, id)
+        }))
+    }
+
+    /// Palette indices whose alpha is below `alpha_threshold`.
+    pub fn translucent_indices(&self, alpha_threshold: u8) -> impl Iterator<Item = u8> + '_ {
+        (1..=255u8).filter(move |index| self.palette[*index as usize][3] < alpha_threshold)
+    }
+
+    pub fn create_svo(
+        &self,
+        palette_map: &HashMap<u8, u64>,
+        default_material: u64,
+        height: usize,
+    ) -> Result<Svo<Option<VoxelCellData>>, TooManyMaterials> {
+        let material_ids = self.material_ids(palette_map, default_material);
+        let unique_ids = Vec::from_iter(BTreeSet::from_iter(material_ids.values().copied()));
+
+        let mut importer = JSONImporter;
+        let (material_mapper, material_id_to_index) =
+            importer.create_material_mapper(&unique_ids, true)?;
+        let mut svo = importer.create_empty_lods(
+            Point::origin(),
+            height,
+            DEFAULT_LEAF_SIZE,
+            &material_mapper,
+        );
+        for model in &self.models {
+            for (pos, palette_index) in &model.voxels {
+                let material_index = material_id_to_index[&material_ids[palette_index]];
+                // Materials live on the +[1, 1, 1] vertex, the same place the JSON importer
+                // puts them.
+                let global_position = pos + Vector::repeat(1);
+                importer.set_material_at_all_lods(
+                    &mut svo,
+                    global_position,
+                    material_index,
+                    DEFAULT_VERTEX_OFFSET,
+                    height,
+                );
+            }
+        }
+        Ok(importer.finish_svo(svo))
+    }
+}
+
+impl Deserialize for VoxFile {
+    fn deserialize(reader: &mut impl Read) -> Result<Self, DeserializeError> {
+        let magic = u32::deserialize(reader)?;
+        if magic != VoxFile::MAGIC {
+            return Err(DeserializeError::BadMagic(magic, VoxFile::MAGIC));
+        }
+        let _version = u32::deserialize(reader)?;
+
+        let mut models = Vec::new();
+        let mut palette = [[255u8; 4]; 256];
+        let mut size = None;
+
+        // Child chunks directly follow their parent's header, so the whole file can be read as a
+        // flat list of chunks.
+        let mut id = [0u8; 4];
+        loop {
+            match reader.read_exact(&mut id) {
+                Ok(()) => (),
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let content_size = u32::deserialize(reader)? as usize;
+            let _children_size = u32::deserialize(reader)?;
+            let mut content = vec![0u8; content_size];
+            reader.read_exact(&mut content)?;
+            let content = &mut content.as_slice();
+
+            match &id {
+                b"SIZE" => size = Some(Vector::deserialize(content)?),
+                b"XYZI" => {
+                    let count = u32::deserialize(content)?;
+                    let mut voxels = Vec::with_capacity(count as usize);
+                    for _ in 0..count {
+                        let mut voxel = [0u8; 4];
+                        content.read_exact(&mut voxel)?;
+                        let pos = Point::new(voxel[0] as i32, voxel[1] as i32, voxel[2] as i32);
+                        voxels.push((pos, voxel[3]));
+                    }
+                    let size = size.take().ok_or(DeserializeError::BadData)?;
+                    models.push(VoxModel { size, voxels });
+                }
+                b"RGBA" => {
+                    // Color i is used by palette index i + 1.
+                    for color in palette.iter_mut().skip(1) {
+                        content.read_exact(color)?;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(VoxFile { models, palette })
+    }
+}
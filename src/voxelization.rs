@@ -1,8 +1,9 @@
-use async_std::task::{self, block_on};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use clap::ValueEnum;
 use line_drawing::{VoxelOrigin, WalkVoxels};
 use ordered_float::NotNan;
 use parry3d_f64::bounding_volume::Aabb;
@@ -10,6 +11,7 @@ use parry3d_f64::math::{Isometry, Point, Vector};
 use parry3d_f64::query::{intersection_test, PointQuery};
 use parry3d_f64::shape::{Cuboid, Shape, TriMesh, Triangle};
 
+use crate::import::{progress_bar, JSONImporter, TooManyMaterials, MAX_HEIGHT};
 use crate::squarion::*;
 use crate::svo::*;
 
@@ -20,46 +22,244 @@ enum Voxel {
     Boundry(bool),
 }
 
-fn voxelize(
+impl Voxel {
+    fn is_solid(&self) -> bool {
+        match self {
+            Voxel::Internal => true,
+            Voxel::External => false,
+            Voxel::Boundry(significant) => *significant,
+        }
+    }
+}
+
+fn for_each_point(range: &RangeZYX, mut func: impl FnMut(Point<i32>)) {
+    let end = range.origin + range.size;
+    for x in range.origin.x..end.x {
+        for y in range.origin.y..end.y {
+            for z in range.origin.z..end.z {
+                func(Point::new(x, y, z));
+            }
+        }
+    }
+}
+
+// Which voxels of a range are solid, for telling the surface apart from the interior.
+struct Solidity {
+    range: RangeZYX,
+    solid: Vec<bool>,
+}
+
+impl Solidity {
+    fn new(voxels: &Svo<Voxel>, range: RangeZYX) -> Solidity {
+        let mut solidity = Solidity {
+            range,
+            solid: vec![false; range.volume() as usize],
+        };
+        voxels.cata(|subrange, value, cs| {
+            if cs.is_some() || !value.is_solid() {
+                return;
+            }
+            for_each_point(&subrange.intersection(&range), |point| {
+                let index = solidity.index(point).unwrap();
+                solidity.solid[index] = true;
+            });
+        });
+        solidity
+    }
+
+    fn index(&self, point: Point<i32>) -> Option<usize> {
+        if !self.range.contains_point(point) {
+            return None;
+        }
+        let offset = (point - self.range.origin).map(|v| v as usize);
+        let size = self.range.size.map(|v| v as usize);
+        Some((offset.z * size.y + offset.y) * size.x + offset.x)
+    }
+
+    // Voxels outside the range count as empty.
+    fn is_solid(&self, point: Point<i32>) -> bool {
+        self.index(point).is_some_and(|index| self.solid[index])
+    }
+
+    // Whether all 6 neighbours of the voxel are solid, so none of its faces are visible.
+    fn is_enclosed(&self, point: Point<i32>) -> bool {
+        (0..3).all(|axis| {
+            let step = Vector::ith(axis, 1);
+            self.is_solid(point + step) && self.is_solid(point - step)
+        })
+    }
+}
+
+/// How points are tested for being inside the mesh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, ValueEnum)]
+pub enum FillMode {
+    /// Use the orientation of the nearest triangle. Fast, but holes in the mesh can turn the
+    /// inside out around them.
+    #[default]
+    Oriented,
+    /// Use the generalized winding number. Tolerates holes and non-manifold meshes, such as
+    /// scans, but is much slower since every point looks at every triangle.
+    Winding,
+}
+
+/// How a supersampled surface voxel decides if it's solid, see `Voxelizer::with_samples`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, ValueEnum)]
+pub enum SampleRule {
+    /// Solid if any sample is inside. Keeps walls thinner than a voxel, but thickens everything
+    /// else a little.
+    Any,
+    /// Solid if more than half of the samples are inside.
+    #[default]
+    Majority,
+}
+
+// The generalized winding number of the mesh around `point`, from Jacobson et al. 2013. This is
+// the sum of the solid angles of the triangles, so it's close to 1 inside the mesh and 0 outside
+// even when some triangles are missing.
+fn winding_number(isometry: &Isometry<f64>, mesh: &TriMesh, point: &Point<f64>) -> f64 {
+    let point = isometry.inverse_transform_point(point);
+    let mut total = 0.0;
+    for triangle in mesh.triangles() {
+        let (a, b, c) = (triangle.a - point, triangle.b - point, triangle.c - point);
+        let (la, lb, lc) = (a.norm(), b.norm(), c.norm());
+        let det = a.dot(&b.cross(&c));
+        let div = la * lb * lc + a.dot(&b) * lc + b.dot(&c) * la + c.dot(&a) * lb;
+        total += 2.0 * det.atan2(div);
+    }
+    total / (4.0 * std::f64::consts::PI)
+}
+
+fn contains_point(
     isometry: &Isometry<f64>,
     mesh: &TriMesh,
+    fill_mode: FillMode,
+    point: &Point<f64>,
+) -> bool {
+    match fill_mode {
+        FillMode::Oriented => mesh.contains_point(isometry, point),
+        // The sign only depends on which way the triangles face, so inside out meshes work too.
+        FillMode::Winding => winding_number(isometry, mesh, point).abs() > 0.5,
+    }
+}
+
+// Checks if a region that doesn't touch the surface is inside the mesh.
+fn is_inside(isometry: &Isometry<f64>, mesh: &TriMesh, fill_mode: FillMode, aabb: &Aabb) -> bool {
+    // Vote on if the voxel is inside or outside. We need to do this because some people won't
+    // read the FAQ, and try to import non-manifold meshes. This makes the process more reliable.
+    let mut inside_count = contains_point(isometry, mesh, fill_mode, &aabb.center()) as u32;
+    for point in aabb.vertices() {
+        inside_count += contains_point(isometry, mesh, fill_mode, &point) as u32
+    }
+    // Bias towards assuming outside, since it's better to have empty internals than random
+    // floating cubes.
+    inside_count >= 7
+}
+
+// Whether a voxel on the surface is solid. With one sample only the centre is tested, so features
+// thinner than a voxel can fall between the centres and vanish. A mesh thinner than a voxel has
+// no inside to test at all, so every voxel on its surface is solid.
+fn is_significant(voxelizer: &Voxelizer, aabb: &Aabb) -> bool {
+    if voxelizer.is_thinner_than(&aabb.extents()) {
+        return true;
+    }
+    let (isometry, mesh, fill_mode) = (&voxelizer.isometry, &voxelizer.mesh, voxelizer.fill_mode);
+    let samples = voxelizer.samples;
+    if samples <= 1 {
+        return contains_point(isometry, mesh, fill_mode, &aabb.center());
+    }
+    // The samples sit in the middle of each cell of an n x n x n grid over the voxel.
+    let step = aabb.extents() / samples as f64;
+    let mut inside_count = 0;
+    for x in 0..samples {
+        for y in 0..samples {
+            for z in 0..samples {
+                let cell = Vector::new(x, y, z).map(|v| v as f64 + 0.5);
+                let point = aabb.mins + step.component_mul(&cell);
+                if contains_point(isometry, mesh, fill_mode, &point) {
+                    if voxelizer.sample_rule == SampleRule::Any {
+                        return true;
+                    }
+                    inside_count += 1;
+                }
+            }
+        }
+    }
+    voxelizer.sample_rule == SampleRule::Majority && 2 * inside_count > samples.pow(3)
+}
+
+// Hollowing settings for one LOD, in that LOD's voxels.
+#[derive(Clone, Copy)]
+struct Hollow {
+    thickness: f64,
+    lattice_spacing: Option<f64>,
+}
+
+// Whether the region crosses one of the lattice planes, which are `spacing` voxels apart on every
+// axis starting from the core's origin.
+fn on_lattice(range: &RangeZYX, spacing: f64) -> bool {
+    (0..3).any(|axis| {
+        let start = range.origin[axis] as f64;
+        (start / spacing).ceil() * spacing < start + range.size[axis] as f64
+    })
+}
+
+fn voxelize(
+    voxelizer: &Voxelizer,
     aabb: &Aabb,
     origin: Point<i32>,
     extent: usize,
     clip_range: &RangeZYX,
+    hollow: Option<Hollow>,
 ) -> Svo<Voxel> {
-    let voxel_size = aabb.extents().x / extent as f64;
+    let (isometry, mesh, fill_mode) = (&voxelizer.isometry, &voxelizer.mesh, voxelizer.fill_mode);
+    let voxel_size = aabb.extents() / extent as f64;
     Svo::from_fn(origin, extent, &|range| {
         if range.intersection(clip_range).volume() == 0 {
             return SvoReturn::Leaf(Voxel::External);
         }
 
-        let mins = aabb.mins + (range.origin - origin).map(|v| v as f64) * voxel_size;
-        let maxs = mins + range.size.map(|v| v as f64) * voxel_size;
+        let mins = aabb.mins
+            + (range.origin - origin)
+                .map(|v| v as f64)
+                .component_mul(&voxel_size);
+        let maxs = mins + range.size.map(|v| v as f64).component_mul(&voxel_size);
         let aabb = Aabb::new(mins, maxs);
 
         // Scale up the region slightly. Makes intersection detection more robust.
         let cuboid = Cuboid::new(aabb.half_extents() * 1.05);
         let cuboid_pos = Isometry::from(aabb.center());
         if !intersection_test(isometry, mesh, &cuboid_pos, &cuboid).unwrap() {
-            // Vote on if the voxel is inside or outside. We need to do this because some people won't
-            // read the FAQ, and try to import non-manifold meshes. This makes the process more reliable.
-            let mut inside_count = mesh.contains_point(isometry, &aabb.center()) as u32;
-            for point in aabb.vertices() {
-                inside_count += mesh.contains_point(isometry, &point) as u32
+            // A mesh thinner than a voxel has no inside, but the orientation test would still
+            // call everything behind it inside.
+            if voxelizer.is_thinner_than(&voxel_size)
+                || !is_inside(isometry, mesh, fill_mode, &aabb)
+            {
+                return SvoReturn::Leaf(Voxel::External);
             }
-            // Bias towards assuming outside, since it's better to have empty internals than random
-            // floating cubes.
-            if inside_count >= 7 {
-                SvoReturn::Leaf(Voxel::Internal)
-            } else {
-                SvoReturn::Leaf(Voxel::External)
+            match hollow {
+                // Anything that is at least `thickness` voxels away from the surface is dropped,
+                // unless it's part of the lattice.
+                Some(hollow) => {
+                    let shell = Cuboid::new(aabb.half_extents() + voxel_size * hollow.thickness);
+                    let in_shell = intersection_test(isometry, mesh, &cuboid_pos, &shell).unwrap();
+                    let in_lattice = hollow
+                        .lattice_spacing
+                        .is_some_and(|spacing| on_lattice(range, spacing));
+                    if !in_shell && !in_lattice {
+                        SvoReturn::Leaf(Voxel::External)
+                    } else if range.volume() == 1 {
+                        SvoReturn::Leaf(Voxel::Internal)
+                    } else {
+                        SvoReturn::Internal(Voxel::Internal)
+                    }
+                }
+                None => SvoReturn::Leaf(Voxel::Internal),
             }
         } else if range.volume() == 1 {
             // We do a quick check to see if the voxel is "significant", i.e. the center is in the mesh.
             //
             // This helps remove artifacts from internal angles in the model.
-            let significant = mesh.contains_point(isometry, &aabb.center());
+            let significant = is_significant(voxelizer, &aabb);
             SvoReturn::Leaf(Voxel::Boundry(significant))
         } else {
             SvoReturn::Internal(Voxel::Boundry(false))
@@ -67,8 +267,10 @@ fn voxelize(
     })
 }
 
-fn discretize(point: Point<f64>, voxel_size: f64) -> Point<f64> {
-    (84.0 * point / voxel_size).map(|v| v.round())
+// Voxels are not necessarily cubes in model space when scaling per axis, so this also maps
+// the model into a space where they are.
+fn discretize(point: Point<f64>, voxel_size: &Vector<f64>) -> Point<f64> {
+    Point::from((84.0 * point.coords).component_div(voxel_size)).map(|v| v.round())
 }
 
 // In game voxels operate on a discrete grid, so the best solutions are ones that
@@ -117,7 +319,7 @@ fn calculate_vertex_offset(
     mesh: &TriMesh,
     aabb: &Aabb,
     anchor: Point<f64>,
-    voxel_size: f64,
+    voxel_size: &Vector<f64>,
 ) -> Vector<u8> {
     let discrete_anchor = discretize(anchor, voxel_size);
     let discrete_pos = discretize(aabb.center(), voxel_size);
@@ -164,14 +366,30 @@ fn calculate_vertex_offset(
     }
 }
 
+// Moves a vertex straight onto the nearest point of the surface. Each axis is limited to half a
+// voxel, so neighbouring vertices can't cross each other and tear the surface. The offset only
+// depends on the model space position, so vertices shared with the neighbouring chunks agree.
+fn smooth_vertex_offset(
+    isometry: &Isometry<f64>,
+    mesh: &TriMesh,
+    pos: &Point<f64>,
+    voxel_size: &Vector<f64>,
+) -> Vector<u8> {
+    let surface = mesh.project_point(isometry, pos, false).point;
+    let offset = (84.0 * (surface - pos)).component_div(voxel_size);
+    to_voxel_offset(offset.map(|v| v.round().clamp(-42.0, 42.0)))
+}
+
 fn extract_vertices(
     voxels: &Svo<Voxel>,
     isometry: &Isometry<f64>,
     mesh: &TriMesh,
+    fill_mode: FillMode,
     aabb: &Aabb,
     origin: Point<i32>,
+    smooth: bool,
 ) -> HashMap<Point<i32>, Point<u8>> {
-    let voxel_size = aabb.extents().x / voxels.range.size.x as f64;
+    let voxel_size = aabb.extents() / voxels.range.size.x as f64;
     let mut significant_points = HashMap::new();
     voxels.cata(|range, v, cs| {
         if cs.is_some() {
@@ -180,14 +398,17 @@ fn extract_vertices(
         match v {
             Voxel::Boundry(significant) => {
                 assert_eq!(range.volume(), 1);
-                let center =
-                    aabb.mins + voxel_size * (range.origin - origin).map(|v| v as f64 + 0.5);
+                let center = aabb.mins
+                    + voxel_size.component_mul(&(range.origin - origin).map(|v| v as f64 + 0.5));
                 for offset in &RangeZYX::OFFSETS {
                     let offset = Vector::from_row_slice(offset);
                     let point = range.origin + offset;
 
-                    let pos = aabb.mins + voxel_size * (point - origin).map(|v| v as f64);
-                    if mesh.contains_point(isometry, &pos) != *significant {
+                    let pos =
+                        aabb.mins + voxel_size.component_mul(&(point - origin).map(|v| v as f64));
+                    // Smoothing moves every corner of the surface voxels, not just the ones
+                    // that cross the surface.
+                    if smooth || contains_point(isometry, mesh, fill_mode, &pos) != *significant {
                         let entry = significant_points
                             .entry(point)
                             .or_insert_with(|| Vec::new());
@@ -202,90 +423,22 @@ fn extract_vertices(
     let mut result = HashMap::new();
     for (point, anchors) in significant_points {
         let anchor = anchors.iter().fold(Point::origin(), |a, v| a + v) / anchors.len() as f64;
-        let pos = aabb.mins + voxel_size * (point - origin).map(|v| v as f64);
-        let aabb = Aabb::from_half_extents(pos, Vector::repeat(voxel_size * 1.5));
-
-        let best = calculate_vertex_offset(isometry, mesh, &aabb, anchor, voxel_size);
+        let pos = aabb.mins + voxel_size.component_mul(&(point - origin).map(|v| v as f64));
+        let best = if smooth {
+            smooth_vertex_offset(isometry, mesh, &pos, &voxel_size)
+        } else {
+            let aabb = Aabb::from_half_extents(pos, voxel_size * 1.5);
+            calculate_vertex_offset(isometry, mesh, &aabb, anchor, &voxel_size)
+        };
         result.insert(point, Point::origin() + best);
     }
     result
 }
 
-// This is by far the most expensive part, mostly due to Trimesh being kinda slow and the algorithm itself
-// being pretty naive. For now we just throw threads at it, but it can definitely be improved.
-fn voxelize_chunk(
-    isometry: &Isometry<f64>,
-    mesh: &TriMesh,
-    aabb: &Aabb,
-    voxel_origin: &Point<i32>,
+pub(crate) fn create_material_mapper(
     material: u64,
-    is_lod: bool,
-) -> Option<VoxelCellData> {
-    // We have to over-voxelize that chunk due to the boundries expected in voxel cell data.
-    // e.g. for an inner_range of [0, 0, 0] -> [32, 32, 32] the actual range of the chunk is
-    //  [-1, -1, -1] -> [34, 34, 34], likely to remove seams when generating the mesh.
-    let voxel_size = aabb.extents().x / 32.0;
-    let voxel_size_offset = Vector::repeat(voxel_size);
-    let origin = aabb.mins - voxel_size_offset * 2.0;
-
-    let range = RangeZYX::with_extent(voxel_origin - Vector::repeat(1), 35);
-
-    // Note that this large aabb could result in a lot of wasted computation, so we clip the range.
-    let svo_aabb = Aabb::new(origin, origin + voxel_size_offset * 64.0);
-    let voxels = voxelize(
-        isometry,
-        mesh,
-        &svo_aabb,
-        voxel_origin - Vector::repeat(2),
-        64,
-        &range,
-    );
-
-    let inner_range = RangeZYX::with_extent(*voxel_origin, 32);
-    let mut grid = VertexGrid::new(range, inner_range);
-    voxels.cata(|subrange, value, cs| {
-        if cs.is_some() {
-            return;
-        }
-        let (place_materials, place_positions) = match value {
-            Voxel::External => (false, false),
-            Voxel::Internal => (true, true),
-            Voxel::Boundry(significant) => (*significant, true),
-        };
-        if place_materials {
-            // Materials are placed on the +[1, 1, 1] vertex.
-            let material_range = RangeZYX {
-                origin: subrange.origin + Vector::repeat(1),
-                size: subrange.size,
-            };
-            grid.set_materials(&material_range, VertexMaterial::new(2));
-        }
-        if place_positions {
-            // Set the default positions for all voxels. We will update the significant ones later.
-            let voxel_range = RangeZYX {
-                origin: subrange.origin,
-                size: subrange.size + Vector::repeat(1),
-            };
-            grid.set_voxels(&voxel_range, VertexVoxel::new([126, 126, 126]));
-        }
-    });
-
-    if !is_lod && grid.is_empty() {
-        return None;
-    }
-
-    // Extract the non-default vertices and set them now.
-    let vertices = extract_vertices(
-        &voxels,
-        isometry,
-        mesh,
-        &svo_aabb,
-        voxel_origin - Vector::repeat(2),
-    );
-    for (point, offset) in vertices {
-        grid.set_voxel(&point, VertexVoxel::new([offset.x, offset.y, offset.z]));
-    }
-
+    interior_material: Option<u64>,
+) -> MaterialMapper {
     let mut mapping = MaterialMapper::default();
 
     // Every blueprint I checked had this debug material in the first index.
@@ -304,65 +457,476 @@ fn voxelize_chunk(
             short_name: "Material".into(),
         },
     );
+    if let Some(interior_material) = interior_material {
+        mapping.insert(
+            3,
+            MaterialId {
+                id: interior_material,
+                short_name: "Interior".into(),
+            },
+        );
+    }
+
+    mapping
+}
 
-    Some(VoxelCellData::new(grid, mapping))
+// The materials every chunk is voxelized with.
+struct ChunkMaterials<'a> {
+    mapping: Arc<MaterialMapper>,
+    // The index of each part's material, see `nearest_part_material`.
+    parts: Vec<(&'a TriMesh, u8)>,
+    // Used instead of the part materials for voxels that don't touch the surface.
+    interior: Option<u8>,
+}
+
+// The material index of the part nearest to `point`, or the single material if the mesh
+// isn't split into parts.
+fn nearest_part_material(
+    isometry: &Isometry<f64>,
+    parts: &[(&TriMesh, u8)],
+    point: &Point<f64>,
+) -> u8 {
+    parts
+        .iter()
+        .min_by_key(|(mesh, _)| {
+            NotNan::new(mesh.distance_to_point(isometry, point, false)).unwrap()
+        })
+        .map_or(2, |(_, index)| *index)
+}
+
+/// Part of a mesh that gets its own material.
+pub struct MeshPart {
+    pub mesh: TriMesh,
+    pub material: u64,
+}
+
+/// The largest height `Voxelizer::create_lods` takes. Its chunks are 32 voxels wide, so this
+/// covers the largest core, `MAX_CORE_VOXELS` wide.
+pub const MAX_VOXELIZE_HEIGHT: usize = MAX_HEIGHT - 3;
+
+#[derive(Debug)]
+pub enum VoxelizeError {
+    TooManyMaterials(TooManyMaterials),
+    Svo(SvoError),
+}
+
+impl std::fmt::Display for VoxelizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VoxelizeError::TooManyMaterials(e) => write!(f, "{}", e),
+            VoxelizeError::Svo(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for VoxelizeError {}
+
+impl From<TooManyMaterials> for VoxelizeError {
+    fn from(value: TooManyMaterials) -> Self {
+        VoxelizeError::TooManyMaterials(value)
+    }
+}
+
+impl From<SvoError> for VoxelizeError {
+    fn from(value: SvoError) -> Self {
+        VoxelizeError::Svo(value)
+    }
 }
 
 pub struct Voxelizer {
-    isometry: Arc<Isometry<f64>>,
-    mesh: Arc<TriMesh>,
+    isometry: Isometry<f64>,
+    mesh: TriMesh,
+    // The bounds of the mesh in the voxelized space.
+    mesh_aabb: Aabb,
+    hollow: Option<u32>,
+    lattice: Option<u32>,
+    collapse_solid: bool,
+    smooth: bool,
+    fill_mode: FillMode,
+    samples: u32,
+    sample_rule: SampleRule,
+    parts: Vec<MeshPart>,
+    cancel: Option<Arc<AtomicBool>>,
+    interior_material: Option<u64>,
+    half_space: Option<Axis>,
 }
 
 impl Voxelizer {
     pub fn new(isometry: Isometry<f64>, mesh: TriMesh) -> Voxelizer {
         Voxelizer {
-            isometry: Arc::new(isometry),
-            mesh: Arc::new(mesh),
+            mesh_aabb: mesh.aabb(&isometry),
+            isometry,
+            mesh,
+            hollow: None,
+            lattice: None,
+            collapse_solid: false,
+            smooth: false,
+            fill_mode: FillMode::default(),
+            samples: 1,
+            sample_rule: SampleRule::default(),
+            parts: Vec::new(),
+            cancel: None,
+            interior_material: None,
+            half_space: None,
+        }
+    }
+
+    /// Only keep a shell of voxels within `thickness` voxels of the surface. If the thickness
+    /// is more than half the width of the model, nothing is removed and this is the same as
+    /// a solid fill.
+    pub fn with_hollow(mut self, thickness: Option<u32>) -> Voxelizer {
+        self.hollow = thickness;
+        self
+    }
+
+    /// When hollowing, also keep the inside voxels on planes `spacing` voxels apart along each
+    /// axis, as a lattice that braces the shell. Ignored without `with_hollow`.
+    pub fn with_lattice(mut self, spacing: Option<u32>) -> Voxelizer {
+        self.lattice = spacing;
+        self
+    }
+
+    /// Regions entirely inside the mesh are normally left empty. With this they become a single
+    /// solid cell at the coarsest LOD that covers them, with no finer LODs below it. Ignored when
+    /// hollowing, since the inside is removed anyway.
+    pub fn with_collapse_solid(mut self, collapse_solid: bool) -> Voxelizer {
+        self.collapse_solid = collapse_solid;
+        self
+    }
+
+    /// Place the vertices of surface voxels on the nearest point of the mesh surface instead of
+    /// snapping them to the nearest mesh feature. Gives smoother curves, at the cost of sharp
+    /// edges and corners.
+    pub fn with_smooth(mut self, smooth: bool) -> Voxelizer {
+        self.smooth = smooth;
+        self
+    }
+
+    /// How to decide which voxels are inside the mesh. See `FillMode`.
+    pub fn with_fill_mode(mut self, fill_mode: FillMode) -> Voxelizer {
+        self.fill_mode = fill_mode;
+        self
+    }
+
+    /// Test `samples` x `samples` x `samples` points in each surface voxel instead of just its
+    /// centre, and decide if it's solid with `rule`. Catches features thinner than a voxel, but
+    /// the surface takes about that many times longer to test.
+    pub fn with_samples(mut self, samples: u32, rule: SampleRule) -> Voxelizer {
+        self.samples = samples;
+        self.sample_rule = rule;
+        self
+    }
+
+    /// Gives each part of the mesh its own material. Voxels take the material of the nearest
+    /// part, so together the parts should cover the whole mesh. Without parts everything uses
+    /// the material passed to `create_lods`.
+    pub fn with_parts(mut self, parts: Vec<MeshPart>) -> Voxelizer {
+        self.parts = parts;
+        self
+    }
+
+    /// Only voxelize the half of the region on the positive side of its middle, perpendicular to
+    /// `axis`, for symmetric models that get mirrored afterwards. The plane falls between
+    /// chunks, so every voxel is on one side or the other. The root spans both halves, so its
+    /// coarsest LOD is still built in full.
+    pub fn with_half_space(mut self, axis: Option<Axis>) -> Voxelizer {
+        self.half_space = axis;
+        self
+    }
+
+    /// Solid voxels whose 6 neighbours are all solid get this material instead of the one passed
+    /// to `create_lods`, or the material of their part. Voxels with an empty neighbour are the
+    /// surface, which with `with_hollow` includes the inside wall of the shell.
+    pub fn with_interior_material(mut self, material: Option<u64>) -> Voxelizer {
+        self.interior_material = material;
+        self
+    }
+
+    /// Stops voxelizing once `cancel` is set, from another thread or a signal handler. Chunks
+    /// that are already being voxelized finish, everything after is left empty. Check
+    /// `is_cancelled` before using the result.
+    pub fn with_cancel(mut self, cancel: Option<Arc<AtomicBool>>) -> Voxelizer {
+        self.cancel = cancel;
+        self
+    }
+
+    // Whether the mesh is thinner than a voxel of this size on some axis, e.g. a flat sign or
+    // decal. Such a mesh has no inside for voxel centres to land in.
+    fn is_thinner_than(&self, voxel_size: &Vector<f64>) -> bool {
+        (0..3).any(|axis| self.mesh_aabb.extents()[axis] < voxel_size[axis])
+    }
+
+    /// Whether voxelizing was cancelled, in which case the result is incomplete.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    // This is by far the most expensive part, mostly due to Trimesh being kinda slow and the algorithm itself
+    // being pretty naive. For now we just throw threads at it (see `Svo::par_from_fn`), but it can
+    // definitely be improved.
+    fn voxelize_chunk(
+        &self,
+        aabb: &Aabb,
+        voxel_origin: &Point<i32>,
+        materials: &ChunkMaterials,
+        is_lod: bool,
+        hollow: Option<Hollow>,
+    ) -> Option<VoxelCellData> {
+        // We have to over-voxelize that chunk due to the boundries expected in voxel cell data.
+        // e.g. for an inner_range of [0, 0, 0] -> [32, 32, 32] the actual range of the chunk is
+        //  [-1, -1, -1] -> [34, 34, 34], likely to remove seams when generating the mesh.
+        let voxel_size_offset = aabb.extents() / 32.0;
+        let origin = aabb.mins - voxel_size_offset * 2.0;
+
+        let inner_range = RangeZYX::with_extent(*voxel_origin, 32);
+        let range = VertexGrid::padded_range(&inner_range);
+
+        // Note that this large aabb could result in a lot of wasted computation, so we clip the range.
+        let svo_aabb = Aabb::new(origin, origin + voxel_size_offset * 64.0);
+        let svo_origin = voxel_origin - Vector::repeat(2);
+        let voxels = voxelize(self, &svo_aabb, svo_origin, 64, &range, hollow);
+
+        // Only needed to tell the interior apart, which is per voxel rather than per region, since
+        // the voxels along the edge of a solid region can still have an empty neighbour.
+        let solidity = materials.interior.map(|_| Solidity::new(&voxels, range));
+        let mut grid = VertexGrid::new(range, inner_range);
+        voxels.cata(|subrange, value, cs| {
+            if cs.is_some() {
+                return;
+            }
+            let (place_materials, place_positions) = match value {
+                Voxel::External => (false, false),
+                Voxel::Internal => (true, true),
+                Voxel::Boundry(significant) => (*significant, true),
+            };
+            if place_materials {
+                // Materials are placed on the +[1, 1, 1] vertex.
+                let material_range = RangeZYX {
+                    origin: subrange.origin + Vector::repeat(1),
+                    size: subrange.size,
+                };
+                let center = origin
+                    + voxel_size_offset.component_mul(
+                        &(subrange.origin - svo_origin).map(|v| v as f64)
+                            + subrange.size.map(|v| v as f64 * 0.5),
+                    );
+                let material = nearest_part_material(&self.isometry, &materials.parts, &center);
+                grid.set_materials(&material_range, VertexMaterial::new(material));
+                if let (Some(solidity), Some(interior)) = (&solidity, materials.interior) {
+                    for_each_point(&subrange.intersection(&range), |voxel| {
+                        if solidity.is_enclosed(voxel) {
+                            let material_range = RangeZYX::single(voxel + Vector::repeat(1));
+                            grid.set_materials(&material_range, VertexMaterial::new(interior));
+                        }
+                    });
+                }
+            }
+            if place_positions {
+                // Set the default positions for all voxels. We will update the significant ones later.
+                let voxel_range = RangeZYX {
+                    origin: subrange.origin,
+                    size: subrange.size + Vector::repeat(1),
+                };
+                grid.set_voxels(&voxel_range, VertexVoxel::new([126, 126, 126]));
+            }
+        });
+
+        if !is_lod && grid.is_empty() {
+            return None;
         }
+
+        // Extract the non-default vertices and set them now. The vertices of a mesh thinner than
+        // a voxel would all snap onto it and flatten the voxels, so they're left as cubes.
+        let vertices = if self.is_thinner_than(&(svo_aabb.extents() / 64.0)) {
+            HashMap::new()
+        } else {
+            extract_vertices(
+                &voxels,
+                &self.isometry,
+                &self.mesh,
+                self.fill_mode,
+                &svo_aabb,
+                svo_origin,
+                self.smooth,
+            )
+        };
+        for (point, offset) in vertices {
+            grid.set_voxel(&point, VertexVoxel::new([offset.x, offset.y, offset.z]));
+        }
+
+        Some(VoxelCellData::new(grid, materials.mapping.clone()))
     }
 
+    /// Voxelizes `aabb` into chunks of 32 voxels, `1 << height` chunks along each axis. Fails
+    /// before allocating anything if the height is above `MAX_VOXELIZE_HEIGHT`.
     pub fn create_lods(
         &self,
         aabb: &Aabb,
         origin: Point<i32>,
         height: usize,
         material: u64,
-    ) -> Svo<Option<VoxelCellData>> {
+    ) -> Result<Svo<Option<VoxelCellData>>, VoxelizeError> {
+        if height > MAX_VOXELIZE_HEIGHT {
+            return Err(SvoError::HeightTooLarge(height, MAX_VOXELIZE_HEIGHT).into());
+        }
         let extent = 1 << height;
-        let chunk_size = aabb.extents().x / extent as f64;
-        let chunk_futures = Svo::from_fn(origin, extent, &|range| {
-            let mins = aabb.mins + (range.origin - origin).map(|v| v as f64) * chunk_size;
-            let maxs = mins + range.size.map(|v| v as f64) * chunk_size;
+        let chunk_size = aabb.extents() / extent as f64;
+        // Every chunk shares the one mapper.
+        let materials = if self.parts.is_empty() {
+            ChunkMaterials {
+                mapping: Arc::new(create_material_mapper(material, self.interior_material)),
+                parts: Vec::new(),
+                interior: self.interior_material.map(|_| 3),
+            }
+        } else {
+            let unique_ids = Vec::from_iter(BTreeSet::from_iter(
+                self.parts
+                    .iter()
+                    .map(|part| part.material)
+                    .chain(self.interior_material),
+            ));
+            let (mapping, material_id_to_index) =
+                JSONImporter.create_material_mapper(&unique_ids, true)?;
+            ChunkMaterials {
+                mapping: Arc::new(mapping),
+                parts: Vec::from_iter(
+                    self.parts
+                        .iter()
+                        .map(|part| (&part.mesh, material_id_to_index[&part.material])),
+                ),
+                interior: self.interior_material.map(|m| material_id_to_index[&m]),
+            }
+        };
+        // The number of chunks that touch the mesh isn't known up front, so this just counts.
+        let bar =
+            progress_bar("{spinner:.green} [{elapsed_precise}] {pos} chunks voxelized ({per_sec})");
+        let svo = Svo::par_from_fn(origin, extent, &|range| {
+            if self.is_cancelled() {
+                return SvoReturn::Leaf(None);
+            }
+            if let Some(axis) = self.half_space {
+                let axis = axis as usize;
+                if range.origin[axis] + range.size[axis] <= origin[axis] + extent as i32 / 2 {
+                    return SvoReturn::Leaf(None);
+                }
+            }
+            let mins = aabb.mins
+                + (range.origin - origin)
+                    .map(|v| v as f64)
+                    .component_mul(&chunk_size);
+            let maxs = mins + range.size.map(|v| v as f64).component_mul(&chunk_size);
             let aabb = Aabb::new(mins.into(), maxs.into());
 
             let cuboid = Cuboid::new(aabb.half_extents() * 1.05);
             let cuboid_pos = Isometry::from(aabb.center());
-            if intersection_test(&self.isometry, self.mesh.as_ref(), &cuboid_pos, &cuboid).unwrap()
-            {
+            let intersects =
+                intersection_test(&self.isometry, &self.mesh, &cuboid_pos, &cuboid).unwrap();
+            let solid = !intersects
+                && self.collapse_solid
+                && self.hollow.is_none()
+                && is_inside(&self.isometry, &self.mesh, self.fill_mode, &aabb);
+            if intersects || solid {
                 let is_lod = range.size.x > 1;
-                let voxel_origin = range.origin * 32 / range.size.x;
-                let isometry = self.isometry.clone();
-                let mesh = self.mesh.clone();
-                let task = task::spawn(async move {
-                    voxelize_chunk(
-                        &isometry,
-                        &mesh,
-                        &aabb,
-                        &voxel_origin,
-                        material,
-                        is_lod,
-                    )
+                // The thickness and spacing are in finest LOD voxels, so scale them down to keep
+                // the same shell and lattice at every LOD.
+                let lod_scale = range.size.x as f64;
+                let hollow = self.hollow.map(|t| Hollow {
+                    thickness: t as f64 / lod_scale,
+                    lattice_spacing: self.lattice.map(|s| s as f64 / lod_scale),
                 });
-                if range.size.x == 1 {
-                    SvoReturn::Leaf(Some(task))
+                let voxel_origin = range.origin * 32 / range.size.x;
+                let chunk = self.voxelize_chunk(&aabb, &voxel_origin, &materials, is_lod, hollow);
+                bar.inc(1);
+                if range.size.x == 1 || solid {
+                    SvoReturn::Leaf(chunk)
                 } else {
-                    SvoReturn::Internal(Some(task))
+                    SvoReturn::Internal(chunk)
                 }
             } else {
                 SvoReturn::Leaf(None)
             }
         });
+        bar.finish();
+        Ok(svo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh_import::is_flat;
+
+    #[test]
+    fn interior_material_is_only_for_enclosed_voxels() {
+        // A 0.8 wide box in the middle of a single 32 voxel chunk, hollowed out to a 4 voxel
+        // shell, so the shell has an inside wall as well as an outside one.
+        let (vertices, indices) = Cuboid::new(Vector::repeat(0.4)).to_trimesh();
+        let mesh = TriMesh::new(vertices, indices);
+        let aabb = Aabb::new(Point::origin(), Point::new(1.0, 1.0, 1.0));
+        let svo = Voxelizer::new(Isometry::translation(0.5, 0.5, 0.5), mesh)
+            .with_fill_mode(FillMode::Winding)
+            .with_hollow(Some(4))
+            .with_interior_material(Some(1234))
+            .create_lods(&aabb, Point::origin(), 0, 5)
+            .unwrap();
+
+        let mut materials = HashMap::new();
+        svo.cata(|_, v, _| {
+            let Some(cell_data) = v else { return };
+            cell_data.grid.for_each_material(|pos, material| {
+                // Materials are on the +[1, 1, 1] vertex of their voxel.
+                let voxel = pos - Vector::repeat(1);
+                if cell_data.grid.inner_range().contains_point(voxel) {
+                    materials.insert(voxel, material);
+                }
+            });
+        });
+        let interior = 3;
+        let is_enclosed = |voxel: &Point<i32>| {
+            (0..3).all(|axis| {
+                let step = Vector::ith(axis, 1);
+                materials.contains_key(&(voxel + step)) && materials.contains_key(&(voxel - step))
+            })
+        };
+        for (voxel, material) in &materials {
+            assert_eq!(*material == interior, is_enclosed(voxel), "voxel {}", voxel);
+        }
+        assert!(materials.values().any(|material| *material == interior));
+        // The middle of the box is hollow, so the inside wall of the shell is surface.
+        assert!(!materials.contains_key(&Point::new(16, 16, 16)));
+    }
 
-        chunk_futures.into_map(|f| f.map(|f| block_on(f)).flatten())
+    #[test]
+    fn planar_quad_voxelizes_one_voxel_thick() {
+        // A flat 1 x 1 sign in the xy plane.
+        let mesh = TriMesh::new(
+            vec![
+                Point::new(0.0, 0.0, 0.0),
+                Point::new(1.0, 0.0, 0.0),
+                Point::new(1.0, 1.0, 0.0),
+                Point::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        );
+        // Generate lets flat meshes through without --allow-open.
+        assert!(is_flat(&mesh));
+        // 32 voxels across, with the quad part way through a layer of voxels.
+        let aabb = Aabb::new(Point::new(-0.5, -0.5, -0.97), Point::new(1.5, 1.5, 1.03));
+        for fill_mode in [FillMode::Oriented, FillMode::Winding] {
+            let svo = Voxelizer::new(Isometry::identity(), mesh.clone())
+                .with_fill_mode(fill_mode)
+                .create_lods(&aabb, Point::origin(), 0, 1971262921)
+                .unwrap();
+
+            let occupancy = svo.occupancy();
+            // 16 x 16 voxels cover the quad, maybe with a ring around it where the slightly grown
+            // intersection test touches the edges.
+            assert!(occupancy.total >= 16 * 16, "{:?}", fill_mode);
+            assert!(occupancy.layers[0].len() >= 16 && occupancy.layers[1].len() >= 16);
+            // The quad is inside a single layer of voxels.
+            assert_eq!(occupancy.layers[2].len(), 1, "{:?}", fill_mode);
+        }
     }
 }